@@ -0,0 +1,305 @@
+use libemu::{
+  gb::GameBoy,
+  soc::ppu::debug::{
+    MapOverlay, MAP_VIEW_HEIGHT, MAP_VIEW_WIDTH, TILES_VIEW_HEIGHT, TILES_VIEW_WIDTH,
+  },
+};
+use log::warn;
+use sdl2::{
+  pixels::{Color, PixelFormatEnum},
+  rect::Rect,
+  render::{Canvas, TextureCreator},
+  ttf::{Font, Sdl2TtfContext},
+  video::{Window, WindowContext},
+  VideoSubsystem,
+};
+
+const DEBUG_SCALE: u32 = 2;
+const FONT_SIZE: u16 = 14;
+const TEXT_MARGIN: i32 = 6;
+const TEXT_COLOR: Color = Color::RGB(0xe0, 0xe0, 0xe0);
+const BACKGROUND_COLOR: Color = Color::RGB(0x20, 0x20, 0x20);
+
+/// Number of instructions disassembled before and after PC.
+const DISASM_BEFORE: usize = 4;
+const DISASM_AFTER: usize = 12;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+  Tiles,
+  Maps,
+  Objects,
+  Cpu,
+}
+
+impl DebugView {
+  pub fn title(&self) -> &'static str {
+    match self {
+      DebugView::Tiles => "Tile Data",
+      DebugView::Maps => "BG/Window Maps",
+      DebugView::Objects => "OAM",
+      DebugView::Cpu => "CPU",
+    }
+  }
+
+  /// Logical size of the view, the image based views are scaled
+  /// up when displayed while the text ones are not.
+  fn size(&self) -> (u32, u32) {
+    match self {
+      DebugView::Tiles => (TILES_VIEW_WIDTH as u32, TILES_VIEW_HEIGHT as u32),
+      DebugView::Maps => (MAP_VIEW_WIDTH as u32 * 2, MAP_VIEW_HEIGHT as u32),
+      DebugView::Objects => (420, 760),
+      DebugView::Cpu => (300, 560),
+    }
+  }
+
+  fn scale(&self) -> u32 {
+    match self {
+      DebugView::Tiles | DebugView::Maps => DEBUG_SCALE,
+      DebugView::Objects | DebugView::Cpu => 1,
+    }
+  }
+}
+
+pub struct DebugWindow {
+  view: DebugView,
+  canvas: Canvas<Window>,
+}
+
+/// Set of the optional secondary windows used to inspect the
+/// state of the emulated system (VRAM viewers and debugger).
+pub struct DebugWindows<'a> {
+  windows: Vec<DebugWindow>,
+  /// Font of the text views, loaded once as parsing it
+  /// on every frame is expensive.
+  font: Option<Font<'a, 'static>>,
+}
+
+impl<'a> DebugWindows<'a> {
+  pub fn new(ttf_context: &'a Sdl2TtfContext, font_path: &str) -> Self {
+    let font = match ttf_context.load_font(font_path, FONT_SIZE) {
+      Ok(font) => Some(font),
+      Err(_) => {
+        warn!(
+          "Failed to load font from {}, text views disabled",
+          font_path
+        );
+        None
+      },
+    };
+    Self {
+      windows: vec![],
+      font,
+    }
+  }
+
+  /// Opens the window for the view or closes it in case
+  /// it's already open.
+  pub fn toggle(&mut self, video_subsystem: &VideoSubsystem, view: DebugView) {
+    if let Some(index) = self.windows.iter().position(|w| w.view == view) {
+      self.windows.remove(index);
+      return;
+    }
+
+    let (width, height) = view.size();
+    let window = video_subsystem
+      .window(
+        &format!("GBREMU - {}", view.title()),
+        width * view.scale(),
+        height * view.scale(),
+      )
+      .resizable()
+      .build()
+      .unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
+    canvas.set_logical_size(width, height).unwrap();
+    self.windows.push(DebugWindow { view, canvas });
+  }
+
+  /// Closes the debug window with the provided SDL id, returning
+  /// if the window was one of the debug windows.
+  pub fn close(&mut self, window_id: u32) -> bool {
+    let index = self
+      .windows
+      .iter()
+      .position(|w| w.canvas.window().id() == window_id);
+    match index {
+      Some(index) => {
+        self.windows.remove(index);
+        true
+      },
+      None => false,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.windows.is_empty()
+  }
+
+  /// Refreshes the contents of every open debug window from
+  /// the current state of the system.
+  pub fn render(&mut self, system: &GameBoy) {
+    let font = &self.font;
+    for window in self.windows.iter_mut() {
+      let canvas = &mut window.canvas;
+      let texture_creator = canvas.texture_creator();
+      canvas.set_draw_color(BACKGROUND_COLOR);
+      canvas.clear();
+
+      match window.view {
+        DebugView::Tiles => {
+          let tiles = system.ppu().render_tiles();
+          let (width, height) = window.view.size();
+          draw_image(canvas, &texture_creator, &tiles, width, height, 0);
+        },
+        DebugView::Maps => {
+          let ppu = system.ppu();
+          let bg = ppu.render_tile_map(ppu.bg_tile_map(), MapOverlay::Scroll);
+          let win = ppu.render_tile_map(ppu.window_tile_map(), MapOverlay::Window);
+          let (width, height) = (MAP_VIEW_WIDTH as u32, MAP_VIEW_HEIGHT as u32);
+          draw_image(canvas, &texture_creator, &bg, width, height, 0);
+          draw_image(canvas, &texture_creator, &win, width, height, width as i32);
+        },
+        DebugView::Objects => {
+          if let Some(font) = font {
+            draw_text(canvas, &texture_creator, font, &object_lines(system));
+          }
+        },
+        DebugView::Cpu => {
+          if let Some(font) = font {
+            draw_text(canvas, &texture_creator, font, &cpu_lines(system));
+          }
+        },
+      }
+
+      canvas.present();
+    }
+  }
+}
+
+fn draw_image(
+  canvas: &mut Canvas<Window>,
+  texture_creator: &TextureCreator<WindowContext>,
+  buffer: &[u8],
+  width: u32,
+  height: u32,
+  x: i32,
+) {
+  let mut texture = texture_creator
+    .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+    .unwrap();
+  texture.update(None, buffer, width as usize * 3).unwrap();
+  canvas
+    .copy(&texture, None, Rect::new(x, 0, width, height))
+    .unwrap();
+}
+
+fn draw_text(
+  canvas: &mut Canvas<Window>,
+  texture_creator: &TextureCreator<WindowContext>,
+  font: &Font,
+  lines: &[String],
+) {
+  let line_height = font.recommended_line_spacing();
+  for (index, line) in lines.iter().enumerate() {
+    // empty lines can't be rendered by SDL TTF (zero width)
+    if line.is_empty() {
+      continue;
+    }
+    let surface = font.render(line).blended(TEXT_COLOR).unwrap();
+    let texture = texture_creator
+      .create_texture_from_surface(&surface)
+      .unwrap();
+    let y = TEXT_MARGIN + index as i32 * line_height;
+    canvas
+      .copy(
+        &texture,
+        None,
+        Rect::new(TEXT_MARGIN, y, surface.width(), surface.height()),
+      )
+      .unwrap();
+  }
+}
+
+fn object_lines(system: &GameBoy) -> Vec<String> {
+  let ppu = system.ppu();
+  let palettes = ppu.palettes();
+  let mut lines = vec![
+    format!("BGP  {:?}", palettes.bgp),
+    format!("OBP0 {:?}", palettes.obp0),
+    format!("OBP1 {:?}", palettes.obp1),
+    String::new(),
+  ];
+  for (index, obj) in ppu.objects().iter().enumerate() {
+    lines.push(format!(
+      "#{:02} X:{:4} Y:{:4} T:{:02X} P:{} {}{}{}",
+      index,
+      obj.x(),
+      obj.y(),
+      obj.tile(),
+      obj.palette(),
+      if obj.xflip() { "X" } else { "-" },
+      if obj.yflip() { "Y" } else { "-" },
+      if obj.bg_over() { "B" } else { "-" },
+    ));
+  }
+  lines
+}
+
+fn cpu_lines(system: &GameBoy) -> Vec<String> {
+  let cpu = system.cpu();
+  let mut lines = vec![
+    format!("AF {:04X}  BC {:04X}", cpu.af(), cpu.bc()),
+    format!("DE {:04X}  HL {:04X}", cpu.de(), cpu.hl()),
+    format!("SP {:04X}  PC {:04X}", cpu.sp(), cpu.pc()),
+    format!(
+      "Z:{} N:{} H:{} C:{}",
+      cpu.zero() as u8,
+      cpu.sub() as u8,
+      cpu.half_carry() as u8,
+      cpu.carry() as u8
+    ),
+    format!("IME:{} HALT:{}", cpu.ime() as u8, cpu.halted() as u8),
+    String::new(),
+  ];
+
+  let pc = cpu.pc();
+  for addr in disasm_addrs(system, pc) {
+    if let Some(name) = system.symbol_name(addr) {
+      lines.push(format!("{}:", name));
+    }
+    let (text, _) = system.disassemble(addr);
+    let marker = if addr == pc { ">" } else { " " };
+    lines.push(format!("{} {:04X}  {}", marker, addr, text));
+  }
+  lines
+}
+
+/// Computes the addresses of the instructions to be disassembled
+/// around PC, as instructions have variable length the ones before
+/// PC are found by sweeping from increasingly earlier addresses
+/// until a sequence that lands exactly on PC is found.
+fn disasm_addrs(system: &GameBoy, pc: u16) -> Vec<u16> {
+  let cpu = system.cpu();
+  let mut addrs = vec![];
+  for back in (1..=(DISASM_BEFORE as u16 * 3)).rev() {
+    let mut addr = pc.wrapping_sub(back);
+    let mut sweep = vec![];
+    while addr != pc && pc.wrapping_sub(addr) <= back {
+      sweep.push(addr);
+      addr = addr.wrapping_add(cpu.disassemble(addr).1);
+    }
+    if addr == pc {
+      let skip = sweep.len().saturating_sub(DISASM_BEFORE);
+      addrs.extend_from_slice(&sweep[skip..]);
+      break;
+    }
+  }
+
+  let mut addr = pc;
+  for _ in 0..DISASM_AFTER {
+    addrs.push(addr);
+    addr = addr.wrapping_add(cpu.disassemble(addr).1);
+  }
+  addrs
+}
@@ -0,0 +1,491 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use libemu::{error::Error, pad::PadKey, util::read_file};
+use log::warn;
+use sdl2::{
+  controller::{Axis, Button},
+  keyboard::Keycode,
+};
+use serde::{Deserialize, Serialize};
+
+/// Location of the bindings inside of the user config
+/// directory (eg: `~/.config/gbremu/bindings.toml`).
+pub const CONFIG_DIR: &str = "gbremu";
+pub const BINDINGS_FILE: &str = "bindings.toml";
+
+/// Number of presses per second of the turbo buttons.
+pub const DEFAULT_TURBO_RATE: f32 = 10.0;
+
+/// Displacement of an axis for it to count as pressed.
+pub const DEFAULT_AXIS_THRESHOLD: i16 = 16000;
+
+/// Keys handled by the frontend itself (eg: reset, fast forward,
+/// debug views), which take precedence over the bindings.
+pub const HOTKEYS: [Keycode; 13] = [
+  Keycode::Escape,
+  Keycode::R,
+  Keycode::P,
+  Keycode::E,
+  Keycode::F,
+  Keycode::Num0,
+  Keycode::Num1,
+  Keycode::Num2,
+  Keycode::Num3,
+  Keycode::F1,
+  Keycode::F2,
+  Keycode::F3,
+  Keycode::F4,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+  Key(PadKey),
+  /// Presses and lifts the key repeatedly while held.
+  Turbo(PadKey),
+}
+
+/// Names bound to each of the actions, keyboard ones are the SDL
+/// key names (eg: `Return`) while the controller ones are the SDL
+/// game controller button names (eg: `dpup`) or axis names with
+/// the direction as prefix (eg: `-lefty`).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActionBindings {
+  pub up: Vec<String>,
+  pub down: Vec<String>,
+  pub left: Vec<String>,
+  pub right: Vec<String>,
+  pub start: Vec<String>,
+  pub select: Vec<String>,
+  pub a: Vec<String>,
+  pub b: Vec<String>,
+  pub turbo_a: Vec<String>,
+  pub turbo_b: Vec<String>,
+}
+
+impl ActionBindings {
+  fn actions(&self) -> [(&[String], Action); 10] {
+    [
+      (&self.up, Action::Key(PadKey::Up)),
+      (&self.down, Action::Key(PadKey::Down)),
+      (&self.left, Action::Key(PadKey::Left)),
+      (&self.right, Action::Key(PadKey::Right)),
+      (&self.start, Action::Key(PadKey::Start)),
+      (&self.select, Action::Key(PadKey::Select)),
+      (&self.a, Action::Key(PadKey::A)),
+      (&self.b, Action::Key(PadKey::B)),
+      (&self.turbo_a, Action::Turbo(PadKey::A)),
+      (&self.turbo_b, Action::Turbo(PadKey::B)),
+    ]
+  }
+}
+
+/// Input configuration, as stored in the TOML bindings file, eg:
+///
+/// ```toml
+/// turbo_rate = 10.0
+/// axis_threshold = 16000
+///
+/// [keyboard]
+/// a = ["A"]
+/// turbo_a = ["Z"]
+///
+/// [controller]
+/// up = ["dpup", "-lefty"]
+/// a = ["b"]
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+  pub turbo_rate: f32,
+  pub axis_threshold: i16,
+  pub keyboard: ActionBindings,
+  pub controller: ActionBindings,
+}
+
+impl Bindings {
+  /// Default path of the bindings file, in the user config directory.
+  pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|path| path.join(CONFIG_DIR).join(BINDINGS_FILE))
+  }
+
+  pub fn from_toml(text: &str) -> Result<Self, Error> {
+    toml::from_str(text)
+      .map_err(|err| Error::CustomError(format!("Invalid bindings file: {}", err)))
+  }
+
+  pub fn from_file(path: &str) -> Result<Self, Error> {
+    let text = String::from_utf8(read_file(path)?)
+      .map_err(|_| Error::CustomError(format!("Invalid bindings file: {}", path)))?;
+    Self::from_toml(&text)
+  }
+
+  pub fn to_toml(&self) -> String {
+    toml::to_string_pretty(self).unwrap()
+  }
+}
+
+impl Default for Bindings {
+  fn default() -> Self {
+    let names = |names: &[&str]| names.iter().map(|name| String::from(*name)).collect();
+    Self {
+      turbo_rate: DEFAULT_TURBO_RATE,
+      axis_threshold: DEFAULT_AXIS_THRESHOLD,
+      keyboard: ActionBindings {
+        up: names(&["Up"]),
+        down: names(&["Down"]),
+        left: names(&["Left"]),
+        right: names(&["Right"]),
+        start: names(&["Return", "Return2"]),
+        select: names(&["Space"]),
+        a: names(&["A"]),
+        b: names(&["S"]),
+        turbo_a: names(&["Z"]),
+        turbo_b: names(&["X"]),
+      },
+      // uses the position (rather than the label) of the buttons
+      // of the Game Boy, with B to the left of A
+      controller: ActionBindings {
+        up: names(&["dpup", "-lefty"]),
+        down: names(&["dpdown", "+lefty"]),
+        left: names(&["dpleft", "-leftx"]),
+        right: names(&["dpright", "+leftx"]),
+        start: names(&["start"]),
+        select: names(&["back"]),
+        a: names(&["b"]),
+        b: names(&["a"]),
+        turbo_a: names(&["y"]),
+        turbo_b: names(&["x"]),
+      },
+    }
+  }
+}
+
+/// Direction of an axis bound to an action.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct AxisBinding {
+  axis: Axis,
+  positive: bool,
+  action: Action,
+}
+
+/// Origin of the press of a key, so that a key held by several
+/// sources is only lifted once all of them are released.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Source {
+  Key(Keycode),
+  Button(Button),
+  Axis(Axis, bool),
+}
+
+/// Input state of the frontend, translating the keyboard and game
+/// controller events into key presses and lifts of the pad, using
+/// the resolved bindings.
+pub struct Input {
+  keys: HashMap<Keycode, Action>,
+  buttons: HashMap<Button, Action>,
+  axes: Vec<AxisBinding>,
+  axis_threshold: i16,
+  /// Axes directions currently pressed, so that only their
+  /// transitions are turned into presses and lifts.
+  axes_pressed: Vec<(Axis, bool)>,
+  /// Sources currently holding each of the keys through
+  /// their normal (not turbo) bindings.
+  held: Vec<(PadKey, Source)>,
+
+  /// Number of frames of each of the turbo periods.
+  turbo_period: u32,
+  turbo_frame: u32,
+  /// Keys with a turbo binding currently held, together
+  /// with their (pressed or lifted) state.
+  turbo_keys: Vec<(PadKey, bool)>,
+}
+
+impl Input {
+  pub fn new(bindings: &Bindings, frame_rate: f32) -> Self {
+    let mut input = Self {
+      keys: HashMap::new(),
+      buttons: HashMap::new(),
+      axes: vec![],
+      axis_threshold: bindings.axis_threshold,
+      axes_pressed: vec![],
+      held: vec![],
+      turbo_period: (frame_rate / bindings.turbo_rate.max(0.1)).round().max(2.0) as u32,
+      turbo_frame: 0,
+      turbo_keys: vec![],
+    };
+    for (names, action) in bindings.keyboard.actions() {
+      for name in names {
+        match Keycode::from_name(name) {
+          Some(keycode) if HOTKEYS.contains(&keycode) => {
+            warn!("Key in bindings is reserved for a hotkey: {}", name)
+          },
+          Some(keycode) => {
+            input.keys.insert(keycode, action);
+          },
+          None => warn!("Unknown key in bindings: {}", name),
+        }
+      }
+    }
+
+    for (names, action) in bindings.controller.actions() {
+      for name in names {
+        let axis = match name.chars().next() {
+          Some('+') => Some(true),
+          Some('-') => Some(false),
+          _ => None,
+        };
+        match axis {
+          Some(positive) => match Axis::from_string(&name[1..]) {
+            Some(axis) => input.axes.push(AxisBinding {
+              axis,
+              positive,
+              action,
+            }),
+            None => warn!("Unknown controller axis in bindings: {}", name),
+          },
+          None => match Button::from_string(name) {
+            Some(button) => {
+              input.buttons.insert(button, action);
+            },
+            None => warn!("Unknown controller button in bindings: {}", name),
+          },
+        }
+      }
+    }
+
+    input
+  }
+
+  pub fn key_action(&self, keycode: Keycode) -> Option<Action> {
+    self.keys.get(&keycode).copied()
+  }
+
+  pub fn button_action(&self, button: Button) -> Option<Action> {
+    self.buttons.get(&button).copied()
+  }
+
+  /// Translates the press (or lift) of a keyboard key into the keys
+  /// of the pad that are now pressed (true) or lifted (false).
+  pub fn key_event(&mut self, keycode: Keycode, pressed: bool) -> Vec<(PadKey, bool)> {
+    match self.key_action(keycode) {
+      Some(action) => self.action(Source::Key(keycode), action, pressed),
+      None => vec![],
+    }
+  }
+
+  /// Translates the press (or lift) of a controller button into the
+  /// keys of the pad that are now pressed (true) or lifted (false).
+  pub fn button_event(&mut self, button: Button, pressed: bool) -> Vec<(PadKey, bool)> {
+    match self.button_action(button) {
+      Some(action) => self.action(Source::Button(button), action, pressed),
+      None => vec![],
+    }
+  }
+
+  /// Translates the motion of an axis into the keys of the
+  /// pad that are now pressed (true) or lifted (false).
+  pub fn axis_motion(&mut self, axis: Axis, value: i16) -> Vec<(PadKey, bool)> {
+    let mut changes = vec![];
+    for positive in [true, false] {
+      let pressed = if positive {
+        value > self.axis_threshold
+      } else {
+        value < -self.axis_threshold
+      };
+      let index = self
+        .axes_pressed
+        .iter()
+        .position(|&item| item == (axis, positive));
+      match (pressed, index) {
+        (true, None) => self.axes_pressed.push((axis, positive)),
+        (false, Some(index)) => {
+          self.axes_pressed.remove(index);
+        },
+        _ => continue,
+      }
+      let actions: Vec<Action> = self
+        .axes
+        .iter()
+        .filter(|binding| binding.axis == axis && binding.positive == positive)
+        .map(|binding| binding.action)
+        .collect();
+      for action in actions {
+        changes.extend(self.action(Source::Axis(axis, positive), action, pressed));
+      }
+    }
+    changes
+  }
+
+  /// Applies the press (or lift) of the action by the source, the
+  /// turbo keys are only pressed by `turbo_frame()`, and a key is
+  /// only lifted once neither a source nor the turbo holds it.
+  fn action(&mut self, source: Source, action: Action, pressed: bool) -> Vec<(PadKey, bool)> {
+    let (key, was_pressed) = match action {
+      Action::Key(key) | Action::Turbo(key) => (key, self.is_pressed(key)),
+    };
+    match (action, pressed) {
+      (Action::Key(key), true) => {
+        if !self.held.contains(&(key, source)) {
+          self.held.push((key, source));
+        }
+      },
+      (Action::Key(key), false) => self.held.retain(|&item| item != (key, source)),
+      (Action::Turbo(key), true) => self.turbo_press(key),
+      (Action::Turbo(key), false) => self.turbo_lift(key),
+    }
+    let is_pressed = self.is_pressed(key);
+    if is_pressed != was_pressed {
+      vec![(key, is_pressed)]
+    } else {
+      vec![]
+    }
+  }
+
+  /// Whether the key is held by any of the sources or by the turbo.
+  fn is_pressed(&self, key: PadKey) -> bool {
+    self.is_held(key) || self.turbo_keys.contains(&(key, true))
+  }
+
+  fn is_held(&self, key: PadKey) -> bool {
+    self.held.iter().any(|&(item, _)| item == key)
+  }
+
+  fn turbo_press(&mut self, key: PadKey) {
+    if !self.turbo_keys.iter().any(|&(item, _)| item == key) {
+      self.turbo_keys.push((key, false));
+    }
+  }
+
+  fn turbo_lift(&mut self, key: PadKey) {
+    self.turbo_keys.retain(|&(item, _)| item != key);
+  }
+
+  /// Advances the turbo by a frame, returning the keys that have
+  /// to be pressed (true) or lifted (false), the keys are pressed
+  /// during the first half of each period, unless also held by
+  /// their normal bindings.
+  pub fn turbo_frame(&mut self) -> Vec<(PadKey, bool)> {
+    self.turbo_frame = self.turbo_frame.wrapping_add(1);
+    let pressed = self.turbo_frame % self.turbo_period < self.turbo_period / 2;
+    let mut changes = vec![];
+    for index in 0..self.turbo_keys.len() {
+      let (key, state) = self.turbo_keys[index];
+      if state != pressed {
+        self.turbo_keys[index].1 = pressed;
+        if !self.is_held(key) {
+          changes.push((key, pressed));
+        }
+      }
+    }
+    changes
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn input() -> Input {
+    // turbo period of 6 frames, pressed for the first 3
+    Input::new(&Bindings::default(), 60.0)
+  }
+
+  fn frames(input: &mut Input, count: usize) -> Vec<Vec<(PadKey, bool)>> {
+    (0..count).map(|_| input.turbo_frame()).collect()
+  }
+
+  #[test]
+  fn test_from_toml() {
+    let bindings = Bindings::from_toml(
+      r#"
+      turbo_rate = 5.0
+
+      [keyboard]
+      a = ["Q"]
+      "#,
+    )
+    .unwrap();
+    assert_eq!(bindings.turbo_rate, 5.0);
+    assert_eq!(bindings.axis_threshold, DEFAULT_AXIS_THRESHOLD);
+    assert_eq!(bindings.keyboard.a, vec![String::from("Q")]);
+    assert!(bindings.keyboard.b.is_empty());
+    assert_eq!(bindings.controller.a, Bindings::default().controller.a);
+
+    let bindings = Bindings::from_toml(&Bindings::default().to_toml()).unwrap();
+    assert_eq!(bindings.keyboard.turbo_a, vec![String::from("Z")]);
+
+    assert!(Bindings::from_toml("turbo_rate = \"fast\"").is_err());
+    assert!(Bindings::from_toml("[keyboard").is_err());
+  }
+
+  #[test]
+  fn test_axis_motion() {
+    let mut input = input();
+    assert_eq!(input.axis_motion(Axis::LeftX, 16000), vec![]);
+    assert_eq!(
+      input.axis_motion(Axis::LeftX, 16001),
+      vec![(PadKey::Right, true)]
+    );
+    assert_eq!(input.axis_motion(Axis::LeftX, 32767), vec![]);
+    assert_eq!(
+      input.axis_motion(Axis::LeftX, -20000),
+      vec![(PadKey::Right, false), (PadKey::Left, true)]
+    );
+    assert_eq!(
+      input.axis_motion(Axis::LeftX, -16000),
+      vec![(PadKey::Left, false)]
+    );
+    assert_eq!(input.axis_motion(Axis::LeftX, 0), vec![]);
+    assert_eq!(input.axis_motion(Axis::RightX, 32767), vec![]);
+  }
+
+  #[test]
+  fn test_turbo_frame() {
+    let mut input = input();
+    assert_eq!(input.key_event(Keycode::Z, true), vec![]);
+    assert_eq!(
+      frames(&mut input, 7),
+      vec![
+        vec![(PadKey::A, true)],
+        vec![],
+        vec![(PadKey::A, false)],
+        vec![],
+        vec![],
+        vec![(PadKey::A, true)],
+        vec![],
+      ]
+    );
+    assert_eq!(input.key_event(Keycode::Z, false), vec![(PadKey::A, false)]);
+    assert_eq!(frames(&mut input, 6), vec![vec![]; 6]);
+  }
+
+  #[test]
+  fn test_turbo_held() {
+    let mut input = input();
+    assert_eq!(input.key_event(Keycode::A, true), vec![(PadKey::A, true)]);
+    assert_eq!(input.key_event(Keycode::A, true), vec![]);
+    assert_eq!(input.key_event(Keycode::Z, true), vec![]);
+    assert_eq!(frames(&mut input, 6), vec![vec![]; 6]);
+    assert_eq!(input.key_event(Keycode::Z, false), vec![]);
+    assert_eq!(input.key_event(Keycode::A, false), vec![(PadKey::A, false)]);
+
+    // lifting the normal binding while the turbo is pressed
+    // leaves the key to be lifted by the turbo
+    assert_eq!(input.key_event(Keycode::Z, true), vec![]);
+    assert_eq!(input.key_event(Keycode::A, true), vec![(PadKey::A, true)]);
+    assert_eq!(frames(&mut input, 1), vec![vec![]]);
+    assert_eq!(input.key_event(Keycode::A, false), vec![]);
+    assert_eq!(
+      frames(&mut input, 2),
+      vec![vec![], vec![(PadKey::A, false)]]
+    );
+    assert_eq!(input.key_event(Keycode::Z, false), vec![]);
+
+    // a key held by two sources is lifted with the last one
+    assert_eq!(input.button_event(Button::B, true), vec![(PadKey::A, true)]);
+    assert_eq!(input.key_event(Keycode::A, true), vec![]);
+    assert_eq!(input.button_event(Button::B, false), vec![]);
+    assert_eq!(input.key_event(Keycode::A, false), vec![(PadKey::A, false)]);
+  }
+}
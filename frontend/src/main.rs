@@ -1,27 +1,60 @@
+pub mod debug;
+pub mod input;
 pub mod sdl;
 
 use clap::Parser;
+use debug::{DebugView, DebugWindows};
+use input::{Bindings, Input};
 use libemu::{
+  cartridge::LoadPolicy,
+  cheats::Cheats,
   error::Error,
   gb::GameBoy,
+  gdb::GdbServer,
+  memory_map::io_register_name,
+  model::Model,
   pad::PadKey,
-  soc::ppu::palette::PaletteInfo,
+  profiler::Profiler,
+  soc::ppu::{
+    debug::{LAYER_BG, LAYER_OBJ, LAYER_WINDOW},
+    palette::PaletteInfo,
+  },
+  symbols::SymbolTable,
+  trace::{AccessKind, BusAccess},
   util::{replace_ext, write_file},
 };
+use log::{info, warn};
 use sdl::SdlSystem;
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum, Sdl};
+use sdl2::{
+  controller::GameController,
+  event::{Event, WindowEvent},
+  keyboard::Keycode,
+  pixels::PixelFormatEnum,
+  ttf::Sdl2TtfContext,
+  Sdl,
+};
 use std::{cmp::max, env::set_var, path::Path};
 
 const SCREEN_SCALE: f32 = 3.0;
 const STORE_RATE: u8 = 5;
 // const DEFAULT_ROM_PATH: &str = "../../res/roms/demo/pocket.gb";
 const DEFAULT_ROM_PATH: &str = "../../res/roms/game/thebouncingball.gb";
+const DEFAULT_FONT_PATH: &str = "../../res/fonts/font.ttf";
+const PROFILE_TOP: usize = 20;
 
-pub struct Emulator {
+pub struct Emulator<'a> {
   system: GameBoy,
   sdl: Option<SdlSystem>,
+  debug: Option<DebugWindows<'a>>,
+  gdb: Option<GdbServer>,
+  font_path: String,
+  trace_target: Option<String>,
   rom_path: String,
+  rom_entry: Option<String>,
   ram_path: String,
+  /// If the cartridge RAM is stored in the save file, which is not
+  /// the case for movies as they start from a clean RAM.
+  store_ram: bool,
   dir_path: String,
   logic_frequency: u32,
   visual_frequency: f32,
@@ -30,15 +63,23 @@ pub struct Emulator {
   fast: bool,
   palettes: [PaletteInfo; 7],
   palette_index: usize,
+  input: Input,
+  controllers: Vec<GameController>,
 }
 
-impl Emulator {
+impl<'a> Emulator<'a> {
   pub fn new(system: GameBoy) -> Self {
     Self {
       system,
       sdl: None,
+      debug: None,
+      gdb: None,
+      font_path: String::from(DEFAULT_FONT_PATH),
+      trace_target: None,
       rom_path: String::from("invalid"),
+      rom_entry: None,
       ram_path: String::from("invalid"),
+      store_ram: true,
       dir_path: String::from("invalid"),
       logic_frequency: GameBoy::CPU_FREQ,
       visual_frequency: GameBoy::VISUAL_FREQ,
@@ -111,15 +152,19 @@ impl Emulator {
         ),
       ],
       palette_index: 0,
+      input: Input::new(&Bindings::default(), GameBoy::VISUAL_FREQ),
+      controllers: vec![],
     }
   }
 
-  pub fn start(&mut self, screen_scale: f32) {
+  /// Starts the graphics using the TTF context, which outlives
+  /// the emulator so that the debug windows can keep its fonts.
+  pub fn start(&mut self, ttf_context: &'a Sdl2TtfContext, screen_scale: f32) {
     let sdl = sdl2::init().unwrap();
-    self.start_graphics(&sdl, screen_scale);
+    self.start_graphics(&sdl, ttf_context, screen_scale);
   }
 
-  pub fn start_graphics(&mut self, sdl: &Sdl, screen_scale: f32) {
+  pub fn start_graphics(&mut self, sdl: &Sdl, ttf_context: &'a Sdl2TtfContext, screen_scale: f32) {
     self.sdl = Some(SdlSystem::new(
       sdl,
       "GBREMU",
@@ -127,13 +172,82 @@ impl Emulator {
       self.system.display_height() as u32,
       screen_scale,
     ));
+    self.debug = Some(DebugWindows::new(ttf_context, &self.font_path));
+  }
+
+  pub fn start_gdb(&mut self, port: u16) -> Result<(), Error> {
+    self.gdb = Some(GdbServer::bind(port)?);
+    Ok(())
+  }
+
+  pub fn set_font_path(&mut self, path: &str) {
+    self.font_path = String::from(path);
+  }
+
+  pub fn set_bindings(&mut self, bindings: &Bindings) {
+    self.input = Input::new(bindings, self.visual_frequency);
+  }
+
+  /// Applies the presses (and lifts) of the keys to the pad.
+  fn input_keys(&mut self, keys: Vec<(PadKey, bool)>) {
+    for (key, pressed) in keys {
+      if pressed {
+        self.system.key_press(key)
+      } else {
+        self.system.key_lift(key)
+      }
+    }
+  }
+
+  fn turbo_frame(&mut self) {
+    let keys = self.input.turbo_frame();
+    self.input_keys(keys);
+  }
+
+  /// Opens the game controller plugged in, SDL notifies the
+  /// controllers already present on start as added as well.
+  fn add_controller(&mut self, index: u32) {
+    let subsystem = &self.sdl.as_ref().unwrap().game_controller_subsystem;
+    match subsystem.open(index) {
+      Ok(controller) => {
+        info!("Controller connected: {}", controller.name());
+        self.controllers.push(controller);
+      },
+      Err(err) => warn!("Failed to open controller {}: {}", index, err),
+    }
+  }
+
+  fn remove_controller(&mut self, id: u32) {
+    if let Some(index) = self
+      .controllers
+      .iter()
+      .position(|controller| controller.instance_id() == id)
+    {
+      let controller = self.controllers.remove(index);
+      info!("Controller disconnected: {}", controller.name());
+    }
+  }
+
+  /// Sets the symbol or address whose bus accesses are going to be
+  /// logged, the trace is (re-)installed whenever a cartridge is loaded.
+  pub fn set_trace_target(&mut self, target: &str) {
+    self.trace_target = Some(String::from(target));
+  }
+
+  /// Sets the name of the entry to be loaded when the ROM path
+  /// is a ZIP archive, by default the first ROM entry is loaded.
+  pub fn set_rom_entry(&mut self, entry: Option<&str>) {
+    self.rom_entry = entry.map(String::from);
   }
 
   pub fn load_cart(&mut self, path: Option<&str>) -> Result<(), Error> {
     let rom_path: &str = path.unwrap_or(&self.rom_path);
+    // the save file is kept next to the archive (eg: `game.zip`
+    // has its save in `game.sav`) and not inside of it
     let ram_path = replace_ext(rom_path, "sav").unwrap_or_else(|| "invalid".to_string());
-    let cart = self.system.load_cart_file(
+    let cart = self.system.load_cart_file_entry(
       rom_path,
+      self.rom_entry.as_deref(),
       if Path::new(&ram_path).exists() {
         Some(&ram_path)
       } else {
@@ -144,12 +258,16 @@ impl Emulator {
       "========= Cartridge =========\n{}\n=============================",
       cart.header()
     );
+    if let Some(rom_entry) = cart.rom_entry() {
+      println!("Database: {}", rom_entry);
+    }
     if let Some(ref mut sdl) = self.sdl {
       sdl
         .window_mut()
         .set_title(format!("{} [{}]", "GBREMU", cart.title()).as_str())
         .unwrap();
     }
+    drop(cart);
     self.rom_path = String::from(rom_path);
     self.ram_path = ram_path;
     self.dir_path = Path::new(&self.rom_path)
@@ -158,12 +276,84 @@ impl Emulator {
       .to_str()
       .unwrap()
       .to_string();
+    self.load_symbols();
+    self.load_cheats();
     Ok(())
   }
 
+  /// Loads the RGBDS symbol file (if any) next to the ROM, so that
+  /// labels are used in the debugger and in the trace logs.
+  fn load_symbols(&mut self) {
+    let sym_path = replace_ext(&self.rom_path, "sym").unwrap_or_else(|| "invalid".to_string());
+    let symbols = if Path::new(&sym_path).exists() {
+      match SymbolTable::from_file(&sym_path) {
+        Ok(symbols) => {
+          info!("Loaded {} symbols from {}", symbols.len(), sym_path);
+          symbols
+        },
+        Err(error) => {
+          warn!("Failed to load symbols: {}", error);
+          SymbolTable::default()
+        },
+      }
+    } else {
+      SymbolTable::default()
+    };
+    self.system.set_symbols(symbols);
+    self.install_trace();
+  }
+
+  /// Loads the cheats file (if any) next to the ROM, the file
+  /// has one Game Genie or GameShark code per line.
+  fn load_cheats(&mut self) {
+    let cht_path = replace_ext(&self.rom_path, "cht").unwrap_or_else(|| "invalid".to_string());
+    let cheats = if Path::new(&cht_path).exists() {
+      match Cheats::from_file(&cht_path) {
+        Ok(cheats) => {
+          for cheat in cheats.cheats() {
+            info!("Cheat: {}", cheat);
+          }
+          cheats
+        },
+        Err(error) => {
+          warn!("Failed to load cheats: {}", error);
+          Cheats::default()
+        },
+      }
+    } else {
+      Cheats::default()
+    };
+    self.system.set_cheats(cheats);
+  }
+
+  fn install_trace(&mut self) {
+    let target = match &self.trace_target {
+      Some(target) => target,
+      None => return,
+    };
+    let addr = self.system.resolve_symbol(target).or_else(|| {
+      u16::from_str_radix(target.trim_start_matches("0x").trim_start_matches('$'), 16).ok()
+    });
+    let addr = match addr {
+      Some(addr) => addr,
+      None => {
+        warn!("Unknown trace target: {}", target);
+        return;
+      },
+    };
+    let symbols = self.system.symbols().clone();
+    self
+      .system
+      .set_bus_trace(Box::new(move |access: &BusAccess| {
+        if access.addr == addr {
+          info!("{}", trace_line(&symbols, access));
+        }
+      }));
+  }
+
   pub fn reset(&mut self) -> Result<(), Error> {
     self.system.reset();
-    self.system.load_dmg();
+    self.system.load_model();
     self.load_cart(None)?;
     Ok(())
   }
@@ -189,8 +379,98 @@ impl Emulator {
     }
   }
 
+  pub fn toggle_debug(&mut self, view: DebugView) {
+    let video_subsystem = &self.sdl.as_ref().unwrap().video_subsystem;
+    self.debug.as_mut().unwrap().toggle(video_subsystem, view);
+  }
+
+  pub fn render_debug(&mut self) {
+    let debug = self.debug.as_mut().unwrap();
+    if debug.is_empty() {
+      return;
+    }
+    debug.render(&self.system);
+  }
+
+  /// Runs the system for the number of frames without any
+  /// display or input, as fast as possible.
+  pub fn run_headless(&mut self, frames: u32) {
+    let mut last_frame = self.system.ppu_frame();
+    for _ in 0..frames {
+      while self.system.ppu_frame() == last_frame {
+        self.system.clock();
+      }
+      last_frame = self.system.ppu_frame();
+    }
+  }
+
+  /// Starts recording the code/data log, continuing the
+  /// one in the file in case it already exists.
+  pub fn start_cdl(&mut self, path: &str) -> Result<(), Error> {
+    if Path::new(path).exists() {
+      self.system.load_cdl_file(path)?;
+    } else {
+      self.system.start_cdl();
+    }
+    Ok(())
+  }
+
+  /// Starts recording a movie, the save file is not touched
+  /// from now on as the movie is recorded from a clean RAM.
+  pub fn record_movie(&mut self, players: usize) -> Result<(), Error> {
+    self.system.record_movie(players)?;
+    self.store_ram = false;
+    Ok(())
+  }
+
+  /// Plays the movie in the file, without touching the save file
+  /// from now on as the movie is played from a clean RAM.
+  pub fn play_movie_file(&mut self, path: &str) -> Result<(), Error> {
+    self.system.load_movie_file(path)?;
+    self.store_ram = false;
+    Ok(())
+  }
+
+  /// Stops the profiler writing the folded stacks into the
+  /// file and logging a summary of the profiling.
+  pub fn write_profile(&mut self, path: &str) -> Result<(), Error> {
+    let profiler = match self.system.stop_profiler() {
+      Some(profiler) => profiler,
+      None => return Ok(()),
+    };
+    let symbols = self.system.symbols();
+    profiler.write_folded(path, symbols)?;
+
+    let total = max(profiler.total_cycles(), 1) as f64;
+    info!(
+      "Profiled {} cycles ({} instructions) into {}",
+      profiler.total_cycles(),
+      profiler.instructions(),
+      path
+    );
+    for (bank, cycles) in profiler.bank_cycles() {
+      info!(
+        "ROM bank {:02X}: {:>12} cycles ({:5.1}%)",
+        bank,
+        cycles,
+        cycles as f64 * 100.0 / total
+      );
+    }
+    for function in profiler.functions().iter().take(PROFILE_TOP) {
+      info!(
+        "{:<32} {:>8} calls {:>12} total {:>12} self ({:5.1}%)",
+        Profiler::name(symbols, function.location),
+        function.calls,
+        function.total_cycles,
+        function.self_cycles,
+        function.total_cycles as f64 * 100.0 / total
+      );
+    }
+    Ok(())
+  }
+
   pub fn run(&mut self) {
-    let (width, height) = (self.system.display_width(), self.system.display_height());
+    let (mut width, mut height) = (self.system.display_width(), self.system.display_height());
     self.sdl.as_mut().unwrap().canvas.present();
     let texture_creator = self.sdl.as_mut().unwrap().canvas.texture_creator();
     let mut texture = texture_creator
@@ -209,15 +489,36 @@ impl Emulator {
 
     'main: loop {
       counter = counter.wrapping_add(1);
-      if counter % store_count == 0 && self.system.cart().has_battery() {
+      if self.store_ram && counter % store_count == 0 && self.system.cart().has_battery() {
         let cart = self.system.cart();
         let ram_data = cart.ram();
         write_file(&self.ram_path, ram_data.inner()).unwrap();
       }
 
+      // processes any pending request from the (optional) attached
+      // debugger, which may halt or resume the execution
+      if let Some(gdb) = self.gdb.as_mut() {
+        if let Err(error) = gdb.poll(&mut self.system) {
+          warn!("GDB connection error: {}", error);
+          gdb.disconnect();
+        }
+      }
+
       while let Some(event) = self.sdl.as_mut().unwrap().event_pump.poll_event() {
         match event {
           Event::Quit { .. } => break 'main,
+          Event::Window {
+            window_id,
+            win_event: WindowEvent::Close,
+            ..
+          } => {
+            // closing one of the debug windows only closes that window,
+            // while closing the main window terminates the emulator
+            let is_debug = self.debug.as_mut().unwrap().close(window_id);
+            if !is_debug {
+              break 'main;
+            }
+          },
           Event::KeyDown {
             keycode: Some(Keycode::Escape),
             ..
@@ -252,26 +553,71 @@ impl Emulator {
             keycode: Some(Keycode::F),
             ..
           } => self.toggle_fullscreen(),
+          Event::KeyDown {
+            keycode: Some(Keycode::Num1),
+            ..
+          } => self.system.toggle_layer(LAYER_BG),
+          Event::KeyDown {
+            keycode: Some(Keycode::Num2),
+            ..
+          } => self.system.toggle_layer(LAYER_WINDOW),
+          Event::KeyDown {
+            keycode: Some(Keycode::Num3),
+            ..
+          } => self.system.toggle_layer(LAYER_OBJ),
+          Event::KeyDown {
+            keycode: Some(Keycode::Num0),
+            ..
+          } => self.system.show_all_layers(),
+          Event::KeyDown {
+            keycode: Some(Keycode::F1),
+            ..
+          } => self.toggle_debug(DebugView::Tiles),
+          Event::KeyDown {
+            keycode: Some(Keycode::F2),
+            ..
+          } => self.toggle_debug(DebugView::Maps),
+          Event::KeyDown {
+            keycode: Some(Keycode::F3),
+            ..
+          } => self.toggle_debug(DebugView::Objects),
+          Event::KeyDown {
+            keycode: Some(Keycode::F4),
+            ..
+          } => self.toggle_debug(DebugView::Cpu),
           Event::DropFile { filename, .. } => {
             self.system.reset();
-            self.system.load_dmg();
+            self.system.load_model();
+            self.set_rom_entry(None);
             self.load_cart(Some(&filename)).unwrap();
           },
           Event::KeyDown {
             keycode: Some(keycode),
             ..
           } => {
-            if let Some(key) = key_to_pad(keycode) {
-              self.system.key_press(key)
-            }
+            let keys = self.input.key_event(keycode, true);
+            self.input_keys(keys);
           },
           Event::KeyUp {
             keycode: Some(keycode),
             ..
           } => {
-            if let Some(key) = key_to_pad(keycode) {
-              self.system.key_lift(key)
-            }
+            let keys = self.input.key_event(keycode, false);
+            self.input_keys(keys);
+          },
+          Event::ControllerDeviceAdded { which, .. } => self.add_controller(which),
+          Event::ControllerDeviceRemoved { which, .. } => self.remove_controller(which),
+          Event::ControllerButtonDown { button, .. } => {
+            let keys = self.input.button_event(button, true);
+            self.input_keys(keys);
+          },
+          Event::ControllerButtonUp { button, .. } => {
+            let keys = self.input.button_event(button, false);
+            self.input_keys(keys);
+          },
+          Event::ControllerAxisMotion { axis, value, .. } => {
+            let keys = self.input.axis_motion(axis, value);
+            self.input_keys(keys);
           },
           _ => (),
         }
@@ -303,6 +649,15 @@ impl Emulator {
             break;
           }
 
+          // in case the execution is halted by the debugger (eg: because
+          // a breakpoint has been reached) the system is not clocked
+          if let Some(gdb) = self.gdb.as_mut() {
+            if gdb.should_break(&self.system) {
+              pending_cycles = 0;
+              break;
+            }
+          }
+
           // runs the Game Boy clock, this operation should
           // include the advance of both the CPU, PPU, APU
           // and any other frequency based component of the system
@@ -311,18 +666,37 @@ impl Emulator {
           // in case a new frame is available from the emulator
           // then the frame must be pushed into SDL for display
           if self.system.ppu_frame() != last_frame {
-            // obtains the frame buffer of the Game Boy PPU and uses it
-            // to update the stream texture, that will latter be copied
-            // to the canvas
+            // obtains the frame buffer of the Game Boy (the SGB output for
+            // the SGB models) and uses it to update the stream texture,
+            // that will latter be copied to the canvas
+            // the texture is created again when the size of the display
+            // changes (eg: switching to or from the SGB model of a movie)
+            let size = (self.system.display_width(), self.system.display_height());
+            if size != (width, height) {
+              (width, height) = size;
+              texture = texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                .unwrap();
+              self
+                .sdl
+                .as_mut()
+                .unwrap()
+                .canvas
+                .set_logical_size(width as u32, height as u32)
+                .unwrap();
+            }
             {
-              let mut ppu_mut = self.system.ppu_mut();
-              let frame_buffer = ppu_mut.frame_buffer();
-              texture.update(None, frame_buffer, width * 3).unwrap();
+              let frame_buffer = self.system.frame_buffer();
+              texture.update(None, &frame_buffer, width * 3).unwrap();
             }
             // obtains the index of the current PPU frame, this value
             // is going to be used to detect for new frame presence
             last_frame = self.system.ppu_frame();
             frame_dirty = true;
+
+            // advances the turbo buttons, so that their rate is
+            // kept in emulated frames (and so on fast forward)
+            self.turbo_frame();
           }
         }
 
@@ -350,6 +724,10 @@ impl Emulator {
           // presents the canvas effectively updating the screen
           // information presented to the user
           self.sdl.as_mut().unwrap().canvas.present();
+
+          // refreshes the (optional) debug windows so that they
+          // reflect the state of the system for the new frame
+          self.render_debug();
         }
 
         // calculates the number of ticks that have elapsed since the
@@ -390,8 +768,95 @@ impl Emulator {
 
 #[derive(Parser)]
 struct Args {
-  #[arg(default_value_t = String::from(DEFAULT_ROM_PATH), help = "Path to the ROM file to be loaded")]
+  #[arg(default_value_t = String::from(DEFAULT_ROM_PATH), help = "Path to the ROM file (or ZIP/gzip archive) to be loaded")]
   rom_path: String,
+
+  #[arg(
+    long,
+    help = "Name of the entry to be loaded from the ZIP archive (first ROM by default)"
+  )]
+  entry: Option<String>,
+
+  #[arg(
+    long,
+    help = "Refuses to load ROMs with header warnings (bad logo, checksums, size or type)"
+  )]
+  strict: bool,
+
+  #[arg(long, default_value_t = String::from(DEFAULT_FONT_PATH), help = "Path to the TTF font used by the debug windows")]
+  font_path: String,
+
+  #[arg(
+    long,
+    default_value = "dmg",
+    value_parser = parse_model,
+    help = "Hardware model to be emulated (DMG0, DMG, MGB, SGB, SGB2, CGB or AGB)"
+  )]
+  model: Model,
+
+  #[arg(long, help = "Port (on localhost) for the GDB remote protocol server")]
+  gdb: Option<u16>,
+
+  #[arg(long, help = "Symbol or address whose bus accesses are logged")]
+  trace: Option<String>,
+
+  #[arg(
+    long,
+    help = "Path of the folded stacks file written by the profiler on exit"
+  )]
+  profile: Option<String>,
+
+  #[arg(long, help = "Number of frames to run without display (headless)")]
+  headless: Option<u32>,
+
+  #[arg(
+    long,
+    help = "Path of the code/data log (CDL) to be continued and saved on exit"
+  )]
+  cdl: Option<String>,
+
+  #[arg(
+    long,
+    help = "Path of the No-Intro DAT file used to identify the ROM (and its .ovr overrides)"
+  )]
+  romdb: Option<String>,
+
+  #[arg(
+    long,
+    help = "Path of the boot ROM (DMG0, DMG, MGB, SGB or CGB) to be used instead of the embedded one"
+  )]
+  boot: Option<String>,
+
+  #[arg(
+    long,
+    help = "Skips the boot ROM, starting the game with the state left by it"
+  )]
+  skip_boot: bool,
+
+  #[arg(
+    long,
+    help = "Displays only the (colorized) Game Boy screen on the SGB models, without the border"
+  )]
+  no_sgb_border: bool,
+
+  #[arg(
+    long,
+    help = "Path of the movie file where the input (from power on) is recorded to on exit"
+  )]
+  record: Option<String>,
+
+  #[arg(long, help = "Path of the movie file to be played (from power on)")]
+  play: Option<String>,
+
+  #[arg(
+    long,
+    help = "Path of the TOML input bindings file (gbremu/bindings.toml in the config directory by default)"
+  )]
+  bindings: Option<String>,
+}
+
+fn parse_model(value: &str) -> Result<Model, String> {
+  Model::from_name(value).ok_or_else(|| format!("Unknown model: {}", value))
 }
 
 fn main() {
@@ -406,28 +871,111 @@ fn main() {
     return;
   }
 
-  let mut game_boy = GameBoy::new();
-  game_boy.load_dmg();
+  let mut game_boy = GameBoy::new(args.model);
+  if let Some(path) = &args.boot {
+    game_boy.load_boot_file(path).unwrap();
+  }
+  game_boy.set_skip_boot(args.skip_boot);
+  game_boy.set_sgb_border(!args.no_sgb_border);
+  game_boy.load_model();
+  if args.strict {
+    game_boy.set_load_policy(LoadPolicy::Strict);
+  }
+  if let Some(path) = &args.romdb {
+    game_boy.load_rom_db_file(path).unwrap();
+  }
+
+  // declared before the emulator so that it outlives its fonts
+  let ttf_context = match args.headless {
+    Some(_) => None,
+    None => Some(sdl2::ttf::init().unwrap()),
+  };
 
   let mut emulator = Emulator::new(game_boy);
-  emulator.start(SCREEN_SCALE);
+  emulator.set_font_path(&args.font_path);
+  emulator.set_bindings(&load_bindings(args.bindings.as_deref()));
+  emulator.set_rom_entry(args.entry.as_deref());
+  if let Some(target) = &args.trace {
+    emulator.set_trace_target(target);
+  }
+  if let Some(ttf_context) = &ttf_context {
+    emulator.start(ttf_context, SCREEN_SCALE);
+  }
+  if let Some(port) = args.gdb {
+    emulator.start_gdb(port).unwrap();
+  }
   emulator.load_cart(Some(&args.rom_path)).unwrap();
   emulator.toggle_palette();
+  if args.profile.is_some() {
+    emulator.system.start_profiler();
+  }
+  if let Some(path) = &args.cdl {
+    emulator.start_cdl(path).unwrap();
+  }
+  if args.record.is_some() {
+    emulator.record_movie(1).unwrap();
+  }
+  if let Some(path) = &args.play {
+    emulator.play_movie_file(path).unwrap();
+  }
 
-  emulator.run();
-}
+  match args.headless {
+    Some(frames) => emulator.run_headless(frames),
+    None => emulator.run(),
+  }
 
-fn key_to_pad(keycode: Keycode) -> Option<PadKey> {
-  match keycode {
-    Keycode::Up => Some(PadKey::Up),
-    Keycode::Down => Some(PadKey::Down),
-    Keycode::Left => Some(PadKey::Left),
-    Keycode::Right => Some(PadKey::Right),
-    Keycode::Return => Some(PadKey::Start),
-    Keycode::Return2 => Some(PadKey::Start),
-    Keycode::Space => Some(PadKey::Select),
-    Keycode::A => Some(PadKey::A),
-    Keycode::S => Some(PadKey::B),
-    _ => None,
+  if let Some(path) = &args.profile {
+    emulator.write_profile(path).unwrap();
+  }
+  if let Some(path) = &args.cdl {
+    emulator.system.save_cdl_file(path).unwrap();
   }
+  if let Some(path) = &args.record {
+    if let Some(movie) = emulator.system.stop_movie() {
+      movie.save_file(path).unwrap();
+    }
+  }
+}
+
+/// Loads the input bindings from the provided path or from the
+/// default one (if it exists), falling back to the default ones.
+fn load_bindings(path: Option<&str>) -> Bindings {
+  let path = match path {
+    Some(path) => String::from(path),
+    None => match Bindings::default_path().filter(|path| path.exists()) {
+      Some(path) => path.to_string_lossy().into_owned(),
+      None => return Bindings::default(),
+    },
+  };
+  Bindings::from_file(&path).unwrap_or_else(|err| {
+    warn!(
+      "Using the default bindings, failed to load {}: {}",
+      path, err
+    );
+    Bindings::default()
+  })
+}
+
+/// Formats a bus access, naming both the address and the
+/// instruction that made it after the loaded symbols.
+fn trace_line(symbols: &SymbolTable, access: &BusAccess) -> String {
+  let name = |addr: u16| {
+    symbols
+      .label(SymbolTable::bank_for(addr, access.bank), addr)
+      .or_else(|| io_register_name(addr).map(String::from))
+      .unwrap_or_else(|| format!("${:04X}", addr))
+  };
+  let (kind, arrow) = match access.kind {
+    AccessKind::Read => ("R", "->"),
+    AccessKind::Write => ("W", "<-"),
+  };
+  format!(
+    "[{:>10}] {:<24} {} {} {} ${:02X}",
+    access.cycle,
+    name(access.pc),
+    kind,
+    name(access.addr),
+    arrow,
+    access.value
+  )
 }
@@ -1,5 +1,5 @@
 use sdl2::{
-  render::Canvas, ttf::Sdl2TtfContext, video::Window, AudioSubsystem, EventPump, Sdl,
+  render::Canvas, video::Window, AudioSubsystem, EventPump, GameControllerSubsystem, Sdl,
   TimerSubsystem, VideoSubsystem,
 };
 
@@ -8,8 +8,8 @@ pub struct SdlSystem {
   pub video_subsystem: VideoSubsystem,
   pub timer_subsystem: TimerSubsystem,
   pub audio_subsystem: AudioSubsystem,
+  pub game_controller_subsystem: GameControllerSubsystem,
   pub event_pump: EventPump,
-  pub ttf_context: Sdl2TtfContext,
 }
 
 impl SdlSystem {
@@ -17,10 +17,9 @@ impl SdlSystem {
     let video_subsystem = sdl.video().unwrap();
     let timer_subsystem = sdl.timer().unwrap();
     let audio_subsystem = sdl.audio().unwrap();
+    let game_controller_subsystem = sdl.game_controller().unwrap();
     let event_pump = sdl.event_pump().unwrap();
 
-    let ttf_context = sdl2::ttf::init().unwrap();
-
     let window = video_subsystem
       .window(
         title,
@@ -44,8 +43,8 @@ impl SdlSystem {
       video_subsystem,
       timer_subsystem,
       audio_subsystem,
+      game_controller_subsystem,
       event_pump,
-      ttf_context,
     }
   }
 
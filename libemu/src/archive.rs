@@ -0,0 +1,351 @@
+use std::{io::Read, path::Path};
+
+use flate2::read::{DeflateDecoder, GzDecoder};
+
+use crate::{
+  cartridge::MAX_ROM_SIZE,
+  error::Error,
+  util::{crc32, read_file},
+};
+
+/// Extensions of the ROM files looked for inside of the archives.
+pub const ROM_EXTENSIONS: [&str; 2] = ["gb", "gbc"];
+
+const ZIP_LOCAL_HEADER: u32 = 0x04034b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP_END_OF_CENTRAL: u32 = 0x06054b50;
+const ZIP_END_SIZE: usize = 22;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArchiveFormat {
+  Zip,
+  Gzip,
+  SevenZip,
+}
+
+impl ArchiveFormat {
+  /// Detects the format of the archive from its signature.
+  pub fn from_data(data: &[u8]) -> Option<Self> {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+      Some(ArchiveFormat::Zip)
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+      Some(ArchiveFormat::Gzip)
+    } else if data.starts_with(b"7z\xbc\xaf\x27\x1c") {
+      Some(ArchiveFormat::SevenZip)
+    } else {
+      None
+    }
+  }
+
+  pub fn description(&self) -> &'static str {
+    match self {
+      ArchiveFormat::Zip => "ZIP",
+      ArchiveFormat::Gzip => "gzip",
+      ArchiveFormat::SevenZip => "7z",
+    }
+  }
+}
+
+/// File stored in a ZIP archive, as listed in its central directory.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ZipEntry {
+  pub name: String,
+  pub method: u16,
+  pub crc: u32,
+  pub compressed_size: usize,
+  pub size: usize,
+  pub offset: usize,
+}
+
+/// Reads the ROM data from the file, which may either be a raw ROM
+/// or an archive, in which case the entry with the provided name
+/// (or the first ROM entry) is extracted from it.
+pub fn read_rom_file(path: &str, entry: Option<&str>) -> Result<Vec<u8>, Error> {
+  let data = read_file(path)?;
+  match ArchiveFormat::from_data(&data) {
+    Some(format) => extract_rom(&data, format, entry),
+    None => Ok(data),
+  }
+}
+
+pub fn extract_rom(
+  data: &[u8],
+  format: ArchiveFormat,
+  entry: Option<&str>,
+) -> Result<Vec<u8>, Error> {
+  match format {
+    ArchiveFormat::Zip => {
+      let entries = zip_entries(data)?;
+      let entry = match entry {
+        Some(name) => entries.iter().find(|entry| entry.name == name),
+        None => entries.iter().find(|entry| is_rom(&entry.name)),
+      }
+      .ok_or_else(|| {
+        Error::CustomError(format!(
+          "No ROM entry {}found in the archive",
+          entry.map(|name| format!("'{}' ", name)).unwrap_or_default()
+        ))
+      })?;
+      zip_extract(data, entry)
+    },
+    // gzip files hold a single file, so no entry has to be picked
+    ArchiveFormat::Gzip => {
+      let mut rom = vec![];
+      GzDecoder::new(data)
+        .take(MAX_ROM_SIZE as u64 + 1)
+        .read_to_end(&mut rom)
+        .map_err(|_| Error::CustomError(String::from("Failed to decompress gzip file")))?;
+      if rom.len() > MAX_ROM_SIZE {
+        return Err(Error::CustomError(String::from(
+          "gzip file too large for a ROM",
+        )));
+      }
+      Ok(rom)
+    },
+    ArchiveFormat::SevenZip => Err(Error::CustomError(String::from(
+      "7z archives are not supported",
+    ))),
+  }
+}
+
+/// Checks if the name of the file has one of the ROM extensions.
+pub fn is_rom(name: &str) -> bool {
+  Path::new(name)
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .is_some_and(|extension| {
+      ROM_EXTENSIONS
+        .iter()
+        .any(|rom| extension.eq_ignore_ascii_case(rom))
+    })
+}
+
+/// Lists the files of the ZIP archive, from its central directory.
+pub fn zip_entries(data: &[u8]) -> Result<Vec<ZipEntry>, Error> {
+  let invalid = || Error::CustomError(String::from("Invalid ZIP archive"));
+
+  // the end of central directory record is at the end of the file,
+  // followed only by a (variable length) comment
+  let end = (0..=data.len().saturating_sub(ZIP_END_SIZE))
+    .rev()
+    .find(|&offset| read_u32(data, offset) == Some(ZIP_END_OF_CENTRAL))
+    .ok_or_else(invalid)?;
+  let count = read_u16(data, end + 10).ok_or_else(invalid)? as usize;
+  let mut offset = read_u32(data, end + 16).ok_or_else(invalid)? as usize;
+
+  let mut entries = Vec::with_capacity(count);
+  for _ in 0..count {
+    if read_u32(data, offset) != Some(ZIP_CENTRAL_HEADER) {
+      return Err(invalid());
+    }
+    let field = |index: usize| read_u32(data, offset + index).ok_or_else(invalid);
+    let short = |index: usize| {
+      read_u16(data, offset + index)
+        .map(|value| value as usize)
+        .ok_or_else(invalid)
+    };
+    let name_size = short(28)?;
+    let name = data
+      .get(offset + 46..offset + 46 + name_size)
+      .ok_or_else(invalid)?;
+    entries.push(ZipEntry {
+      name: String::from_utf8_lossy(name).into_owned(),
+      method: short(10)? as u16,
+      crc: field(16)?,
+      compressed_size: field(20)? as usize,
+      size: field(24)? as usize,
+      offset: field(42)? as usize,
+    });
+    offset += 46 + name_size + short(30)? + short(32)?;
+  }
+  Ok(entries)
+}
+
+/// Extracts the (stored or deflated) entry of the ZIP archive,
+/// validating the CRC32 of the extracted data, entries larger
+/// than the largest ROM are rejected.
+pub fn zip_extract(data: &[u8], entry: &ZipEntry) -> Result<Vec<u8>, Error> {
+  let invalid = || Error::CustomError(format!("Invalid ZIP entry: {}", entry.name));
+  if entry.size > MAX_ROM_SIZE {
+    return Err(Error::CustomError(format!(
+      "ZIP entry too large for a ROM: {}",
+      entry.name
+    )));
+  }
+  if read_u32(data, entry.offset) != Some(ZIP_LOCAL_HEADER) {
+    return Err(invalid());
+  }
+  let name_size = read_u16(data, entry.offset + 26).ok_or_else(invalid)? as usize;
+  let extra_size = read_u16(data, entry.offset + 28).ok_or_else(invalid)? as usize;
+  let start = entry.offset + 30 + name_size + extra_size;
+  let compressed = data
+    .get(start..start + entry.compressed_size)
+    .ok_or_else(invalid)?;
+
+  let contents = match entry.method {
+    0 => compressed.to_vec(),
+    8 => {
+      // the central directory size is not trusted, reading one
+      // more byte so that larger contents are detected below
+      let mut contents = Vec::with_capacity(entry.size);
+      DeflateDecoder::new(compressed)
+        .take(entry.size as u64 + 1)
+        .read_to_end(&mut contents)
+        .map_err(|_| invalid())?;
+      contents
+    },
+    method => {
+      return Err(Error::CustomError(format!(
+        "Unsupported ZIP compression method {} for {}",
+        method, entry.name
+      )))
+    },
+  };
+
+  if contents.len() != entry.size || crc32(&contents) != entry.crc {
+    return Err(Error::CustomError(format!(
+      "ZIP entry checksum mismatch: {}",
+      entry.name
+    )));
+  }
+  Ok(contents)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+  let bytes = data.get(offset..offset + 2)?;
+  Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+  let bytes = data.get(offset..offset + 4)?;
+  Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use flate2::{write::DeflateEncoder, write::GzEncoder, Compression};
+
+  use super::*;
+
+  /// Builds a ZIP archive with the (name, method, contents) entries,
+  /// the contents being deflated for the method 8.
+  fn zip(files: &[(&str, u16, &[u8])]) -> Vec<u8> {
+    let mut data = vec![];
+    let mut central = vec![];
+    for (name, method, contents) in files {
+      let compressed = match method {
+        8 => {
+          let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+          encoder.write_all(contents).unwrap();
+          encoder.finish().unwrap()
+        },
+        _ => contents.to_vec(),
+      };
+      let offset = data.len() as u32;
+      let fields = |header: &mut Vec<u8>| {
+        header.extend_from_slice(&method.to_le_bytes());
+        header.extend_from_slice(&[0x00; 4]);
+        header.extend_from_slice(&crc32(contents).to_le_bytes());
+        header.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&[0x00; 2]);
+      };
+
+      data.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+      data.extend_from_slice(&[0x14, 0x00, 0x00, 0x00]);
+      fields(&mut data);
+      data.extend_from_slice(name.as_bytes());
+      data.extend_from_slice(&compressed);
+
+      central.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+      central.extend_from_slice(&[0x14, 0x00, 0x14, 0x00, 0x00, 0x00]);
+      fields(&mut central);
+      central.extend_from_slice(&[0x00; 10]);
+      central.extend_from_slice(&offset.to_le_bytes());
+      central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = data.len() as u32;
+    data.extend_from_slice(&central);
+    data.extend_from_slice(&ZIP_END_OF_CENTRAL.to_le_bytes());
+    data.extend_from_slice(&[0x00; 4]);
+    data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    data.extend_from_slice(&central_offset.to_le_bytes());
+    data.extend_from_slice(&[0x00; 2]);
+    data
+  }
+
+  #[test]
+  fn test_zip() {
+    let rom = [0x42u8; 0x200];
+    let data = zip(&[
+      ("readme.txt", 0, b"hello"),
+      ("game.gb", 8, &rom),
+      ("other.GBC", 0, b"stored"),
+    ]);
+    assert_eq!(ArchiveFormat::from_data(&data), Some(ArchiveFormat::Zip));
+
+    let entries = zip_entries(&data).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[1].name, "game.gb");
+    assert_eq!(entries[1].method, 8);
+    assert_eq!(entries[1].size, rom.len());
+
+    assert_eq!(extract_rom(&data, ArchiveFormat::Zip, None).unwrap(), rom);
+    assert_eq!(
+      extract_rom(&data, ArchiveFormat::Zip, Some("other.GBC")).unwrap(),
+      b"stored"
+    );
+    assert!(extract_rom(&data, ArchiveFormat::Zip, Some("missing.gb")).is_err());
+  }
+
+  #[test]
+  fn test_zip_invalid() {
+    let data = zip(&[("game.gb", 8, &[0x42u8; 0x200])]);
+    let entry = &zip_entries(&data).unwrap()[0];
+
+    let mut corrupt = data.clone();
+    corrupt[40] ^= 0xff;
+    assert!(zip_extract(&corrupt, entry).is_err());
+
+    // sizes stated by the central directory that can't be trusted
+    let smaller = ZipEntry {
+      size: 0x100,
+      ..entry.clone()
+    };
+    assert!(zip_extract(&data, &smaller).is_err());
+    let larger = ZipEntry {
+      size: u32::MAX as usize,
+      ..entry.clone()
+    };
+    assert!(zip_extract(&data, &larger).is_err());
+
+    assert!(zip_entries(&data[..data.len() - 4]).is_err());
+    assert!(zip_entries(b"PK\x03\x04").is_err());
+  }
+
+  #[test]
+  fn test_gzip() {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(b"rom data").unwrap();
+    let data = encoder.finish().unwrap();
+    assert_eq!(ArchiveFormat::from_data(&data), Some(ArchiveFormat::Gzip));
+    assert_eq!(
+      extract_rom(&data, ArchiveFormat::Gzip, None).unwrap(),
+      b"rom data"
+    );
+  }
+
+  #[test]
+  fn test_is_rom() {
+    assert!(is_rom("game.gb"));
+    assert!(is_rom("dir/game.GBC"));
+    assert!(!is_rom("game.gba"));
+    assert!(!is_rom("gb"));
+  }
+}
@@ -1,13 +1,19 @@
-use std::cell::{Ref, RefMut};
+use std::{
+  cell::{Ref, RefMut},
+  rc::Rc,
+};
 
 use log::debug;
 
 use crate::{
   cartridge::Cartridge,
-  gb::{HRAM_SIZE, WRAM_SIZE},
+  gb::HRAM_SIZE,
   generic::{address::Address, device::Device, memory::Ram, shared::Shared},
+  memory_map::{MemoryRegion, RegionInfo, MEMORY_MAP},
+  model::Model,
   pad::Pad,
   soc::{boot::Boot, dma::Dma, ppu::Ppu},
+  trace::{AccessKind, BusTrace, TraceHook},
 };
 
 type HRam = Ram;
@@ -24,7 +30,20 @@ pub struct Bus {
   boot: Shared<Boot>,
   wram: Shared<WRam>,
 
+  /// Flat RAM backing the whole address space, only available
+  /// with the `flat-bus` feature as it's meant for tests.
+  #[cfg(feature = "flat-bus")]
+  flat: Option<Shared<Ram>>,
+  trace: Option<Rc<BusTrace>>,
+
   ie: u8,
+
+  model: Model,
+
+  /// Work RAM bank mapped at 0xD000-0xDFFF (SVBK), where
+  /// bank 0 selects bank 1, always 1 for the models other
+  /// than the CGB.
+  wram_bank: u8,
 }
 
 impl Bus {
@@ -38,10 +57,27 @@ impl Bus {
       boot: Boot::new().to_shared(),
       wram: WRam::default().to_shared(),
 
+      #[cfg(feature = "flat-bus")]
+      flat: None,
+      trace: None,
+
       ie: 0x00,
+
+      model: Model::default(),
+      wram_bank: 1,
     }
   }
 
+  /// Creates a bus whose complete 64 KB address space is backed
+  /// by a single flat RAM, bypassing every mapped device, this is
+  /// meant to drive the CPU in isolation (eg: instruction tests).
+  #[cfg(feature = "flat-bus")]
+  pub fn flat() -> Self {
+    let mut bus = Self::new();
+    bus.set_flat(Ram::from(vec![0u8; 0x10000].as_ref()).to_shared());
+    bus
+  }
+
   pub fn reset(&mut self) {
     self.hram.borrow_mut().reset();
     self.ppu.borrow_mut().reset();
@@ -51,9 +87,13 @@ impl Bus {
     self.boot.borrow_mut().reset();
     self.wram.borrow_mut().reset();
     self.ie = 0x00;
+    self.wram_bank = 1;
   }
 
-  pub fn allocate_dmg(&mut self) {
+  /// Allocates the memory available in the model.
+  pub fn allocate(&mut self, model: Model) {
+    self.model = model;
+    self.wram_bank = 1;
     self
       .hram
       .borrow_mut()
@@ -61,7 +101,7 @@ impl Bus {
     self
       .wram
       .borrow_mut()
-      .set_data(vec![0u8; WRAM_SIZE].as_ref());
+      .set_data(vec![0u8; model.wram_size()].as_ref());
   }
 }
 
@@ -109,11 +149,121 @@ impl Bus {
   pub fn set_dma(&mut self, dma: Shared<Dma>) {
     self.dma = dma
   }
+
+  #[cfg(feature = "flat-bus")]
+  pub fn set_flat(&mut self, flat: Shared<Ram>) {
+    self.flat = Some(flat)
+  }
 }
 
 impl Bus {
+  /// Installs a hook to be called for every read and write
+  /// performed through the bus.
+  pub fn set_trace(&mut self, hook: TraceHook) {
+    self.trace = Some(Rc::new(BusTrace::new(hook)));
+  }
+
+  pub fn clear_trace(&mut self) {
+    self.trace = None;
+  }
+
+  pub fn trace(&self) -> Option<&Rc<BusTrace>> {
+    self.trace.as_ref()
+  }
+
+  /// Resolves the region the provided address currently maps to,
+  /// taking into account the boot ROM overlay.
+  pub fn region(&self, addr: u16) -> MemoryRegion {
+    #[cfg(feature = "flat-bus")]
+    if self.flat.is_some() {
+      return MemoryRegion::Flat;
+    }
+    if self.boot.borrow().maps(addr) {
+      return MemoryRegion::Boot;
+    }
+    MemoryRegion::from_addr(addr)
+  }
+
+  /// Bank currently mapped in the switchable ROM area.
+  pub fn rom_bank(&self) -> u16 {
+    #[cfg(feature = "flat-bus")]
+    if self.flat.is_some() {
+      return 0;
+    }
+    self.cart.borrow().rom_bank()
+  }
+
+  /// Enumerates the regions currently mapped in the address space.
+  pub fn regions(&self) -> Vec<RegionInfo> {
+    #[cfg(feature = "flat-bus")]
+    if self.flat.is_some() {
+      return vec![RegionInfo::new(MemoryRegion::Flat, 0x0000, 0xffff)];
+    }
+    let mut regions = vec![];
+    if self.boot.borrow().maps(0x0000) {
+      regions.push(RegionInfo::new(MemoryRegion::Boot, 0x0000, 0x00ff));
+    }
+    if self.boot.borrow().maps(0x0200) {
+      regions.push(RegionInfo::new(MemoryRegion::Boot, 0x0200, 0x08ff));
+    }
+    for (region, start, end) in MEMORY_MAP {
+      regions.push(RegionInfo::new(region, start, end));
+    }
+    regions
+  }
+
+  /// Loads the boot ROM, mapping it over the cartridge
+  /// until it's unmapped with a write to 0xFF50.
   pub fn write_boot(&mut self, data: &[u8]) {
     self.boot.borrow_mut().set_data(data);
+    self.boot.borrow_mut().set_active(true);
+  }
+
+  pub fn oam_bug(&mut self, addr: u16) {
+    #[cfg(feature = "flat-bus")]
+    if self.flat.is_some() {
+      return;
+    }
+    self.ppu_mut().oam_bug(addr);
+  }
+
+  /// Offset in the work RAM of the address (or of its echo),
+  /// taking into account the bank mapped at 0xD000-0xDFFF.
+  fn wram_addr(&self, addr: u16) -> u16 {
+    match addr & 0x1000 {
+      0x0000 => addr & 0x0fff,
+      _ => self.wram_bank.max(1) as u16 * 0x1000 + (addr & 0x0fff),
+    }
+  }
+
+  /// Reads the address without the access being traced.
+  pub fn peek(&self, addr: u16) -> u8 {
+    self.read_device(addr)
+  }
+
+  /// Writes the address without the access being traced, for the
+  /// writes not made by the CPU (eg: debugger or boot state).
+  pub fn poke(&mut self, addr: u16, value: u8) {
+    self.write_device(addr, value)
+  }
+
+  /// Writes the work RAM address (or its echo) into the provided
+  /// bank of the 0xD000-0xDFFF area rather than into the mapped one,
+  /// only the CGB has more than one bank to select from.
+  pub fn poke_wram(&mut self, bank: Option<u8>, addr: u16, value: u8) {
+    let offset = match (addr & 0x1000, bank) {
+      (0x1000, Some(bank)) if self.model.is_cgb() => {
+        (bank & 0x07).max(1) as u16 * 0x1000 + (addr & 0x0fff)
+      },
+      _ => self.wram_addr(addr),
+    };
+    self.wram.write(offset, value);
+  }
+
+  /// Reads the range without the accesses being traced, for the
+  /// reads not made by the CPU (eg: the OAM DMA source).
+  pub fn peek_many(&self, addr: u16, count: u16) -> Vec<u8> {
+    (0..count).map(|index| self.peek(addr + index)).collect()
   }
 
   pub fn read_many(&self, addr: u16, count: u16) -> Vec<u8> {
@@ -134,10 +284,32 @@ impl Bus {
 
 impl Address for Bus {
   fn read(&self, addr: u16) -> u8 {
+    let value = self.read_device(addr);
+    if let Some(trace) = &self.trace {
+      trace.record(AccessKind::Read, addr, value, self.region(addr));
+    }
+    value
+  }
+
+  fn write(&mut self, addr: u16, value: u8) {
+    if let Some(trace) = &self.trace {
+      trace.record(AccessKind::Write, addr, value, self.region(addr));
+    }
+    self.write_device(addr, value);
+  }
+}
+
+impl Bus {
+  fn read_device(&self, addr: u16) -> u8 {
+    #[cfg(feature = "flat-bus")]
+    if let Some(flat) = &self.flat {
+      return flat.read(addr);
+    }
+
     match addr & 0xF000 {
-      // BOOT (256 B) + ROM0 (4 KB/16 KB)
+      // BOOT (256 B/2304 B) + ROM0 (4 KB/16 KB)
       0x0000 => {
-        if self.boot.borrow().boot_active() && addr <= 0x00FE {
+        if self.boot.borrow().maps(addr) {
           return self.boot.borrow().read(addr);
         }
         self.cart.read(addr)
@@ -151,15 +323,15 @@ impl Address for Bus {
       // External RAM (8 KB)
       0xa000 | 0xb000 => self.cart.read(addr),
       // Working RAM 0 (4 KB)
-      0xc000 => self.wram.read(addr & 0x0fff),
+      0xc000 => self.wram.read(self.wram_addr(addr)),
       // Working RAM 1 (Banked) (4KB)
-      0xd000 => self.wram.read(0x1000 + (addr & 0x0fff)),
+      0xd000 => self.wram.read(self.wram_addr(addr)),
       // Working RAM Shadow
-      0xe000 => self.wram.read(addr & 0x1fff),
+      0xe000 => self.wram.read(self.wram_addr(addr)),
       // Working RAM Shadow, I/O, Zero-page RAM
       0xf000 => match addr & 0x0f00 {
         0x000 | 0x100 | 0x200 | 0x300 | 0x400 | 0x500 | 0x600 | 0x700 | 0x800 | 0x900 | 0xa00
-        | 0xb00 | 0xc00 | 0xd00 => self.wram.read(addr & 0x1fff),
+        | 0xb00 | 0xc00 | 0xd00 => self.wram.read(self.wram_addr(addr)),
         0xe00 => self.ppu.read(addr),
         0xf00 => match addr & 0x00ff {
           // 0xFF01-0xFF02 - Serial data transfer
@@ -197,6 +369,12 @@ impl Address for Bus {
             0x40 | 0x60 | 0x70 => match addr & 0x00ff {
               // 0xFF46 — DMA: OAM DMA source address & start
               0x0046 => self.dma.read(addr),
+              // 0xFF70 — SVBK: WRAM bank (CGB only)
+              0x0070 if self.model.is_cgb() => 0xf8 | self.wram_bank,
+              0x004d | 0x004f => {
+                debug!("Reading from unimplemented IO control 0x{:04x}", addr);
+                0xFF
+              },
               // VRAM related read
               _ => self.ppu.read(addr),
             },
@@ -220,7 +398,12 @@ impl Address for Bus {
     }
   }
 
-  fn write(&mut self, addr: u16, value: u8) {
+  fn write_device(&mut self, addr: u16, value: u8) {
+    #[cfg(feature = "flat-bus")]
+    if let Some(flat) = &mut self.flat {
+      return flat.write(addr, value);
+    }
+
     match addr & 0xf000 {
       // BOOT (256 B) + ROM0 (4 KB/16 KB)
       0x0000 => self.cart.write(addr, value),
@@ -233,16 +416,17 @@ impl Address for Bus {
       // External RAM (8 KB)
       0xa000 | 0xb000 => self.cart.write(addr, value),
       // Working RAM 0 (4 KB)
-      0xc000 => self.wram.write(addr & 0x0FFF, value),
+      0xc000 => self.wram.write(self.wram_addr(addr), value),
       // Working RAM 1 (Banked) (4KB)
-      0xd000 => self.wram.write(0x1000 + (addr & 0x0fff), value),
+      0xd000 => self.wram.write(self.wram_addr(addr), value),
       // Working RAM Shadow
-      0xe000 => self.wram.write(addr & 0x1fff, value),
+      0xe000 => self.wram.write(self.wram_addr(addr), value),
       // Working RAM Shadow, I/O, Zero-page RAM
       0xf000 => match addr & 0x0f00 {
         0x000 | 0x100 | 0x200 | 0x300 | 0x400 | 0x500 | 0x600 | 0x700 | 0x800 | 0x900 | 0xa00
         | 0xb00 | 0xc00 | 0xd00 => {
-          self.wram.write(addr & 0x1fff, value);
+          let addr = self.wram_addr(addr);
+          self.wram.write(addr, value);
         },
         0xe00 => self.ppu.write(addr, value),
         0xf00 => match addr & 0x00ff {
@@ -274,6 +458,9 @@ impl Address for Bus {
             0x40 | 0x60 | 0x70 => match addr & 0x00ff {
               // 0xFF46 — DMA: OAM DMA source address & start
               0x0046 => self.dma.write(addr, value),
+              // 0xFF70 — SVBK: WRAM bank (CGB only)
+              0x0070 if self.model.is_cgb() => self.wram_bank = value & 0x07,
+              0x004d | 0x004f => debug!("Writing to unimplemented IO control 0x{:04x}", addr),
               // VRAM related write
               _ => self.ppu.write(addr, value),
             },
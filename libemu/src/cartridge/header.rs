@@ -1,4 +1,7 @@
-use std::convert::TryInto;
+use std::{
+  convert::TryInto,
+  fmt::{self, Display, Formatter},
+};
 
 use log::warn;
 
@@ -65,11 +68,8 @@ impl Header {
       .try_into()
       .map_err(|_| Error::CustomError("Can not into slice".to_string()))?;
 
-    // check logo
+    // check logo, a mismatch is reported by `validate()`
     let logo = header[0x04..=0x33] == LOGO;
-    if !logo {
-      return Err(Error::CustomError("Invalid logo".to_string()));
-    }
 
     // parse title
     // TODO: check title parser
@@ -110,8 +110,8 @@ impl Header {
       },
     };
 
-    // parse cartridge type
-    let cart = header[0x47].try_into()?;
+    // parse cartridge type, unknown types are reported by `validate()`
+    let cart = header[0x47].try_into().unwrap_or(CartType::Unknown);
 
     // parse ROM size
     let romsz = match header[0x48] {
@@ -146,34 +146,10 @@ impl Header {
     // parse version
     let version = header[0x4C];
 
-    // parse header checksum
+    // parse checksums (the global one is stored big endian),
+    // both are verified by `validate()`
     let hchk = header[0x4D];
-    let chk = rom[0x134..=0x14c]
-      .iter()
-      .copied()
-      .fold(0u8, |accum, itme| accum.wrapping_sub(itme).wrapping_sub(1));
-    if chk != hchk {
-      return Err(Error::CustomError(format!(
-        "Ecpected: {:#04x}, found: {:#04x}",
-        hchk, chk
-      )));
-    }
-
-    // parse global checksum
-    let gchk = u16::from_le_bytes([header[0x4E], header[0x4F]]);
-    let chk = rom
-      .iter()
-      .copied()
-      .fold(0u16, |accum, item| accum.wrapping_add(item as u16))
-      .wrapping_sub(rom[0x14E] as u16)
-      .wrapping_sub(rom[0x14F] as u16);
-    if chk != gchk {
-      // Gameboy doesn't verify this checksum
-      warn!(
-        "Global checksum mismatch: found {:#04x}, expected {:#04x}",
-        chk, gchk
-      )
-    }
+    let gchk = u16::from_be_bytes([header[0x4E], header[0x4F]]);
 
     Ok(Self {
       logo,
@@ -191,6 +167,130 @@ impl Header {
       gchk,
     })
   }
+
+  /// Verifies the header against the ROM it was parsed from,
+  /// reporting the issues found as warnings.
+  pub fn validate(&self, rom: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport::new();
+    if !self.logo {
+      report.push(HeaderWarning::LogoMismatch);
+    }
+    let chk = Self::header_checksum(rom);
+    if chk != self.hchk {
+      report.push(HeaderWarning::HeaderChecksum {
+        expected: self.hchk,
+        found: chk,
+      });
+    }
+    // the boot ROM doesn't verify the global checksum, so
+    // a mismatch is (on the real hardware) harmless
+    let chk = Self::global_checksum(rom);
+    if chk != self.gchk {
+      report.push(HeaderWarning::GlobalChecksum {
+        expected: self.gchk,
+        found: chk,
+      });
+    }
+    if self.romsz != rom.len() {
+      report.push(HeaderWarning::RomSizeMismatch {
+        header: self.romsz,
+        file: rom.len(),
+      });
+    }
+    if self.cart == CartType::Unknown {
+      report.push(HeaderWarning::UnknownCartType(rom[0x147]));
+    }
+    report
+  }
+
+  /// Computes the header checksum of the ROM (verified by
+  /// the boot ROM against the one at 0x014D).
+  pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..=0x14c]
+      .iter()
+      .copied()
+      .fold(0u8, |accum, itme| accum.wrapping_sub(itme).wrapping_sub(1))
+  }
+
+  /// Computes the global checksum of the ROM, the sum of all
+  /// of its bytes except the checksum (0x014E-0x014F) itself.
+  pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom
+      .iter()
+      .copied()
+      .fold(0u16, |accum, item| accum.wrapping_add(item as u16))
+      .wrapping_sub(rom[0x14E] as u16)
+      .wrapping_sub(rom[0x14F] as u16)
+  }
+}
+
+/// Issue found while validating the header of a ROM.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeaderWarning {
+  LogoMismatch,
+  HeaderChecksum { expected: u8, found: u8 },
+  GlobalChecksum { expected: u16, found: u16 },
+  RomSizeMismatch { header: usize, file: usize },
+  UnknownCartType(u8),
+}
+
+impl Display for HeaderWarning {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      HeaderWarning::LogoMismatch => write!(f, "Nintendo logo mismatch"),
+      HeaderWarning::HeaderChecksum { expected, found } => write!(
+        f,
+        "Header checksum mismatch: expected {:#04x}, found {:#04x}",
+        expected, found
+      ),
+      HeaderWarning::GlobalChecksum { expected, found } => write!(
+        f,
+        "Global checksum mismatch: expected {:#06x}, found {:#06x}",
+        expected, found
+      ),
+      HeaderWarning::RomSizeMismatch { header, file } => write!(
+        f,
+        "ROM size mismatch: header has {} B, file has {} B",
+        header, file
+      ),
+      HeaderWarning::UnknownCartType(byte) => write!(f, "Unknown cartridge type: {:#04x}", byte),
+    }
+  }
+}
+
+/// Result of the validation of the header of a ROM, a
+/// ROM without warnings would pass the boot ROM checks.
+#[derive(Clone, Default, Debug)]
+pub struct ValidationReport {
+  warnings: Vec<HeaderWarning>,
+}
+
+impl ValidationReport {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn push(&mut self, warning: HeaderWarning) {
+    self.warnings.push(warning);
+  }
+
+  pub fn warnings(&self) -> &[HeaderWarning] {
+    &self.warnings
+  }
+
+  pub fn is_valid(&self) -> bool {
+    self.warnings.is_empty()
+  }
+}
+
+impl Display for ValidationReport {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    if self.is_valid() {
+      return write!(f, "OK");
+    }
+    let warnings: Vec<String> = self.warnings.iter().map(ToString::to_string).collect();
+    write!(f, "{}", warnings.join(", "))
+  }
 }
 
 impl std::fmt::Display for Header {
@@ -237,3 +337,123 @@ impl std::fmt::Display for Header {
     write!(f, "└──────────────────┘")
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cartridge::{Cartridge, LoadPolicy};
+
+  /// Creates a 32 KB ROM only ROM with a valid header.
+  fn rom() -> Vec<u8> {
+    let mut data = vec![0x00; 0x8000];
+    data[0x104..0x134].copy_from_slice(&LOGO);
+    data[0x200] = 0x80;
+    fix_checksums(&mut data);
+    data
+  }
+
+  fn fix_checksums(data: &mut [u8]) {
+    data[0x14d] = Header::header_checksum(data);
+    fix_global_checksum(data);
+  }
+
+  fn fix_global_checksum(data: &mut [u8]) {
+    let checksum = Header::global_checksum(data);
+    data[0x14e..0x150].copy_from_slice(&checksum.to_be_bytes());
+  }
+
+  fn warnings(data: &[u8]) -> Vec<HeaderWarning> {
+    let header = Header::parse(data).unwrap();
+    header.validate(data).warnings().to_vec()
+  }
+
+  #[test]
+  fn test_checksums() {
+    let data = rom();
+    assert_eq!(Header::header_checksum(&data), 0xe7);
+    assert_eq!(Header::global_checksum(&data), 0x16ad);
+    // the global checksum is stored big endian
+    assert_eq!(data[0x14e..0x150], [0x16, 0xad]);
+    let header = Header::parse(&data).unwrap();
+    assert_eq!((header.hchk, header.gchk), (0xe7, 0x16ad));
+    assert!(header.validate(&data).is_valid());
+    assert_eq!(header.validate(&data).to_string(), "OK");
+  }
+
+  #[test]
+  fn test_validate() {
+    let mut data = rom();
+    data[0x104] = 0x00;
+    fix_checksums(&mut data);
+    assert_eq!(warnings(&data), [HeaderWarning::LogoMismatch]);
+
+    let mut data = rom();
+    data[0x14d] = 0x00;
+    fix_global_checksum(&mut data);
+    assert_eq!(
+      warnings(&data),
+      [HeaderWarning::HeaderChecksum {
+        expected: 0x00,
+        found: 0xe7
+      }]
+    );
+
+    // swapping the bytes of the global checksum breaks it, as
+    // it's read big endian (unlike the little endian values)
+    let mut data = rom();
+    data.swap(0x14e, 0x14f);
+    assert_eq!(
+      warnings(&data),
+      [HeaderWarning::GlobalChecksum {
+        expected: 0xad16,
+        found: 0x16ad
+      }]
+    );
+
+    let mut data = rom();
+    data.extend_from_slice(&[0x00; 0x4000]);
+    assert_eq!(
+      warnings(&data),
+      [HeaderWarning::RomSizeMismatch {
+        header: 0x8000,
+        file: 0xc000
+      }]
+    );
+
+    let mut data = rom();
+    data[0x147] = 0xfc;
+    fix_checksums(&mut data);
+    assert_eq!(warnings(&data), [HeaderWarning::UnknownCartType(0xfc)]);
+
+    let mut data = rom();
+    data[0x104] = 0x00;
+    data[0x147] = 0xfc;
+    let header = Header::parse(&data).unwrap();
+    let report = header.validate(&data);
+    assert_eq!(report.warnings().len(), 4);
+    assert_eq!(
+      report.to_string(),
+      "Nintendo logo mismatch, Header checksum mismatch: expected 0xe7, found 0xeb, \
+       Global checksum mismatch: expected 0x16ad, found 0x16db, \
+       Unknown cartridge type: 0xfc"
+    );
+  }
+
+  #[test]
+  fn test_policy() {
+    let mut data = rom();
+    data[0x147] = 0xfc;
+    fix_checksums(&mut data);
+    assert!(Cartridge::from_data(&data, LoadPolicy::Strict).is_err());
+    let cart = Cartridge::from_data(&data, LoadPolicy::Lenient).unwrap();
+    assert!(!cart.validation().is_valid());
+    assert_eq!(cart.mbc().ok().map(|mbc| mbc.name), None);
+
+    // the warnings that the boot ROM ignores are refused as well
+    let mut data = rom();
+    data.swap(0x14e, 0x14f);
+    assert!(Cartridge::from_data(&data, LoadPolicy::Strict).is_err());
+    assert!(Cartridge::from_data(&data, LoadPolicy::Lenient).is_ok());
+    assert!(Cartridge::from_data(&rom(), LoadPolicy::Strict).is_ok());
+  }
+}
@@ -1,4 +1,4 @@
-mod header;
+pub mod header;
 mod licensee;
 mod mbc;
 
@@ -11,22 +11,29 @@ use std::{
 use log::debug;
 
 use crate::{
+  cdl::CodeDataLog,
+  cheats::Cheats,
   error::Error,
   generic::{
     address::Address,
     device::Device,
     memory::{Ram, Rom},
+    shared::Shared,
   },
+  romdb::{RomEntry, RomOverrides},
 };
 
 use self::{
-  header::Header,
+  header::{Header, HeaderWarning, ValidationReport},
   mbc::{Mbc, MBC1, NO_MBC},
 };
 
 pub const ROM_BANK_SIZE: usize = 16384;
 pub const RAM_BANK_SIZE: usize = 8192;
 
+/// Size of the largest ROM, as described by the header (8 MB).
+pub const MAX_ROM_SIZE: usize = 512 * ROM_BANK_SIZE;
+
 const LOGO: [u8; 0x30] = [
   0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
   0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
@@ -247,6 +254,20 @@ impl Display for RamSize {
   }
 }
 
+/// How the issues found while validating the header of
+/// a ROM are handled when loading it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LoadPolicy {
+  /// Refuses to load ROMs with any header warning.
+  Strict,
+
+  /// Loads ROMs despite the warnings (eg: homebrew without
+  /// the logo or bad dumps), running unknown cartridge types
+  /// as if they had no MBC.
+  #[default]
+  Lenient,
+}
+
 pub struct Cartridge {
   rom: Rom,
   ram: Ram,
@@ -256,6 +277,15 @@ pub struct Cartridge {
   ram_enabled: bool,
 
   header: Header,
+  validation: ValidationReport,
+
+  /// Game of the ROM database this ROM is a dump of
+  /// and the overrides applied to it (if any).
+  rom_entry: Option<RomEntry>,
+  overrides: RomOverrides,
+
+  cdl: Option<Shared<CodeDataLog>>,
+  cheats: Option<Shared<Cheats>>,
 }
 
 impl Cartridge {
@@ -268,12 +298,28 @@ impl Cartridge {
       rom_offset: 0x4000,
       ram_enabled: false,
       header: Header::default(),
+      validation: ValidationReport::new(),
+      rom_entry: None,
+      overrides: RomOverrides::default(),
+      cdl: None,
+      cheats: None,
     }
   }
 
-  pub fn from_data(data: &[u8]) -> Result<Self, Error> {
+  pub fn from_data(data: &[u8], policy: LoadPolicy) -> Result<Self, Error> {
+    Self::from_data_overrides(data, policy, RomOverrides::default())
+  }
+
+  /// Creates the cartridge with the overrides taking precedence
+  /// over the header, so that the mapper is selected from them.
+  pub fn from_data_overrides(
+    data: &[u8],
+    policy: LoadPolicy,
+    overrides: RomOverrides,
+  ) -> Result<Self, Error> {
     let mut cartridge = Cartridge::new();
-    cartridge.set_data(data)?;
+    cartridge.overrides = overrides;
+    cartridge.set_data(data, policy)?;
     Ok(cartridge)
   }
 
@@ -284,6 +330,9 @@ impl Cartridge {
     self.rom_bank_count = 0;
     self.rom_offset = 0x4000;
     self.ram_enabled = false;
+    self.rom_entry = None;
+    self.overrides = RomOverrides::default();
+    self.cdl = None;
   }
 
   pub fn set_cart_type(&mut self, rom_type: CartType) -> Result<(), Error> {
@@ -348,12 +397,51 @@ impl Cartridge {
     self.rom_offset = rom_bank as usize * ROM_BANK_SIZE;
   }
 
-  fn set_data(&mut self, data: &[u8]) -> Result<(), Error> {
+  /// Offset in the ROM data of the byte currently
+  /// mapped at the address of the ROM area.
+  pub fn rom_addr(&self, addr: u16) -> usize {
+    match addr {
+      0x0000..=0x3fff => addr as usize,
+      _ => self.rom_offset + (addr as usize & 0x3fff),
+    }
+  }
+
+  pub fn cdl(&self) -> Option<&Shared<CodeDataLog>> {
+    self.cdl.as_ref()
+  }
+
+  pub fn set_cdl(&mut self, cdl: Option<Shared<CodeDataLog>>) {
+    self.cdl = cdl;
+  }
+
+  /// Sets the cheats whose Game Genie codes are applied
+  /// to the values read from the ROM.
+  pub fn set_cheats(&mut self, cheats: Option<Shared<Cheats>>) {
+    self.cheats = cheats;
+  }
+
+  fn set_data(&mut self, data: &[u8], policy: LoadPolicy) -> Result<(), Error> {
     self.ensure_data(data)?;
+    let header = Header::parse(data)?;
+    let validation = header.validate(data);
+    // an unknown type in the header is harmless when the type is
+    // overridden, as the one in the header is then never used
+    let overridden = self.overrides.cart.is_some();
+    let valid = validation
+      .warnings()
+      .iter()
+      .all(|warning| overridden && matches!(warning, HeaderWarning::UnknownCartType(_)));
+    if policy == LoadPolicy::Strict && !valid {
+      return Err(Error::CustomError(format!(
+        "Invalid ROM header: {}",
+        validation
+      )));
+    }
     self.rom.set_data(data);
-    self.set_header(Header::parse(data)?);
+    self.set_header(header);
+    self.validation = validation;
     self.rom_offset = 0x4000;
-    self.set_mbc()?;
+    self.set_mbc(policy)?;
     self.set_computed();
     self.set_rom_bank(1);
     Ok(())
@@ -363,13 +451,20 @@ impl Cartridge {
     self.header = header;
   }
 
-  fn set_mbc(&mut self) -> Result<(), Error> {
-    self.mbc = self.mbc()?;
+  fn set_mbc(&mut self, policy: LoadPolicy) -> Result<(), Error> {
+    self.mbc = match self.mbc() {
+      Ok(mbc) => mbc,
+      Err(_) if policy == LoadPolicy::Lenient && self.cart_type() == CartType::Unknown => &NO_MBC,
+      Err(err) => return Err(err),
+    };
     Ok(())
   }
 
   fn set_computed(&mut self) {
-    self.rom_bank_count = self.rom_size().rom_banks();
+    // the bank count is limited to the ROM data available, as
+    // the header size may not match the one of (bad) dumps
+    let file_banks = (self.rom.len() / ROM_BANK_SIZE) as u16;
+    self.rom_bank_count = self.rom_size().rom_banks().min(file_banks);
   }
 
   fn ensure_data(&self, data: &[u8]) -> Result<(), Error> {
@@ -388,11 +483,31 @@ impl Cartridge {
     &self.header
   }
 
+  /// Warnings found while validating the header of the ROM.
+  pub fn validation(&self) -> &ValidationReport {
+    &self.validation
+  }
+
+  pub fn rom_entry(&self) -> Option<&RomEntry> {
+    self.rom_entry.as_ref()
+  }
+
+  pub fn set_rom_entry(&mut self, rom_entry: Option<RomEntry>) {
+    self.rom_entry = rom_entry;
+  }
+
+  pub fn overrides(&self) -> &RomOverrides {
+    &self.overrides
+  }
+
   pub fn title(&self) -> String {
     self.header.title.clone().unwrap_or_default()
   }
 
   pub fn cart_type(&self) -> CartType {
+    if let Some(cart) = self.overrides.cart {
+      return cart;
+    }
     match self.rom.read(0x0147) {
       0x00 => CartType::RomOnly,
       0x01 => CartType::Mbc1,
@@ -454,6 +569,16 @@ impl Cartridge {
     )
   }
 
+  /// If the cartridge has a real time clock, as overridden by the
+  /// ROM database or from the header otherwise. The override is only
+  /// informative for now, as the MBC3 (and so its RTC) is not emulated.
+  pub fn has_rtc(&self) -> bool {
+    self.overrides.rtc.unwrap_or(matches!(
+      self.cart_type(),
+      CartType::Mbc3TimerBattery | CartType::Mbc3TimerRamBattery
+    ))
+  }
+
   pub fn set_ram_data(&mut self, data: &[u8]) {
     self.ram = Ram::from(data)
   }
@@ -471,6 +596,17 @@ impl Cartridge {
   pub fn ram(&self) -> &Ram {
     &self.ram
   }
+
+  /// Writes the external RAM address in the provided bank (bank 0
+  /// otherwise, the only one mapped as the MBCs don't switch RAM
+  /// banks yet) directly, even if the game has the RAM disabled,
+  /// writes beyond the RAM size are ignored.
+  pub fn poke_ram(&mut self, bank: Option<u8>, addr: u16, value: u8) {
+    let offset = bank.unwrap_or(0) as usize * 0x2000 + (addr & 0x1fff) as usize;
+    if let Some(byte) = self.ram.inner_mut().get_mut(offset) {
+      *byte = value;
+    }
+  }
 }
 
 impl Default for Cartridge {
@@ -483,7 +619,14 @@ impl Address for Cartridge {
   fn read(&self, addr: u16) -> u8 {
     match addr & 0xf000 {
       0x0000 | 0x1000 | 0x2000 | 0x3000 | 0x4000 | 0x5000 | 0x6000 | 0x7000 => {
-        (self.mbc.read_rom)(self, addr)
+        if let Some(cdl) = &self.cdl {
+          cdl.borrow_mut().mark(self.rom_addr(addr));
+        }
+        let value = (self.mbc.read_rom)(self, addr);
+        match &self.cheats {
+          Some(cheats) => cheats.borrow().patch_rom(addr, value),
+          None => value,
+        }
       },
       0xa000 | 0xb000 => (self.mbc.read_ram)(self, addr),
       _ => {
@@ -505,3 +648,61 @@ impl Address for Cartridge {
 }
 
 impl Device for Cartridge {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Creates a 32 KB ROM of the cartridge type with a valid header.
+  fn rom(cart_type: u8) -> Vec<u8> {
+    let mut data = vec![0x00; 0x8000];
+    data[0x104..0x134].copy_from_slice(&LOGO);
+    data[0x147] = cart_type;
+    data[0x14d] = Header::header_checksum(&data);
+    let checksum = Header::global_checksum(&data);
+    data[0x14e..0x150].copy_from_slice(&checksum.to_be_bytes());
+    data
+  }
+
+  #[test]
+  fn test_cart_override() {
+    let data = rom(0x00);
+    assert!(Cartridge::from_data(&data, LoadPolicy::Strict).is_ok());
+
+    // an unknown type is refused on strict loading, unless
+    // it's overridden, and run without a MBC on lenient one
+    let data = rom(0xfc);
+    assert!(Cartridge::from_data(&data, LoadPolicy::Strict).is_err());
+    let cart = Cartridge::from_data(&data, LoadPolicy::Lenient).unwrap();
+    assert_eq!(cart.cart_type(), CartType::Unknown);
+    assert_eq!(
+      cart.validation().warnings(),
+      [HeaderWarning::UnknownCartType(0xfc)]
+    );
+
+    let overrides = RomOverrides {
+      cart: Some(CartType::Mbc1),
+      ..Default::default()
+    };
+    let cart = Cartridge::from_data_overrides(&data, LoadPolicy::Strict, overrides).unwrap();
+    assert_eq!(cart.cart_type(), CartType::Mbc1);
+    assert_eq!(cart.mbc().unwrap().name, "MBC1");
+
+    // other warnings are still refused
+    let mut data = rom(0xfc);
+    data[0x104] ^= 0xff;
+    assert!(Cartridge::from_data_overrides(&data, LoadPolicy::Strict, overrides).is_err());
+  }
+
+  #[test]
+  fn test_rtc_override() {
+    let cart = Cartridge::from_data(&rom(0x00), LoadPolicy::Strict).unwrap();
+    assert!(!cart.has_rtc());
+    let overrides = RomOverrides {
+      rtc: Some(true),
+      ..Default::default()
+    };
+    let cart = Cartridge::from_data_overrides(&rom(0x00), LoadPolicy::Strict, overrides).unwrap();
+    assert!(cart.has_rtc());
+  }
+}
@@ -0,0 +1,223 @@
+use crate::{
+  error::Error,
+  util::{read_file, write_file},
+};
+
+/// Byte of the ROM fetched as the opcode of an instruction
+/// (including the second byte of the 0xCB prefixed ones).
+pub const CDL_CODE: u8 = 0x01;
+
+/// Byte of the ROM fetched as an immediate operand of an instruction.
+pub const CDL_OPERAND: u8 = 0x02;
+
+/// Byte of the ROM read as data by an instruction (eg: `LD A, [HL]`).
+pub const CDL_DATA: u8 = 0x04;
+
+const CDL_MAGIC: &[u8; 4] = b"GBCD";
+const CDL_VERSION: u8 = 1;
+const CDL_HEADER_SIZE: usize = 16;
+
+/// Code/data log of the cartridge ROM, keeping for each byte
+/// of the ROM (across all banks) how it has been accessed by
+/// the CPU, only the accesses made while executing instructions
+/// are logged (debugger and DMA reads are not).
+///
+/// The binary format (all values little endian) consists of a
+/// 16 bytes header followed by one flags byte per ROM byte:
+///
+/// | Offset | Size | Description                                   |
+/// |--------|------|-----------------------------------------------|
+/// | 0x00   | 4    | Magic `GBCD`                                  |
+/// | 0x04   | 1    | Version of the format (1)                     |
+/// | 0x05   | 1    | Reserved (0)                                  |
+/// | 0x06   | 2    | Global checksum of the ROM (header 0x014E)    |
+/// | 0x08   | 4    | Size of the ROM in bytes (N)                  |
+/// | 0x0C   | 4    | Reserved (0)                                  |
+/// | 0x10   | N    | Flags (`CDL_CODE`, `CDL_OPERAND`, `CDL_DATA`) |
+pub struct CodeDataLog {
+  flags: Vec<u8>,
+  checksum: u16,
+
+  /// Kind of the accesses currently being made by the
+  /// CPU (one of the `CDL_*` flags, zero when idle).
+  access: u8,
+}
+
+impl CodeDataLog {
+  pub fn new(rom_size: usize, checksum: u16) -> Self {
+    Self {
+      flags: vec![0x00; rom_size],
+      checksum,
+      access: 0x00,
+    }
+  }
+
+  pub fn from_data(data: &[u8]) -> Result<Self, Error> {
+    if data.len() < CDL_HEADER_SIZE || &data[0..4] != CDL_MAGIC {
+      return Err(Error::CustomError(String::from("Invalid CDL file")));
+    }
+    if data[4] != CDL_VERSION {
+      return Err(Error::CustomError(format!(
+        "Unsupported CDL version {}",
+        data[4]
+      )));
+    }
+    let checksum = u16::from_le_bytes([data[6], data[7]]);
+    let size = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
+    if data.len() != CDL_HEADER_SIZE + size {
+      return Err(Error::CustomError(String::from("Invalid CDL file size")));
+    }
+    Ok(Self {
+      flags: data[CDL_HEADER_SIZE..].to_vec(),
+      checksum,
+      access: 0x00,
+    })
+  }
+
+  pub fn from_file(path: &str) -> Result<Self, Error> {
+    Self::from_data(&read_file(path)?)
+  }
+
+  pub fn to_data(&self) -> Vec<u8> {
+    let mut data = Vec::with_capacity(CDL_HEADER_SIZE + self.flags.len());
+    data.extend_from_slice(CDL_MAGIC);
+    data.extend_from_slice(&[CDL_VERSION, 0x00]);
+    data.extend_from_slice(&self.checksum.to_le_bytes());
+    data.extend_from_slice(&(self.flags.len() as u32).to_le_bytes());
+    data.extend_from_slice(&[0x00; 4]);
+    data.extend_from_slice(&self.flags);
+    data
+  }
+
+  pub fn save_file(&self, path: &str) -> Result<(), Error> {
+    write_file(path, &self.to_data())
+  }
+
+  /// Checks if the log was recorded for a ROM with the
+  /// provided size and global checksum.
+  pub fn matches(&self, rom_size: usize, checksum: u16) -> bool {
+    self.flags.len() == rom_size && self.checksum == checksum
+  }
+
+  pub fn flags(&self) -> &[u8] {
+    &self.flags
+  }
+
+  pub fn flag(&self, offset: usize) -> u8 {
+    self.flags.get(offset).copied().unwrap_or(0x00)
+  }
+
+  pub fn access(&self) -> u8 {
+    self.access
+  }
+
+  pub fn set_access(&mut self, access: u8) {
+    self.access = access;
+  }
+
+  /// Logs a read of the byte at the offset of the ROM, using
+  /// the kind of the access currently being made by the CPU.
+  pub fn mark(&mut self, offset: usize) {
+    if let Some(flags) = self.flags.get_mut(offset) {
+      *flags |= self.access;
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.flags.fill(0x00);
+  }
+
+  /// Number of ROM bytes that have any of the flags set.
+  pub fn count(&self, flags: u8) -> usize {
+    self
+      .flags
+      .iter()
+      .filter(|&&value| value & flags != 0)
+      .count()
+  }
+
+  /// Fraction of the ROM that has been accessed in any way.
+  pub fn coverage(&self) -> f32 {
+    if self.flags.is_empty() {
+      return 0.0;
+    }
+    self.count(CDL_CODE | CDL_OPERAND | CDL_DATA) as f32 / self.flags.len() as f32
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cdl() -> CodeDataLog {
+    let mut cdl = CodeDataLog::new(8, 0xbeef);
+    cdl.set_access(CDL_CODE);
+    cdl.mark(0);
+    cdl.set_access(CDL_OPERAND);
+    cdl.mark(1);
+    cdl.mark(2);
+    cdl.set_access(CDL_DATA);
+    cdl.mark(2);
+    cdl.mark(7);
+    cdl.mark(8);
+    cdl.set_access(0x00);
+    cdl.mark(3);
+    cdl
+  }
+
+  #[test]
+  fn test_mark() {
+    let cdl = cdl();
+    assert_eq!(
+      cdl.flags(),
+      &[
+        CDL_CODE,
+        CDL_OPERAND,
+        CDL_OPERAND | CDL_DATA,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        CDL_DATA
+      ]
+    );
+    assert_eq!(cdl.flag(8), 0x00);
+    assert_eq!(cdl.count(CDL_CODE), 1);
+    assert_eq!(cdl.count(CDL_DATA), 2);
+    assert_eq!(cdl.count(CDL_OPERAND | CDL_DATA), 3);
+    assert_eq!(cdl.coverage(), 0.5);
+    assert_eq!(CodeDataLog::new(0, 0).coverage(), 0.0);
+  }
+
+  #[test]
+  fn test_data() {
+    let data = cdl().to_data();
+    assert_eq!(data.len(), CDL_HEADER_SIZE + 8);
+    assert_eq!(&data[0..12], b"GBCD\x01\x00\xef\xbe\x08\x00\x00\x00");
+
+    let cdl = CodeDataLog::from_data(&data).unwrap();
+    assert!(cdl.matches(8, 0xbeef));
+    assert!(!cdl.matches(8, 0xbeee));
+    assert!(!cdl.matches(16, 0xbeef));
+    assert_eq!(cdl.to_data(), data);
+  }
+
+  #[test]
+  fn test_data_invalid() {
+    let data = cdl().to_data();
+    assert!(CodeDataLog::from_data(&data[..CDL_HEADER_SIZE - 1]).is_err());
+    assert!(CodeDataLog::from_data(&data[..data.len() - 1]).is_err());
+
+    let mut extra = data.clone();
+    extra.push(0x00);
+    assert!(CodeDataLog::from_data(&extra).is_err());
+
+    let mut magic = data.clone();
+    magic[0] = b'X';
+    assert!(CodeDataLog::from_data(&magic).is_err());
+
+    let mut version = data;
+    version[4] = 2;
+    assert!(CodeDataLog::from_data(&version).is_err());
+  }
+}
@@ -0,0 +1,419 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::{
+  error::Error,
+  util::{read_file, write_file},
+};
+
+/// Effect of a cheat code on the system.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheatKind {
+  /// Game Genie code, replaces the value read from the ROM at the
+  /// address, only when the original value matches the compare
+  /// byte (in case there's one).
+  GameGenie {
+    addr: u16,
+    value: u8,
+    compare: Option<u8>,
+  },
+
+  /// GameShark code, writes the value into the RAM address
+  /// once per frame (at the start of VBlank), the bank being
+  /// the code type, with `8X`/`9X` selecting the RAM bank `X`.
+  GameShark { bank: u8, addr: u16, value: u8 },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cheat {
+  code: String,
+  name: String,
+  kind: CheatKind,
+  enabled: bool,
+}
+
+impl Cheat {
+  /// Parses either a Game Genie (`ABC-DEF` or `ABC-DEF-GHI`)
+  /// or a GameShark (`ABCDEFGH`) code.
+  pub fn parse(code: &str, name: &str) -> Result<Self, Error> {
+    let code = code.trim().to_uppercase();
+    let digits: Vec<u8> = code
+      .chars()
+      .filter(|&c| c != '-')
+      .map(|c| c.to_digit(16).map(|digit| digit as u8))
+      .collect::<Option<Vec<u8>>>()
+      .ok_or_else(|| Error::CustomError(format!("Invalid cheat code: {}", code)))?;
+
+    let kind = match (digits.len(), code.contains('-')) {
+      (6, true) | (9, true) => Self::parse_game_genie(&digits),
+      (8, false) => Self::parse_game_shark(&digits),
+      _ => None,
+    }
+    .ok_or_else(|| Error::CustomError(format!("Invalid cheat code: {}", code)))?;
+
+    Ok(Self {
+      code,
+      name: String::from(name.trim()),
+      kind,
+      enabled: true,
+    })
+  }
+
+  /// Decodes a Game Genie code, where (for `ABC-DEF-GHI`) `AB` is
+  /// the new value, `FCDE` the address with its top nibble XORed
+  /// with 0xF and `GI` the compare byte XORed with 0xBA and rotated
+  /// left by two (`H` is not used).
+  fn parse_game_genie(digits: &[u8]) -> Option<CheatKind> {
+    let value = digits[0] << 4 | digits[1];
+    let addr = ((digits[5] ^ 0xf) as u16) << 12
+      | (digits[2] as u16) << 8
+      | (digits[3] as u16) << 4
+      | digits[4] as u16;
+    if addr >= 0x8000 {
+      return None;
+    }
+    let compare = if digits.len() == 9 {
+      Some((digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xba)
+    } else {
+      None
+    };
+    Some(CheatKind::GameGenie {
+      addr,
+      value,
+      compare,
+    })
+  }
+
+  /// Decodes a GameShark code, where (for `ABCDEFGH`) `AB` is the
+  /// RAM bank, `CD` the new value and `GHEF` the address.
+  fn parse_game_shark(digits: &[u8]) -> Option<CheatKind> {
+    let bank = digits[0] << 4 | digits[1];
+    let value = digits[2] << 4 | digits[3];
+    let addr = (digits[6] as u16) << 12
+      | (digits[7] as u16) << 8
+      | (digits[4] as u16) << 4
+      | digits[5] as u16;
+    // the ROM can't be written, writes there would instead
+    // be interpreted as MBC register writes
+    if addr < 0x8000 {
+      return None;
+    }
+    Some(CheatKind::GameShark { bank, addr, value })
+  }
+
+  pub fn code(&self) -> &str {
+    &self.code
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn kind(&self) -> CheatKind {
+    self.kind
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  pub fn set_enabled(&mut self, value: bool) {
+    self.enabled = value;
+  }
+}
+
+impl Display for Cheat {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}{} {}",
+      if self.enabled { "" } else { "-" },
+      self.code,
+      self.name
+    )
+  }
+}
+
+/// Set of the cheat codes of a ROM.
+///
+/// The text format has one code per line optionally followed
+/// by its description, codes prefixed with `-` are disabled
+/// and anything after a `;` is a comment, eg:
+///
+/// ```text
+/// ; Infinite lives
+/// 00A-17B-C49 Lives
+/// -010138CD Max health
+/// ```
+#[derive(Clone, Default)]
+pub struct Cheats {
+  cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn from_text(text: &str) -> Result<Self, Error> {
+    let mut cheats = Self::new();
+    for line in text.lines() {
+      let line = line.split(';').next().unwrap_or("").trim();
+      if line.is_empty() {
+        continue;
+      }
+      let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+      let (code, enabled) = match code.strip_prefix('-') {
+        Some(code) => (code, false),
+        None => (code, true),
+      };
+      let mut cheat = Cheat::parse(code, name)?;
+      cheat.set_enabled(enabled);
+      cheats.cheats.push(cheat);
+    }
+    Ok(cheats)
+  }
+
+  pub fn from_file(path: &str) -> Result<Self, Error> {
+    let data = read_file(path)?;
+    let text = String::from_utf8(data)
+      .map_err(|_| Error::CustomError(format!("Invalid cheats file: {}", path)))?;
+    Self::from_text(&text)
+  }
+
+  pub fn to_text(&self) -> String {
+    self
+      .cheats
+      .iter()
+      .map(|cheat| format!("{}\n", cheat))
+      .collect()
+  }
+
+  pub fn save_file(&self, path: &str) -> Result<(), Error> {
+    write_file(path, self.to_text().as_bytes())
+  }
+
+  pub fn cheats(&self) -> &[Cheat] {
+    &self.cheats
+  }
+
+  pub fn len(&self) -> usize {
+    self.cheats.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.cheats.is_empty()
+  }
+
+  /// Adds a new (enabled) code, returning its index.
+  pub fn add(&mut self, code: &str, name: &str) -> Result<usize, Error> {
+    self.cheats.push(Cheat::parse(code, name)?);
+    Ok(self.cheats.len() - 1)
+  }
+
+  pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+    if index >= self.cheats.len() {
+      return None;
+    }
+    Some(self.cheats.remove(index))
+  }
+
+  pub fn clear(&mut self) {
+    self.cheats.clear();
+  }
+
+  pub fn set_enabled(&mut self, index: usize, value: bool) {
+    if let Some(cheat) = self.cheats.get_mut(index) {
+      cheat.set_enabled(value);
+    }
+  }
+
+  /// Toggles the code, returning if it's now enabled.
+  pub fn toggle(&mut self, index: usize) -> bool {
+    match self.cheats.get_mut(index) {
+      Some(cheat) => {
+        cheat.set_enabled(!cheat.enabled());
+        cheat.enabled()
+      },
+      None => false,
+    }
+  }
+
+  /// Applies the enabled Game Genie codes to the value read
+  /// from the ROM at the address.
+  pub fn patch_rom(&self, addr: u16, value: u8) -> u8 {
+    for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+      if let CheatKind::GameGenie {
+        addr: cheat_addr,
+        value: cheat_value,
+        compare,
+      } = cheat.kind
+      {
+        if cheat_addr == addr && compare.is_none_or(|compare| compare == value) {
+          return cheat_value;
+        }
+      }
+    }
+    value
+  }
+
+  /// Bank, address and value of the writes of the enabled GameShark
+  /// codes, without a bank for the codes meant for the mapped one.
+  pub fn ram_writes(&self) -> Vec<(Option<u8>, u16, u8)> {
+    self
+      .cheats
+      .iter()
+      .filter(|cheat| cheat.enabled)
+      .filter_map(|cheat| match cheat.kind {
+        CheatKind::GameShark { bank, addr, value } => {
+          let bank = match bank & 0xf0 {
+            0x80 | 0x90 => Some(bank & 0x0f),
+            _ => None,
+          };
+          Some((bank, addr, value))
+        },
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Encodes a Game Genie code, the inverse of the decoding.
+  fn game_genie(addr: u16, value: u8, compare: Option<u8>) -> String {
+    let mut code = format!(
+      "{:02X}{:X}-{:02X}{:X}",
+      value,
+      (addr >> 8) & 0xf,
+      addr & 0xff,
+      (addr >> 12) ^ 0xf
+    );
+    if let Some(compare) = compare {
+      let compare = (compare ^ 0xba).rotate_left(2);
+      code.push_str(&format!("-{:X}0{:X}", compare >> 4, compare & 0xf));
+    }
+    code
+  }
+
+  #[test]
+  fn test_game_genie() {
+    let cheat = Cheat::parse("00a-17b-c49", "Lives").unwrap();
+    assert_eq!(cheat.code(), "00A-17B-C49");
+    assert_eq!(cheat.name(), "Lives");
+    assert_eq!(
+      cheat.kind(),
+      CheatKind::GameGenie {
+        addr: 0x4a17,
+        value: 0x00,
+        compare: Some(0xc8),
+      }
+    );
+
+    for (addr, value, compare) in [
+      (0x0000, 0xff, None),
+      (0x4a17, 0x3c, Some(0x00)),
+      (0x7fff, 0x01, Some(0xba)),
+      (0x1234, 0x80, Some(0x45)),
+    ] {
+      let code = game_genie(addr, value, compare);
+      assert_eq!(
+        Cheat::parse(&code, "").unwrap().kind(),
+        CheatKind::GameGenie {
+          addr,
+          value,
+          compare,
+        },
+        "{}",
+        code
+      );
+    }
+  }
+
+  #[test]
+  fn test_game_genie_compare() {
+    let mut cheats = Cheats::new();
+    cheats
+      .add(&game_genie(0x4a17, 0x63, Some(0x05)), "")
+      .unwrap();
+    cheats.add(&game_genie(0x0150, 0x00, None), "").unwrap();
+    assert_eq!(cheats.patch_rom(0x4a17, 0x05), 0x63);
+    assert_eq!(cheats.patch_rom(0x4a17, 0x06), 0x06);
+    assert_eq!(cheats.patch_rom(0x4a18, 0x05), 0x05);
+    assert_eq!(cheats.patch_rom(0x0150, 0xc3), 0x00);
+
+    cheats.set_enabled(0, false);
+    assert_eq!(cheats.patch_rom(0x4a17, 0x05), 0x05);
+  }
+
+  #[test]
+  fn test_game_shark() {
+    let cheat = Cheat::parse("91FF38DA", "").unwrap();
+    assert_eq!(
+      cheat.kind(),
+      CheatKind::GameShark {
+        bank: 0x91,
+        addr: 0xda38,
+        value: 0xff,
+      }
+    );
+
+    let mut cheats = Cheats::new();
+    cheats.add("91FF38DA", "").unwrap();
+    cheats.add("0163D0C0", "").unwrap();
+    cheats.add("8A0100A0", "").unwrap();
+    cheats.add("01020080", "").unwrap();
+    cheats.set_enabled(3, false);
+    assert_eq!(
+      cheats.ram_writes(),
+      vec![
+        (Some(1), 0xda38, 0xff),
+        (None, 0xc0d0, 0x63),
+        (Some(10), 0xa000, 0x01),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_invalid() {
+    for code in [
+      "",
+      "00A-17",
+      "00A-17B-C4",
+      "00A17BC49",
+      "0GA-17B",
+      // Game Genie codes out of the ROM
+      "00A-171",
+      "00A-170-C49",
+      // GameShark codes in the ROM
+      "01013841",
+      "01-013841",
+      "0101384",
+    ] {
+      assert!(Cheat::parse(code, "").is_err(), "{}", code);
+    }
+  }
+
+  #[test]
+  fn test_text() {
+    let text = "; Infinite lives\n00A-17B-C49 Lives\n\n-010138CD  Max health ; full\n";
+    let cheats = Cheats::from_text(text).unwrap();
+    assert_eq!(cheats.len(), 2);
+    assert_eq!(cheats.cheats()[0].name(), "Lives");
+    assert!(cheats.cheats()[0].enabled());
+    assert_eq!(cheats.cheats()[1].code(), "010138CD");
+    assert_eq!(cheats.cheats()[1].name(), "Max health");
+    assert!(!cheats.cheats()[1].enabled());
+    assert_eq!(
+      cheats.to_text(),
+      "00A-17B-C49 Lives\n-010138CD Max health\n"
+    );
+    assert_eq!(
+      Cheats::from_text(&cheats.to_text()).unwrap().cheats(),
+      cheats.cheats()
+    );
+
+    assert!(Cheats::from_text("00A-17B-C49 Lives\nXYZ Bad").is_err());
+  }
+}
@@ -1,17 +1,32 @@
 use std::cell::{Ref, RefMut};
 
+use log::{info, warn};
+
 use crate::{
+  archive::read_rom_file,
   boot_data::DMG_BOOT,
   bus::Bus,
-  cartridge::{Cartridge, RamSize},
+  cartridge::{Cartridge, LoadPolicy, RamSize},
+  cdl::CodeDataLog,
+  cheats::Cheats,
   error::Error,
   generic::{memory::Ram, shared::Shared},
-  pad::{Pad, PadKey},
+  memory_map::{MemoryRegion, RegionInfo},
+  model::{Model, CGB_BOOT_SIZE, DMG_BOOT_SIZE},
+  movie::{Movie, MovieMode, MovieSession},
+  pad::{Pad, PadKey, MAX_PLAYERS},
+  patch::{apply_patch_file, checksum_report, find_patch},
+  profiler::Profiler,
+  romdb::{sha1_hex, DumpStatus, RomDatabase, RomOverrides},
+  sgb::{Sgb, SGB_HEIGHT, SGB_WIDTH},
   soc::{
-    ppu::{Ppu, DISPLAY_HEIGHT, DISPLAY_WIDTH},
+    cpu::Cpu,
+    ppu::{Ppu, PpuMode, DISPLAY_HEIGHT, DISPLAY_WIDTH},
     Soc,
   },
-  util::read_file,
+  symbols::SymbolTable,
+  trace::TraceHook,
+  util::{crc32, read_file},
 };
 
 // TODO: impl const
@@ -24,14 +39,37 @@ pub struct GameBoy {
   cart: Shared<Cartridge>,
   wram: Shared<Ram>,
   bus: Shared<Bus>,
+  symbols: SymbolTable,
+  cdl: Option<Shared<CodeDataLog>>,
+  cheats: Shared<Cheats>,
+  load_policy: LoadPolicy,
+  rom_db: Option<RomDatabase>,
+  /// Model being emulated, which is the configured one unless
+  /// the ROM database forces the loaded cartridge to another.
+  model: Model,
+  base_model: Model,
+  /// Model of the movie being played, which takes precedence
+  /// over the other ones until the playback ends.
+  movie_model: Option<Model>,
+
+  /// Boot ROM loaded from file, the embedded DMG one
+  /// is used when there's none.
+  boot_rom: Option<Vec<u8>>,
+  skip_boot: bool,
+
+  /// SGB hardware, only present for the SGB models.
+  sgb: Option<Shared<Sgb>>,
+  /// If the SGB frames include the border (256x224).
+  sgb_border: bool,
+
+  movie: Option<MovieSession>,
 }
 
 impl GameBoy {
   pub const CPU_FREQ: u32 = 4194304;
   pub const VISUAL_FREQ: f32 = 59.7275;
 
-  // TODO: impl new
-  pub fn new() -> Self {
+  pub fn new(model: Model) -> Self {
     let bus = Shared::new(Bus::new());
     let soc = Soc::new(bus.clone());
 
@@ -40,22 +78,46 @@ impl GameBoy {
     bus.borrow_mut().set_wram(wram.clone());
     bus.borrow_mut().set_pad(pad.clone());
 
-    Self {
+    let mut game_boy = Self {
       soc,
       pad,
       cart: Shared::new(Cartridge::default()),
       wram,
       bus,
-    }
+      symbols: SymbolTable::default(),
+      cdl: None,
+      cheats: Shared::new(Cheats::default()),
+      load_policy: LoadPolicy::default(),
+      rom_db: None,
+      model,
+      base_model: model,
+      movie_model: None,
+      boot_rom: None,
+      skip_boot: false,
+      sgb: None,
+      sgb_border: true,
+      movie: None,
+    };
+    game_boy.set_model(model);
+    game_boy
   }
 
   pub fn reset(&mut self) {
     self.soc.reset();
     self.cart.borrow_mut().reset();
+    self.pad.borrow_mut().reset();
   }
 }
 
 impl GameBoy {
+  pub fn cpu(&self) -> &Cpu {
+    self.soc.cpu()
+  }
+
+  pub fn cpu_mut(&mut self) -> &mut Cpu {
+    self.soc.cpu_mut()
+  }
+
   pub fn ppu(&self) -> Ref<Ppu> {
     self.soc.ppu()
   }
@@ -82,20 +144,277 @@ impl GameBoy {
 }
 
 impl GameBoy {
+  /// Number of CPU cycles executed since the last reset.
+  pub fn cycles(&self) -> u64 {
+    self.soc.cycles()
+  }
+
+  /// Installs a hook called for every access made through the
+  /// bus, the hook must not access the system itself.
+  pub fn set_bus_trace(&mut self, hook: TraceHook) {
+    self.bus.borrow_mut().set_trace(hook);
+  }
+
+  pub fn clear_bus_trace(&mut self) {
+    self.bus.borrow_mut().clear_trace();
+  }
+
+  /// Enumerates the regions currently mapped in the address space.
+  pub fn memory_regions(&self) -> Vec<RegionInfo> {
+    self.bus.borrow().regions()
+  }
+
+  pub fn profiler(&self) -> Option<&Profiler> {
+    self.soc.profiler()
+  }
+
+  pub fn start_profiler(&mut self) {
+    self.soc.start_profiler();
+  }
+
+  pub fn stop_profiler(&mut self) -> Option<Profiler> {
+    self.soc.stop_profiler()
+  }
+
+  pub fn cdl(&self) -> Option<Ref<'_, CodeDataLog>> {
+    self.cdl.as_ref().map(|cdl| cdl.borrow())
+  }
+
+  /// Starts logging the accesses to the ROM of the loaded
+  /// cartridge, replacing any previous log.
+  pub fn start_cdl(&mut self) {
+    let cdl = {
+      let cart = self.cart();
+      CodeDataLog::new(cart.rom().len(), cart.header().gchk)
+    };
+    self.cdl = Some(Shared::new(cdl));
+    self.attach_cdl();
+  }
+
+  /// Continues logging into the provided log, which must have
+  /// been recorded for the cartridge currently loaded.
+  pub fn set_cdl(&mut self, cdl: CodeDataLog) -> Result<(), Error> {
+    let matches = {
+      let cart = self.cart();
+      cdl.matches(cart.rom().len(), cart.header().gchk)
+    };
+    if !matches {
+      return Err(Error::CustomError(String::from(
+        "CDL does not match the loaded ROM",
+      )));
+    }
+    self.cdl = Some(Shared::new(cdl));
+    self.attach_cdl();
+    Ok(())
+  }
+
+  pub fn stop_cdl(&mut self) {
+    self.cdl = None;
+    self.attach_cdl();
+  }
+
+  pub fn load_cdl_file(&mut self, path: &str) -> Result<(), Error> {
+    self.set_cdl(CodeDataLog::from_file(path)?)
+  }
+
+  pub fn save_cdl_file(&self, path: &str) -> Result<(), Error> {
+    match self.cdl() {
+      Some(cdl) => cdl.save_file(path),
+      None => Err(Error::CustomError(String::from("No CDL being recorded"))),
+    }
+  }
+
+  fn attach_cdl(&mut self) {
+    let cdl = self.cdl.clone();
+    self.cart_mut().set_cdl(cdl.clone());
+    self.cpu_mut().set_cdl(cdl);
+  }
+
+  pub fn cheats(&self) -> Ref<'_, Cheats> {
+    self.cheats.borrow()
+  }
+
+  pub fn cheats_mut(&mut self) -> RefMut<'_, Cheats> {
+    self.cheats.borrow_mut()
+  }
+
+  pub fn set_cheats(&mut self, cheats: Cheats) {
+    *self.cheats.borrow_mut() = cheats;
+  }
+
+  pub fn load_cheats_file(&mut self, path: &str) -> Result<(), Error> {
+    self.set_cheats(Cheats::from_file(path)?);
+    Ok(())
+  }
+
+  /// Applies the writes of the enabled GameShark codes, directly
+  /// into the RAM bank targeted by each of them.
+  fn apply_cheats(&mut self) {
+    let writes = self.cheats.borrow().ram_writes();
+    for (bank, addr, value) in writes {
+      match addr {
+        0xa000..=0xbfff => self.cart_mut().poke_ram(bank, addr, value),
+        0xc000..=0xfdff => self.bus.borrow_mut().poke_wram(bank, addr, value),
+        _ => self.bus.borrow_mut().poke(addr, value),
+      }
+    }
+  }
+
+  pub fn load_policy(&self) -> LoadPolicy {
+    self.load_policy
+  }
+
+  /// Sets how the header warnings of the ROMs loaded
+  /// from now on are handled (lenient by default).
+  pub fn set_load_policy(&mut self, policy: LoadPolicy) {
+    self.load_policy = policy;
+  }
+
+  pub fn rom_db(&self) -> Option<&RomDatabase> {
+    self.rom_db.as_ref()
+  }
+
+  /// Sets the database used to identify the ROMs loaded from
+  /// now on and to apply their overrides.
+  pub fn set_rom_db(&mut self, rom_db: Option<RomDatabase>) {
+    self.rom_db = rom_db;
+  }
+
+  pub fn load_rom_db_file(&mut self, path: &str) -> Result<(), Error> {
+    self.rom_db = Some(RomDatabase::from_file(path)?);
+    Ok(())
+  }
+
+  pub fn symbols(&self) -> &SymbolTable {
+    &self.symbols
+  }
+
+  pub fn set_symbols(&mut self, symbols: SymbolTable) {
+    self.symbols = symbols;
+  }
+
+  pub fn load_symbols_file(&mut self, path: &str) -> Result<(), Error> {
+    self.symbols = SymbolTable::from_file(path)?;
+    Ok(())
+  }
+
+  /// Label of the address using the loaded symbols, the ROM
+  /// bank currently mapped is used for the switchable area.
+  pub fn label(&self, addr: u16) -> Option<String> {
+    let bank = self.symbol_bank(addr)?;
+    self.symbols.label(bank, addr)
+  }
+
+  /// Name of the symbol defined exactly at the address.
+  pub fn symbol_name(&self, addr: u16) -> Option<&str> {
+    let bank = self.symbol_bank(addr)?;
+    self.symbols.name(bank, addr)
+  }
+
+  /// Address of the symbol with the name, note that for banked
+  /// symbols the bank is not required to be mapped.
+  pub fn resolve_symbol(&self, name: &str) -> Option<u16> {
+    self.symbols.resolve(name).map(|(_, addr)| addr)
+  }
+
+  /// Disassembles the instruction at the address, replacing the
+  /// addresses that have a symbol defined by their names.
+  pub fn disassemble(&self, addr: u16) -> (String, u16) {
+    let (mut text, length) = self.cpu().disassemble(addr);
+    if let Some(dollar) = text.find('$') {
+      let end = text[dollar + 1..]
+        .find(|c: char| !c.is_ascii_hexdigit())
+        .map_or(text.len(), |digits| dollar + 1 + digits);
+      let value = u16::from_str_radix(&text[dollar + 1..end], 16).ok();
+      // high RAM accesses (eg: LD [FF00+u8], A) are named as a whole
+      let (start, operand) = match (end - dollar - 1, value) {
+        (4, Some(value)) => (dollar, Some(value)),
+        (2, Some(value)) if text[..dollar].ends_with("FF00+") => (dollar - 5, Some(0xff00 | value)),
+        _ => (dollar, None),
+      };
+      if let Some(name) = operand.and_then(|operand| self.symbol_name(operand)) {
+        text.replace_range(start..end, name);
+      }
+    } else if text.starts_with("JR") {
+      // relative jumps are displayed with the name of their target
+      if let Some(start) = text.rfind(['+', '-']) {
+        let target = text[start..]
+          .parse::<i8>()
+          .ok()
+          .map(|offset| addr.wrapping_add(length).wrapping_add(offset as u16));
+        if let Some(name) = target.and_then(|target| self.symbol_name(target)) {
+          text.replace_range(start.., name);
+        }
+      }
+    }
+    (text, length)
+  }
+
+  fn symbol_bank(&self, addr: u16) -> Option<u16> {
+    // the boot ROM is not part of the cartridge, so no
+    // symbols are resolved while it's mapped
+    if self.bus.borrow().region(addr) == MemoryRegion::Boot {
+      return None;
+    }
+    Some(SymbolTable::bank_for(addr, self.cart().rom_bank()))
+  }
+
   pub fn ppu_frame(&self) -> u16 {
     self.ppu().frame_index()
   }
 
   pub fn display_width(&self) -> usize {
-    DISPLAY_WIDTH
+    if self.sgb.is_some() && self.sgb_border {
+      SGB_WIDTH
+    } else {
+      DISPLAY_WIDTH
+    }
   }
 
   pub fn display_height(&self) -> usize {
-    DISPLAY_HEIGHT
+    if self.sgb.is_some() && self.sgb_border {
+      SGB_HEIGHT
+    } else {
+      DISPLAY_HEIGHT
+    }
+  }
+
+  /// RGB frame to be displayed, with `display_width()` and
+  /// `display_height()` dimensions, which for the SGB models
+  /// is the colorized (and bordered) PPU frame.
+  pub fn frame_buffer(&mut self) -> Ref<'_, [u8]> {
+    match &self.sgb {
+      Some(sgb) => {
+        sgb
+          .borrow_mut()
+          .render(self.soc.ppu().shade_buffer(), self.sgb_border);
+        Ref::map(sgb.borrow(), |sgb| sgb.frame_buffer())
+      },
+      None => {
+        self.soc.ppu_mut().frame_buffer();
+        Ref::map(self.soc.ppu(), |ppu| ppu.rgb_buffer().as_slice())
+      },
+    }
+  }
+
+  pub fn sgb(&self) -> Option<Ref<'_, Sgb>> {
+    self.sgb.as_ref().map(|sgb| sgb.borrow())
+  }
+
+  pub fn sgb_border(&self) -> bool {
+    self.sgb_border
+  }
+
+  pub fn set_sgb_border(&mut self, value: bool) {
+    self.sgb_border = value;
   }
 
   pub fn ram_size(&self) -> RamSize {
-    RamSize::Size8K
+    if self.model.is_cgb() {
+      RamSize::Size32K
+    } else {
+      RamSize::Size8K
+    }
   }
 
   pub fn vram_size(&self) -> RamSize {
@@ -106,11 +425,30 @@ impl GameBoy {
 impl GameBoy {
   pub fn clock(&mut self) -> u16 {
     let cycles = self.clock_cpu() as u16;
+    let vblank = self.ppu().mode() == PpuMode::VBlank;
     self.clock_ppu(cycles);
     self.clock_dma(cycles);
+    // the RAM cheats are applied once per frame, when entering
+    // VBlank, as done by the GameShark hardware
+    if !vblank && self.ppu().mode() == PpuMode::VBlank {
+      self.apply_cheats();
+      self.sgb_transfer();
+      self.movie_frame();
+    }
     cycles
   }
 
+  /// Makes the VRAM transfer requested by a SGB command,
+  /// which takes place on the frame following the command.
+  fn sgb_transfer(&mut self) {
+    if let Some(sgb) = &self.sgb {
+      if sgb.borrow().pending_transfer() {
+        let data = self.soc.ppu().screen_tile_data();
+        sgb.borrow_mut().vram_transfer(&data);
+      }
+    }
+  }
+
   fn clock_cpu(&mut self) -> u8 {
     self.soc.clock_cpu()
   }
@@ -123,21 +461,289 @@ impl GameBoy {
     self.soc.clock_dma(cycles)
   }
 
+  /// Toggles the visibility of one of the PPU debug
+  /// layers (`LAYER_*`).
+  pub fn toggle_layer(&mut self, layer: u8) {
+    self.ppu_mut().toggle_layer(layer);
+  }
+
+  pub fn set_object_hidden(&mut self, index: usize, value: bool) {
+    self.ppu_mut().set_object_hidden(index, value);
+  }
+
+  pub fn show_all_layers(&mut self) {
+    self.ppu_mut().show_all();
+  }
+
   pub fn key_press(&mut self, key: PadKey) {
-    self.pad.borrow_mut().key_press(key);
+    self.key_press_player(0, key);
   }
 
   pub fn key_lift(&mut self, key: PadKey) {
-    self.pad.borrow_mut().key_lift(key);
+    self.key_lift_player(0, key);
+  }
+
+  /// Presses the key of one of the (up to four) controllers,
+  /// the ones other than the first are only read by SGB
+  /// multiplayer games (through MLT_REQ).
+  ///
+  /// While a movie is being recorded the key is only pressed
+  /// at the start of the next frame, and while one is being
+  /// played the key is ignored.
+  pub fn key_press_player(&mut self, player: usize, key: PadKey) {
+    match &mut self.movie {
+      Some(movie) if movie.mode() == MovieMode::Recording => movie.set_key(player, key, true),
+      Some(_) => (),
+      None => self.pad.borrow_mut().key_press_player(player, key),
+    }
+  }
+
+  pub fn key_lift_player(&mut self, player: usize, key: PadKey) {
+    match &mut self.movie {
+      Some(movie) if movie.mode() == MovieMode::Recording => movie.set_key(player, key, false),
+      Some(_) => (),
+      None => self.pad.borrow_mut().key_lift_player(player, key),
+    }
   }
 
-  pub fn load_dmg(&mut self) {
-    self.load_boot(&DMG_BOOT);
-    self.bus.borrow_mut().allocate_dmg();
+  pub fn pad(&self) -> Ref<'_, Pad> {
+    self.pad.borrow()
   }
 
-  fn load_boot(&mut self, data: &[u8]) {
-    self.bus.borrow_mut().write_boot(data);
+  /// Hash of the emulation state (CPU registers, cycle count and
+  /// the video, work and high RAM), used to detect the desyncs
+  /// of the movies being played.
+  pub fn state_hash(&self) -> u32 {
+    let cpu = self.cpu();
+    let mut data = vec![];
+    for value in [cpu.pc(), cpu.sp(), cpu.af(), cpu.bc(), cpu.de(), cpu.hl()] {
+      data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(&self.cycles().to_le_bytes());
+    let bus = self.bus.borrow();
+    for range in [0x8000..=0x9fff, 0xc000..=0xdfff, 0xff80..=0xfffe] {
+      data.extend(range.map(|addr| bus.peek(addr)));
+    }
+    crc32(&data)
+  }
+
+  pub fn movie(&self) -> Option<&MovieSession> {
+    self.movie.as_ref()
+  }
+
+  /// Starts recording a movie of the loaded cartridge, which
+  /// is power cycled so that the movie starts from power on.
+  pub fn record_movie(&mut self, players: usize) -> Result<(), Error> {
+    let sha1 = sha1_hex(self.cart().rom().inner());
+    let movie = Movie::new(&sha1, self.model, players);
+    self.power_cycle()?;
+    self.movie = Some(MovieSession::new(movie, MovieMode::Recording));
+    Ok(())
+  }
+
+  /// Plays the movie, recorded for the loaded cartridge, from
+  /// power on (on the model it was recorded with), driving the
+  /// keys of the pad on each frame.
+  pub fn play_movie(&mut self, movie: Movie) -> Result<(), Error> {
+    if movie.rom_sha1() != sha1_hex(self.cart().rom().inner()) {
+      return Err(Error::CustomError(String::from(
+        "Movie was recorded with a different ROM",
+      )));
+    }
+    if movie.model() != self.model {
+      info!("Switching to the {} model of the movie", movie.model());
+    }
+    self.movie_model = Some(movie.model());
+    if let Err(error) = self.power_cycle() {
+      self.movie_model = None;
+      return Err(error);
+    }
+    let movie = MovieSession::new(movie, MovieMode::Playing);
+    self.apply_buttons(movie.buttons());
+    self.movie = Some(movie);
+    Ok(())
+  }
+
+  pub fn load_movie_file(&mut self, path: &str) -> Result<(), Error> {
+    self.play_movie(Movie::from_file(path)?)
+  }
+
+  /// Stops recording (or playing) the movie, returning it. The
+  /// configured model is restored on the next cartridge load (eg:
+  /// reset) rather than switching the hardware of the running game.
+  pub fn stop_movie(&mut self) -> Option<Movie> {
+    self.movie_model = None;
+    self.movie.take().map(MovieSession::into_movie)
+  }
+
+  /// Restarts the loaded cartridge as if the system had been
+  /// turned off and on, with its RAM cleared.
+  fn power_cycle(&mut self) -> Result<(), Error> {
+    let data = self.cart().rom().inner().to_vec();
+    self.reset();
+    self.load_model();
+    self.load_cart(&data, None)?;
+    Ok(())
+  }
+
+  /// Ends the frame of the movie being recorded or played,
+  /// setting the keys to be held during the next one.
+  fn movie_frame(&mut self) {
+    if self.movie.is_none() {
+      return;
+    }
+    let hash = self.state_hash();
+    let buttons = {
+      let pad = self.pad.borrow();
+      let mut buttons = [0u8; MAX_PLAYERS];
+      for (player, value) in buttons.iter_mut().enumerate() {
+        *value = pad.buttons_player(player);
+      }
+      buttons
+    };
+    let movie = self.movie.as_mut().unwrap();
+    if movie.end_frame(buttons, hash) {
+      warn!("Movie desync at frame {}", movie.frame() - 1);
+    }
+    if movie.finished() {
+      match movie.desync() {
+        Some(frame) => info!("Movie playback finished, desynced at frame {}", frame),
+        None => info!("Movie playback finished in sync"),
+      }
+      self.stop_movie();
+      self.apply_buttons([0u8; MAX_PLAYERS]);
+      return;
+    }
+    let buttons = movie.buttons();
+    self.apply_buttons(buttons);
+  }
+
+  /// Presses and lifts the keys of the pad so that only
+  /// the provided buttons are held.
+  fn apply_buttons(&mut self, buttons: [u8; MAX_PLAYERS]) {
+    let mut pad = self.pad.borrow_mut();
+    for (player, &value) in buttons.iter().enumerate() {
+      let held = pad.buttons_player(player);
+      for key in PadKey::ALL {
+        let mask = key.mask();
+        if value & mask != held & mask {
+          if value & mask != 0 {
+            pad.key_press_player(player, key);
+          } else {
+            pad.key_lift_player(player, key);
+          }
+        }
+      }
+    }
+  }
+
+  /// Loads the boot ROM and allocates the memory of the model,
+  /// the embedded boot ROM is only meant for the DMG so for other
+  /// models without a boot ROM loaded the boot is skipped.
+  pub fn load_model(&mut self) {
+    self.load_boot();
+    self.bus.borrow_mut().allocate(self.model);
+  }
+
+  /// Maps the boot ROM loaded (or the embedded DMG one) over
+  /// the cartridge, until it's unmapped by the boot ROM itself.
+  fn load_boot(&mut self) {
+    let mut bus = self.bus.borrow_mut();
+    match &self.boot_rom {
+      Some(data) => bus.write_boot(data),
+      None => bus.write_boot(&DMG_BOOT),
+    }
+  }
+
+  pub fn model(&self) -> Model {
+    self.model
+  }
+
+  /// Sets the model being emulated, which takes effect (for
+  /// the boot ROM and memory) on the next `load_model()`.
+  pub fn set_model(&mut self, model: Model) {
+    self.base_model = model;
+    self.switch_model(model);
+  }
+
+  /// Model configured to be emulated, regardless of the one
+  /// forced for the loaded cartridge.
+  pub fn base_model(&self) -> Model {
+    self.base_model
+  }
+
+  fn switch_model(&mut self, model: Model) {
+    self.model = model;
+    self.ppu_mut().set_model(model);
+    self.sgb = model.is_sgb().then(|| Shared::new(Sgb::new()));
+    self.pad.borrow_mut().set_sgb(self.sgb.clone());
+  }
+
+  /// If the boot ROM is run (rather than skipped) when
+  /// loading a cartridge.
+  pub fn runs_boot(&self) -> bool {
+    !self.skip_boot && (self.boot_rom.is_some() || self.model == Model::Dmg)
+  }
+
+  pub fn boot_rom(&self) -> Option<&[u8]> {
+    self.boot_rom.as_deref()
+  }
+
+  /// Sets the boot ROM used (from now on) instead of the
+  /// embedded DMG one, either a 256 bytes (DMG0, DMG, MGB,
+  /// SGB) or a 2304 bytes (CGB, AGB) one.
+  pub fn set_boot_rom(&mut self, data: Option<Vec<u8>>) -> Result<(), Error> {
+    if let Some(data) = &data {
+      if data.len() != DMG_BOOT_SIZE && data.len() != CGB_BOOT_SIZE {
+        return Err(Error::CustomError(format!(
+          "Invalid boot ROM size: {} B",
+          data.len()
+        )));
+      }
+      if data.len() != self.model.boot_size() {
+        warn!("Boot ROM size doesn't match the {} model", self.model);
+      }
+    }
+    self.boot_rom = data;
+    Ok(())
+  }
+
+  pub fn load_boot_file(&mut self, path: &str) -> Result<(), Error> {
+    self.set_boot_rom(Some(read_file(path)?))
+  }
+
+  pub fn skip_boot(&self) -> bool {
+    self.skip_boot
+  }
+
+  /// Skips the boot ROM for the cartridges loaded from now on,
+  /// starting them with the state left by the boot ROM instead.
+  pub fn set_skip_boot(&mut self, value: bool) {
+    self.skip_boot = value;
+  }
+
+  /// Sets the CPU and IO registers to the values left by the boot
+  /// ROM of the model and unmaps it, so that the cartridge starts
+  /// (at 0x0100) as if the boot ROM had been run. Writes to IO
+  /// registers of hardware not emulated (eg: APU) are ignored.
+  pub fn apply_boot_state(&mut self) {
+    let (header_checksum, cgb_game) = {
+      let cart = self.cart();
+      (cart.header().hchk, cart.header().cgb)
+    };
+    let registers = self.model.boot_registers(header_checksum, cgb_game);
+    let cpu = self.cpu_mut();
+    cpu.reset();
+    cpu.set_af(registers.af);
+    cpu.set_bc(registers.bc);
+    cpu.set_de(registers.de);
+    cpu.set_hl(registers.hl);
+
+    let mut bus = self.bus.borrow_mut();
+    for (addr, value) in self.model.boot_io() {
+      bus.poke(addr, value);
+    }
+    bus.poke(0xff50, 0x01);
   }
 
   pub fn load_cart_file(
@@ -145,7 +751,30 @@ impl GameBoy {
     path: &str,
     ram_path: Option<&str>,
   ) -> Result<Ref<Cartridge>, Error> {
-    let data = read_file(path)?;
+    self.load_cart_file_entry(path, None, ram_path)
+  }
+
+  /// Loads the cartridge from the file, which may be a ZIP or gzip
+  /// archive, picking the entry with the provided name (or the first
+  /// ROM entry) in the ZIP case. Patches are looked for next to the
+  /// file itself, so they have the name of the archive.
+  pub fn load_cart_file_entry(
+    &mut self,
+    path: &str,
+    entry: Option<&str>,
+    ram_path: Option<&str>,
+  ) -> Result<Ref<'_, Cartridge>, Error> {
+    let mut data = read_rom_file(path, entry)?;
+
+    // a patch with the same name of the ROM (eg: `game.ips`) is
+    // applied to the ROM data before the cartridge is created
+    if let Some(patch_path) = find_patch(path) {
+      let (patched, format) = apply_patch_file(&data, &patch_path)?;
+      info!("Applied {} patch {}", format.description(), patch_path);
+      info!("{}", checksum_report(&patched));
+      data = patched;
+    }
+
     match ram_path {
       Some(ram_path) => {
         let ram_data = read_file(ram_path)?;
@@ -156,18 +785,336 @@ impl GameBoy {
   }
 
   fn load_cart(&mut self, data: &[u8], ram_data: Option<&[u8]>) -> Result<Ref<Cartridge>, Error> {
-    let mut cart = Cartridge::from_data(data)?;
+    let (rom_entry, overrides) = match &self.rom_db {
+      Some(rom_db) => (rom_db.lookup(data).cloned(), rom_db.overrides(data)),
+      None => (None, RomOverrides::default()),
+    };
+    // the model forced for a cartridge (or by the movie played) only
+    // applies to it, the configured one being restored afterwards
+    let model = self
+      .movie_model
+      .or(overrides.model)
+      .unwrap_or(self.base_model);
+    if model != self.model {
+      if overrides.model.is_some() {
+        info!("Forcing the {} model", model);
+      }
+      self.switch_model(model);
+      self.load_model();
+    }
+    let mut cart = Cartridge::from_data_overrides(data, self.load_policy, overrides)?;
+    for warning in cart.validation().warnings() {
+      warn!("{}", warning);
+    }
+    if let Some(rom_entry) = &rom_entry {
+      info!("Identified as {}", rom_entry);
+      if rom_entry.status == DumpStatus::BadDump {
+        warn!("ROM is a known bad dump");
+      }
+    }
+    if !overrides.is_empty() {
+      info!("Applied overrides {:?}", overrides);
+    }
+    cart.set_rom_entry(rom_entry);
     if let Some(ram_data) = ram_data {
       cart.set_ram_data(ram_data)
     }
+    // the log being recorded is kept when the same ROM is
+    // loaded again (eg: on reset) and dropped otherwise
+    let matches = self
+      .cdl()
+      .is_some_and(|cdl| cdl.matches(cart.rom().len(), cart.header().gchk));
+    if !matches {
+      self.cdl = None;
+    }
+    cart.set_cheats(Some(self.cheats.clone()));
     self.cart = Shared::new(cart);
     self.bus.borrow_mut().set_cart(self.cart.clone());
+    self.attach_cdl();
+    // the SGB only listens to the commands of the games
+    // flagged as supporting it in their header
+    if let Some(sgb) = &self.sgb {
+      let mut sgb = sgb.borrow_mut();
+      sgb.reset();
+      sgb.set_enabled(self.cart.borrow().header().sgb);
+    }
+    // the CPU reset leaves it at the entry point of the cartridge, so
+    // it has to be moved to the start of the boot ROM, which is mapped
+    // again as its previous run has unmapped it
+    if self.runs_boot() {
+      self.load_boot();
+      self.cpu_mut().set_pc(0x0000);
+    } else {
+      self.apply_boot_state();
+    }
     Ok(self.cart.borrow())
   }
 }
 
 impl Default for GameBoy {
   fn default() -> Self {
-    Self::new()
+    Self::new(Model::default())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use super::*;
+  use crate::trace::{AccessKind, BusAccess};
+
+  /// Creates a system running (from 0x0100, with the boot
+  /// skipped) a ROM only cartridge with the provided code.
+  fn system(code: &[u8]) -> GameBoy {
+    let mut data = vec![0x00; 0x8000];
+    data[0x0100..0x0100 + code.len()].copy_from_slice(code);
+    let mut game_boy = GameBoy::new(Model::Dmg);
+    game_boy.set_skip_boot(true);
+    game_boy.load_model();
+    game_boy.load_cart(&data, None).unwrap();
+    game_boy
+  }
+
+  /// Installs a hook recording the (kind, address, value)
+  /// of the accesses made through the bus.
+  fn record(game_boy: &mut GameBoy) -> Rc<RefCell<Vec<(AccessKind, u16, u8)>>> {
+    let accesses = Rc::new(RefCell::new(vec![]));
+    let hook_accesses = accesses.clone();
+    game_boy.set_bus_trace(Box::new(move |access: &BusAccess| {
+      hook_accesses
+        .borrow_mut()
+        .push((access.kind, access.addr, access.value))
+    }));
+    accesses
+  }
+
+  #[test]
+  fn test_wram_banks() {
+    let mut game_boy = GameBoy::new(Model::Cgb);
+    game_boy.load_model();
+    let mut bus = game_boy.bus.borrow_mut();
+    for bank in 0..8u8 {
+      bus.poke(0xff70, bank);
+      bus.poke(0xd000, bank);
+    }
+    // bank 0 selects bank 1, the upper bits being ignored
+    bus.poke(0xff70, 0x00);
+    assert_eq!((bus.peek(0xff70), bus.peek(0xd000)), (0xf8, 0x01));
+    bus.poke(0xff70, 0xfb);
+    assert_eq!((bus.peek(0xff70), bus.peek(0xd000)), (0xfb, 0x03));
+    assert_eq!(bus.peek(0xf000), 0x03);
+    bus.poke(0xc000, 0xc0);
+    bus.poke_wram(Some(5), 0xdfff, 0x55);
+    bus.poke(0xff70, 0x05);
+    assert_eq!(bus.peek(0xdfff), 0x55);
+    assert_eq!(bus.peek(0xe000), 0xc0);
+    drop(bus);
+
+    // the DMG has a single bank, with SVBK not being mapped
+    let mut game_boy = GameBoy::new(Model::Dmg);
+    game_boy.load_model();
+    let mut bus = game_boy.bus.borrow_mut();
+    bus.poke(0xd000, 0x01);
+    bus.poke(0xff70, 0x02);
+    bus.poke(0xd000, 0x02);
+    bus.poke(0xff70, 0x01);
+    assert_eq!(bus.peek(0xd000), 0x02);
+    assert_eq!(bus.peek(0xff70), 0xff);
+  }
+
+  #[test]
+  fn test_apply_boot_state() {
+    let table = [
+      (Model::Dmg0, 0x0100, 0xff13, 0x00c1, 0x8403, false),
+      (Model::Dmg, 0x01b0, 0x0013, 0x00d8, 0x014d, false),
+      (Model::Mgb, 0xffb0, 0x0013, 0x00d8, 0x014d, false),
+      (Model::Sgb, 0x0100, 0x0014, 0x0000, 0xc060, false),
+      (Model::Sgb2, 0xff00, 0x0014, 0x0000, 0xc060, false),
+      (Model::Cgb, 0x1180, 0x0000, 0x0008, 0x007c, true),
+      (Model::Agb, 0x1100, 0x0100, 0x0008, 0x007c, true),
+    ];
+    let mut data = vec![0x00; 0x8000];
+    data[0x014d] = 0xe7;
+    for (model, af, bc, de, hl, cgb) in table {
+      let mut game_boy = GameBoy::new(model);
+      game_boy.set_skip_boot(true);
+      game_boy.load_model();
+      game_boy.load_cart(&data, None).unwrap();
+
+      let cpu = game_boy.cpu();
+      assert_eq!(
+        (cpu.af(), cpu.bc(), cpu.de(), cpu.hl()),
+        (af, bc, de, hl),
+        "{}",
+        model
+      );
+      assert_eq!((cpu.pc(), cpu.sp()), (0x0100, 0xfffe), "{}", model);
+
+      let bus = game_boy.bus.borrow();
+      assert_eq!(bus.region(0x0000), MemoryRegion::Rom0, "{}", model);
+      assert_eq!(bus.peek(0xff40), 0x91, "{}", model);
+      assert_eq!(bus.peek(0xff47), 0xfc, "{}", model);
+      assert_eq!(bus.peek(0xffff), 0x00, "{}", model);
+      if cgb {
+        assert_eq!(bus.peek(0xff68), 0xc0, "{}", model);
+        assert_eq!(bus.peek(0xff6a), 0xc0, "{}", model);
+        assert_eq!(bus.peek(0xff70), 0xf8, "{}", model);
+      }
+    }
+  }
+
+  #[test]
+  fn test_movie_model() {
+    let data = vec![0x00; 0x8000];
+    let mut game_boy = system(&[]);
+    let sha1 = sha1_hex(&data);
+
+    game_boy
+      .play_movie(Movie::new(&sha1, Model::Cgb, 1))
+      .unwrap();
+    assert_eq!(game_boy.model(), Model::Cgb);
+    assert_eq!(game_boy.base_model(), Model::Dmg);
+    // the model of the movie is kept by the power cycles
+    game_boy.power_cycle().unwrap();
+    assert_eq!(game_boy.model(), Model::Cgb);
+    assert!(game_boy.stop_movie().is_some());
+    game_boy.power_cycle().unwrap();
+    assert_eq!(game_boy.model(), Model::Dmg);
+
+    // as well as once the playback has finished
+    game_boy
+      .play_movie(Movie::new(&sha1, Model::Mgb, 1))
+      .unwrap();
+    assert_eq!(game_boy.model(), Model::Mgb);
+    game_boy.movie_frame();
+    assert!(game_boy.stop_movie().is_none());
+    game_boy.power_cycle().unwrap();
+    assert_eq!(game_boy.model(), Model::Dmg);
+    assert_eq!(game_boy.base_model(), Model::Dmg);
+  }
+
+  #[test]
+  fn test_boot_remap() {
+    let data = vec![0x00; 0x8000];
+    let mut game_boy = GameBoy::new(Model::Dmg);
+    game_boy.load_model();
+    game_boy.load_cart(&data, None).unwrap();
+    assert_eq!(game_boy.cpu().pc(), 0x0000);
+    assert_eq!(game_boy.bus.borrow().region(0x0000), MemoryRegion::Boot);
+
+    // the boot ROM unmaps itself once it's done running
+    game_boy.bus.borrow_mut().poke(0xff50, 0x01);
+    assert_eq!(game_boy.bus.borrow().region(0x0000), MemoryRegion::Rom0);
+
+    game_boy.load_cart(&data, None).unwrap();
+    assert_eq!(game_boy.cpu().pc(), 0x0000);
+    assert_eq!(game_boy.bus.borrow().region(0x0000), MemoryRegion::Boot);
+    assert_eq!(game_boy.bus.borrow().peek(0x0000), DMG_BOOT[0]);
+
+    game_boy.set_skip_boot(true);
+    game_boy.load_cart(&data, None).unwrap();
+    assert_eq!(game_boy.cpu().pc(), 0x0100);
+    assert_eq!(game_boy.bus.borrow().region(0x0000), MemoryRegion::Rom0);
+  }
+
+  type SetRegister = fn(&mut Cpu, u16);
+
+  /// Executes the instruction with the register set to the address,
+  /// while the PPU scans the third row of OAM (line 1, dot 8),
+  /// returning that row as left by the execution.
+  fn oam_bug_row(opcode: u8, set: SetRegister, addr: u16) -> Vec<u8> {
+    let mut game_boy = system(&[opcode]);
+    game_boy.bus.borrow_mut().poke(0xff40, 0x00);
+    for index in 0..0xa0 {
+      game_boy.bus.borrow_mut().poke(0xfe00 + index, index as u8);
+    }
+    for (addr, value) in [(0xfe08, 0x00ff), (0xfe0c, 0x0f0f), (0xfe10, 0x3333)] {
+      game_boy.bus.borrow_mut().poke(addr, value as u8);
+      game_boy.bus.borrow_mut().poke(addr + 1, (value >> 8) as u8);
+    }
+    game_boy.bus.borrow_mut().poke(0xff40, 0x91);
+    while game_boy.bus.borrow().peek(0xff44) != 1 {
+      game_boy.ppu_mut().clock(1);
+    }
+    game_boy.ppu_mut().clock(8);
+    set(game_boy.cpu_mut(), addr);
+    game_boy.clock();
+    game_boy.bus.borrow_mut().poke(0xff40, 0x00);
+    let bus = game_boy.bus.borrow();
+    (0xfe10..0xfe18).map(|addr| bus.peek(addr)).collect()
+  }
+
+  #[test]
+  fn test_oam_bug() {
+    // the first word mixed as ((a ^ c) & (b ^ c)) ^ c with the
+    // previous row, whose other words are copied over
+    let clean = vec![0x33, 0x33, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17];
+    let corrupted = vec![0x3f, 0x03, 0x0a, 0x0b, 0x0f, 0x0f, 0x0e, 0x0f];
+    let table: [(u8, SetRegister); 12] = [
+      (0x03, Cpu::set_bc), // INC BC
+      (0x0b, Cpu::set_bc), // DEC BC
+      (0x13, Cpu::set_de), // INC DE
+      (0x1b, Cpu::set_de), // DEC DE
+      (0x23, Cpu::set_hl), // INC HL
+      (0x2b, Cpu::set_hl), // DEC HL
+      (0x33, Cpu::set_sp), // INC SP
+      (0x3b, Cpu::set_sp), // DEC SP
+      (0x22, Cpu::set_hl), // LD (HL+),A
+      (0x32, Cpu::set_hl), // LD (HL-),A
+      (0x2a, Cpu::set_hl), // LD A,(HL+)
+      (0x3a, Cpu::set_hl), // LD A,(HL-)
+    ];
+    for (opcode, set) in table {
+      assert_eq!(
+        oam_bug_row(opcode, set, 0xfe40),
+        corrupted,
+        "{:02x}",
+        opcode
+      );
+      assert_eq!(oam_bug_row(opcode, set, 0xc000), clean, "{:02x}", opcode);
+    }
+    // INC A
+    assert_eq!(oam_bug_row(0x3c, Cpu::set_hl, 0xfe40), clean);
+  }
+
+  #[test]
+  fn test_bus_trace() {
+    // LD A,(0xC000); LDH (0x46),A
+    let mut game_boy = system(&[0xfa, 0x00, 0xc0, 0xe0, 0x46]);
+    game_boy.bus.borrow_mut().poke(0xc000, 0xc1);
+    game_boy.bus.borrow_mut().poke(0xffff, 0x1f);
+    let accesses = record(&mut game_boy);
+
+    game_boy.clock();
+    assert_eq!(
+      *accesses.borrow(),
+      vec![
+        (AccessKind::Read, 0x0100, 0xfa),
+        (AccessKind::Read, 0x0101, 0x00),
+        (AccessKind::Read, 0x0102, 0xc0),
+        (AccessKind::Read, 0xc000, 0xc1),
+      ]
+    );
+
+    // the OAM DMA reads its source without being traced, so only
+    // the fetches of the instructions executed meanwhile are seen
+    accesses.borrow_mut().clear();
+    game_boy.clock();
+    while game_boy.cpu().pc() < 0x0200 {
+      game_boy.clock();
+    }
+    let accesses = accesses.borrow();
+    assert_eq!(
+      accesses[..3],
+      [
+        (AccessKind::Read, 0x0103, 0xe0),
+        (AccessKind::Read, 0x0104, 0x46),
+        (AccessKind::Write, 0xff46, 0xc1),
+      ]
+    );
+    assert!(accesses[3..]
+      .iter()
+      .all(|&(kind, addr, _)| kind == AccessKind::Read && (0x0105..0x0200).contains(&addr)));
   }
 }
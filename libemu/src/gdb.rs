@@ -0,0 +1,778 @@
+use std::{
+  collections::HashSet,
+  io::{ErrorKind, Read, Write},
+  net::{TcpListener, TcpStream},
+};
+
+use log::{info, warn};
+
+use crate::{error::Error, gb::GameBoy};
+
+/// Maximum size of a packet accepted from the debugger.
+pub const PACKET_SIZE: usize = 0x1000;
+
+/// End of the cartridge ROM area of the address space.
+const ROM_END: u16 = 0x8000;
+
+/// Signal numbers reported in the stop replies.
+const SIGINT: u8 = 0x02;
+const SIGTRAP: u8 = 0x05;
+
+/// Number of registers exposed to the debugger, the 8-bit ones
+/// (A, F, B, C, D, E, H and L) followed by SP and PC.
+const REGISTER_COUNT: usize = 10;
+
+/// Target description of the SM83 register file, the CPU is not
+/// known by GDB so no architecture is declared.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gbremu.sm83">
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="f" bitsize="8"/>
+    <reg name="b" bitsize="8"/>
+    <reg name="c" bitsize="8"/>
+    <reg name="d" bitsize="8"/>
+    <reg name="e" bitsize="8"/>
+    <reg name="h" bitsize="8"/>
+    <reg name="l" bitsize="8"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Server for the GDB Remote Serial Protocol, allowing an external
+/// debugger to control the execution of a `GameBoy`.
+///
+/// The server never blocks, the frontend is expected to call
+/// `poll()` regularly and to check `should_break()` before running
+/// each instruction, not clocking the system while `stopped()`.
+pub struct GdbServer {
+  listener: TcpListener,
+  stream: Option<TcpStream>,
+  buffer: Vec<u8>,
+  /// Data pending to be sent, as the (non-blocking) connection
+  /// may not accept a large reply at once.
+  output: Vec<u8>,
+  breakpoints: HashSet<u16>,
+  stopped: bool,
+  no_ack: bool,
+}
+
+impl GdbServer {
+  /// Starts listening for a debugger on the provided port
+  /// of the loopback interface.
+  pub fn bind(port: u16) -> Result<Self, Error> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+      .map_err(|_| Error::CustomError(format!("Failed to bind GDB server to port {}", port)))?;
+    listener
+      .set_nonblocking(true)
+      .map_err(|_| Error::CustomError(String::from("Failed to configure GDB server")))?;
+    info!("GDB server listening on 127.0.0.1:{}", port);
+    Ok(Self {
+      listener,
+      stream: None,
+      buffer: vec![],
+      output: vec![],
+      breakpoints: HashSet::new(),
+      stopped: false,
+      no_ack: false,
+    })
+  }
+
+  pub fn connected(&self) -> bool {
+    self.stream.is_some()
+  }
+
+  /// If the execution is currently halted by the debugger.
+  pub fn stopped(&self) -> bool {
+    self.stopped
+  }
+
+  pub fn breakpoints(&self) -> &HashSet<u16> {
+    &self.breakpoints
+  }
+
+  pub fn add_breakpoint(&mut self, addr: u16) {
+    self.breakpoints.insert(addr);
+  }
+
+  pub fn remove_breakpoint(&mut self, addr: u16) {
+    self.breakpoints.remove(&addr);
+  }
+
+  /// Checks if the instruction at the current PC has a breakpoint
+  /// set, in which case execution is halted and the debugger notified.
+  pub fn should_break(&mut self, system: &GameBoy) -> bool {
+    if self.stopped {
+      return true;
+    }
+    if self.stream.is_none() || !self.breakpoints.contains(&system.cpu().pc()) {
+      return false;
+    }
+    self.stop(SIGTRAP);
+    true
+  }
+
+  /// Accepts pending connections and processes every complete
+  /// packet received from the debugger.
+  pub fn poll(&mut self, system: &mut GameBoy) -> Result<(), Error> {
+    if self.stream.is_none() {
+      self.accept()?;
+      return Ok(());
+    }
+
+    self.flush();
+
+    let mut data = [0u8; PACKET_SIZE];
+    loop {
+      let stream = match self.stream.as_mut() {
+        Some(stream) => stream,
+        None => return Ok(()),
+      };
+      match stream.read(&mut data) {
+        Ok(0) => {
+          self.disconnect();
+          return Ok(());
+        },
+        Ok(count) => self.buffer.extend_from_slice(&data[..count]),
+        Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(()),
+        Err(_) => {
+          self.disconnect();
+          return Ok(());
+        },
+      }
+
+      // the packets are handled as they're received so that only
+      // the last (incomplete) one is kept in the buffer
+      while let Some(packet) = self.next_packet()? {
+        self.handle(system, &packet)?;
+      }
+    }
+  }
+
+  fn accept(&mut self) -> Result<(), Error> {
+    match self.listener.accept() {
+      Ok((stream, addr)) => {
+        stream
+          .set_nonblocking(true)
+          .map_err(|_| Error::CustomError(String::from("Failed to configure GDB connection")))?;
+        info!("GDB client connected from {}", addr);
+        self.stream = Some(stream);
+        self.buffer.clear();
+        self.output.clear();
+        self.no_ack = false;
+
+        // the system is halted as soon as the debugger attaches
+        self.stopped = true;
+        Ok(())
+      },
+      Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(()),
+      Err(_) => Err(Error::CustomError(String::from(
+        "Failed to accept GDB connection",
+      ))),
+    }
+  }
+
+  /// Drops the connection to the debugger (if any), clearing
+  /// the breakpoints and resuming the execution.
+  pub fn disconnect(&mut self) {
+    if self.stream.is_some() {
+      info!("GDB client disconnected");
+    }
+    self.stream = None;
+    self.buffer.clear();
+    self.output.clear();
+    self.breakpoints.clear();
+    self.stopped = false;
+  }
+
+  /// Extracts the next complete packet from the receive buffer,
+  /// acknowledgements are skipped and an interrupt request (0x03)
+  /// halts the execution right away.
+  fn next_packet(&mut self) -> Result<Option<String>, Error> {
+    loop {
+      match self.buffer.first() {
+        None => return Ok(None),
+        Some(b'+') | Some(b'-') => {
+          self.buffer.remove(0);
+        },
+        Some(0x03) => {
+          self.buffer.remove(0);
+          self.stop(SIGINT);
+        },
+        Some(b'$') => break,
+        Some(_) => {
+          self.buffer.remove(0);
+        },
+      }
+    }
+
+    // packets longer than the size advertised to the debugger are
+    // discarded, so that the buffer can't grow without limit
+    let end = self.buffer.iter().position(|&byte| byte == b'#');
+    let length = end.map_or(self.buffer.len(), |end| end + 3);
+    if length > PACKET_SIZE {
+      warn!("GDB packet too long, discarding it");
+      self.buffer.drain(..length.min(self.buffer.len()));
+      if !self.no_ack {
+        self.send_raw(b"-")?;
+      }
+      return Ok(None);
+    }
+    let end = match end {
+      Some(end) if self.buffer.len() >= end + 3 => end,
+      _ => return Ok(None),
+    };
+    let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+    let payload = &packet[1..end];
+    let checksum = u8::from_str_radix(&String::from_utf8_lossy(&packet[end + 1..]), 16).ok();
+
+    if checksum != Some(Self::checksum(payload)) {
+      warn!("Invalid GDB packet checksum");
+      if !self.no_ack {
+        self.send_raw(b"-")?;
+      }
+      return Ok(None);
+    }
+    if !self.no_ack {
+      self.send_raw(b"+")?;
+    }
+
+    Ok(Some(String::from_utf8_lossy(payload).into_owned()))
+  }
+
+  fn handle(&mut self, system: &mut GameBoy, packet: &str) -> Result<(), Error> {
+    let (command, args) = packet.split_at(1.min(packet.len()));
+    match command {
+      "?" => self.send(&format!("S{:02x}", SIGTRAP)),
+      "g" => self.send(&Self::encode(&Self::registers(system))),
+      "G" => match Self::decode(args) {
+        Some(data) if data.len() == REGISTER_COUNT + 2 => {
+          Self::set_registers(system, &data);
+          self.send("OK")
+        },
+        _ => self.send("E01"),
+      },
+      "p" => match usize::from_str_radix(args, 16) {
+        Ok(index) if index < REGISTER_COUNT => {
+          let registers = Self::registers(system);
+          let data = if index < 8 {
+            &registers[index..index + 1]
+          } else {
+            let offset = 8 + (index - 8) * 2;
+            &registers[offset..offset + 2]
+          };
+          self.send(&Self::encode(data))
+        },
+        _ => self.send("E01"),
+      },
+      "P" => {
+        let result = args.split_once('=').and_then(|(index, value)| {
+          Some((usize::from_str_radix(index, 16).ok()?, Self::decode(value)?))
+        });
+        match result {
+          Some((index, value)) if index < REGISTER_COUNT => {
+            Self::set_register(system, index, &value);
+            self.send("OK")
+          },
+          _ => self.send("E01"),
+        }
+      },
+      "m" => match Self::parse_range(args) {
+        Some((addr, length)) => {
+          let bus = system.cpu().bus();
+          let data: Vec<u8> = (0..length)
+            .map(|offset| bus.peek(addr.wrapping_add(offset)))
+            .collect();
+          drop(bus);
+          self.send(&Self::encode(&data))
+        },
+        None => self.send("E01"),
+      },
+      "M" => {
+        let result = args
+          .split_once(':')
+          .and_then(|(range, value)| Some((Self::parse_range(range)?, Self::decode(value)?)));
+        match result {
+          // the ROM area is not writable, writes there would instead
+          // be interpreted as MBC register writes
+          Some(((addr, _), _)) if addr < ROM_END => self.send("E02"),
+          Some(((addr, _), data)) => {
+            let mut bus = system.cpu().bus_mut();
+            for (offset, byte) in data.iter().enumerate() {
+              bus.poke(addr.wrapping_add(offset as u16), *byte);
+            }
+            drop(bus);
+            self.send("OK")
+          },
+          None => self.send("E01"),
+        }
+      },
+      "Z" | "z" => {
+        // only software breakpoints (type 0) are supported
+        let mut parts = args.split(',');
+        let kind = parts.next();
+        let addr = parts
+          .next()
+          .and_then(|addr| u16::from_str_radix(addr, 16).ok());
+        match (kind, addr) {
+          (Some("0"), Some(addr)) => {
+            if command == "Z" {
+              self.breakpoints.insert(addr);
+            } else {
+              self.breakpoints.remove(&addr);
+            }
+            self.send("OK")
+          },
+          _ => self.send(""),
+        }
+      },
+      "s" => {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+          system.cpu_mut().set_pc(addr);
+        }
+        system.clock();
+        self.stop(SIGTRAP);
+        Ok(())
+      },
+      "c" => {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+          system.cpu_mut().set_pc(addr);
+        }
+        // runs the current instruction right away so that execution
+        // does not halt again on the breakpoint it's stopped at
+        system.clock();
+        self.stopped = false;
+        Ok(())
+      },
+      "k" => {
+        self.disconnect();
+        Ok(())
+      },
+      "D" => {
+        self.send("OK")?;
+        self.disconnect();
+        Ok(())
+      },
+      "q" => self.handle_query(system, args),
+      "Q" => {
+        if args == "StartNoAckMode" {
+          self.send("OK")?;
+          self.no_ack = true;
+          Ok(())
+        } else {
+          self.send("")
+        }
+      },
+      _ => self.send(""),
+    }
+  }
+
+  fn handle_query(&mut self, system: &GameBoy, query: &str) -> Result<(), Error> {
+    if query.starts_with("Supported") {
+      return self.send(&format!(
+        "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+",
+        PACKET_SIZE
+      ));
+    }
+    if query == "Attached" {
+      return self.send("1");
+    }
+    if query == "C" {
+      return self.send("QC1");
+    }
+    if query == "fThreadInfo" {
+      return self.send("m1");
+    }
+    if query == "sThreadInfo" {
+      return self.send("l");
+    }
+    if let Some(command) = query.strip_prefix("Rcmd,") {
+      return match Self::decode(command) {
+        Some(command) => {
+          let output = self.monitor(system, &String::from_utf8_lossy(&command));
+          self.send(&format!("O{}", Self::encode(output.as_bytes())))?;
+          self.send("OK")
+        },
+        None => self.send("E01"),
+      };
+    }
+    if let Some(args) = query.strip_prefix("Xfer:features:read:target.xml:") {
+      let range = args.split_once(',').and_then(|(offset, length)| {
+        Some((
+          usize::from_str_radix(offset, 16).ok()?,
+          usize::from_str_radix(length, 16).ok()?,
+        ))
+      });
+      return match range {
+        Some((offset, length)) => {
+          let data = TARGET_XML.as_bytes();
+          let start = offset.min(data.len());
+          let end = offset.saturating_add(length).min(data.len());
+          let prefix = if end == data.len() { "l" } else { "m" };
+          self.send(&format!(
+            "{}{}",
+            prefix,
+            String::from_utf8_lossy(&data[start..end])
+          ))
+        },
+        None => self.send("E01"),
+      };
+    }
+    self.send("")
+  }
+
+  /// Runs a `monitor` command, allowing breakpoints to be managed
+  /// using the names of the loaded symbols (eg: `monitor break Main`).
+  fn monitor(&mut self, system: &GameBoy, command: &str) -> String {
+    let (name, target) = command
+      .trim()
+      .split_once(' ')
+      .unwrap_or((command.trim(), ""));
+    let target = target.trim();
+    let addr = system.resolve_symbol(target).or_else(|| {
+      u16::from_str_radix(target.trim_start_matches("0x").trim_start_matches('$'), 16).ok()
+    });
+    let label = |addr: u16| {
+      system
+        .label(addr)
+        .unwrap_or_else(|| format!("${:04X}", addr))
+    };
+    match (name, addr) {
+      ("break", Some(addr)) => {
+        self.add_breakpoint(addr);
+        format!("Breakpoint set at {} (${:04X})\n", label(addr), addr)
+      },
+      ("delete", Some(addr)) => {
+        self.remove_breakpoint(addr);
+        format!("Breakpoint removed at {} (${:04X})\n", label(addr), addr)
+      },
+      ("break" | "delete", None) => format!("Unknown symbol or address: {}\n", target),
+      ("breakpoints", _) => {
+        let mut addrs: Vec<&u16> = self.breakpoints.iter().collect();
+        addrs.sort();
+        addrs
+          .into_iter()
+          .map(|&addr| format!("${:04X} {}\n", addr, label(addr)))
+          .collect()
+      },
+      _ => String::from("Commands: break <symbol|addr>, delete <symbol|addr>, breakpoints\n"),
+    }
+  }
+
+  fn stop(&mut self, signal: u8) {
+    self.stopped = true;
+    if self.send(&format!("S{:02x}", signal)).is_err() {
+      self.disconnect();
+    }
+  }
+
+  fn registers(system: &GameBoy) -> Vec<u8> {
+    let cpu = system.cpu();
+    let mut data = vec![
+      cpu.regs.a,
+      cpu.f(),
+      cpu.regs.b,
+      cpu.regs.c,
+      cpu.regs.d,
+      cpu.regs.e,
+      cpu.regs.h,
+      cpu.regs.l,
+    ];
+    data.extend_from_slice(&cpu.sp().to_le_bytes());
+    data.extend_from_slice(&cpu.pc().to_le_bytes());
+    data
+  }
+
+  fn set_registers(system: &mut GameBoy, data: &[u8]) {
+    for index in 0..REGISTER_COUNT {
+      let value = if index < 8 {
+        &data[index..index + 1]
+      } else {
+        let offset = 8 + (index - 8) * 2;
+        &data[offset..offset + 2]
+      };
+      Self::set_register(system, index, value);
+    }
+  }
+
+  fn set_register(system: &mut GameBoy, index: usize, value: &[u8]) {
+    let cpu = system.cpu_mut();
+    let byte = value.first().copied().unwrap_or(0);
+    let word = byte as u16 | (value.get(1).copied().unwrap_or(0) as u16) << 8;
+    match index {
+      0 => cpu.regs.a = byte,
+      1 => cpu.set_f(byte),
+      2 => cpu.regs.b = byte,
+      3 => cpu.regs.c = byte,
+      4 => cpu.regs.d = byte,
+      5 => cpu.regs.e = byte,
+      6 => cpu.regs.h = byte,
+      7 => cpu.regs.l = byte,
+      8 => cpu.set_sp(word),
+      9 => cpu.set_pc(word),
+      _ => (),
+    }
+  }
+
+  fn parse_range(args: &str) -> Option<(u16, u16)> {
+    let (addr, length) = args.split_once(',')?;
+    Some((
+      u16::from_str_radix(addr, 16).ok()?,
+      u16::from_str_radix(length, 16).ok()?,
+    ))
+  }
+
+  fn send(&mut self, payload: &str) -> Result<(), Error> {
+    let packet = format!("${}#{:02x}", payload, Self::checksum(payload.as_bytes()));
+    self.send_raw(packet.as_bytes())
+  }
+
+  /// Queues the data to be sent and sends as much of it as the
+  /// connection accepts, the rest being sent on the next `poll()`.
+  fn send_raw(&mut self, data: &[u8]) -> Result<(), Error> {
+    if self.stream.is_some() {
+      self.output.extend_from_slice(data);
+      self.flush();
+    }
+    Ok(())
+  }
+
+  /// Writes the pending output, a failed write means that the
+  /// debugger went away and so the connection is dropped.
+  fn flush(&mut self) {
+    while !self.output.is_empty() {
+      let stream = match self.stream.as_mut() {
+        Some(stream) => stream,
+        None => return,
+      };
+      match stream.write(&self.output) {
+        Ok(0) => {
+          self.disconnect();
+          return;
+        },
+        Ok(count) => {
+          self.output.drain(..count);
+        },
+        Err(error) if error.kind() == ErrorKind::WouldBlock => return,
+        Err(error) if error.kind() == ErrorKind::Interrupted => (),
+        Err(_) => {
+          self.disconnect();
+          return;
+        },
+      }
+    }
+  }
+
+  fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+  }
+
+  fn encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+  }
+
+  fn decode(value: &str) -> Option<Vec<u8>> {
+    if value.len() & 1 == 1 {
+      return None;
+    }
+    (0..value.len())
+      .step_by(2)
+      .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    thread,
+    time::{Duration, Instant},
+  };
+
+  use super::*;
+  use crate::model::Model;
+
+  /// Connects a client to a server listening on a free port.
+  fn connect() -> (GdbServer, TcpStream) {
+    let mut server = GdbServer::bind(0).unwrap();
+    let client = TcpStream::connect(server.listener.local_addr().unwrap()).unwrap();
+    client.set_nonblocking(true).unwrap();
+    while !server.connected() {
+      server.accept().unwrap();
+      thread::yield_now();
+    }
+    (server, client)
+  }
+
+  /// Sends the data to the server, polling it until the
+  /// expected reply is received by the client.
+  fn request(
+    server: &mut GdbServer,
+    system: &mut GameBoy,
+    client: &mut TcpStream,
+    data: &[u8],
+    reply: &str,
+  ) {
+    client.write_all(data).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut received = vec![];
+    let mut buffer = [0u8; PACKET_SIZE];
+    while received.len() < reply.len() {
+      assert!(Instant::now() < deadline, "No reply for {:?}", reply);
+      server.poll(system).unwrap();
+      match client.read(&mut buffer) {
+        Ok(count) => received.extend_from_slice(&buffer[..count]),
+        Err(error) if error.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+        Err(error) => panic!("{}", error),
+      }
+    }
+    assert_eq!(String::from_utf8_lossy(&received), reply);
+  }
+
+  #[test]
+  fn test_checksum() {
+    assert_eq!(GdbServer::checksum(b""), 0x00);
+    assert_eq!(GdbServer::checksum(b"?"), 0x3f);
+    assert_eq!(GdbServer::checksum(b"m0100,4"), 0x8e);
+    assert_eq!(GdbServer::checksum(&[0xff, 0x02]), 0x01);
+  }
+
+  #[test]
+  fn test_encode() {
+    assert_eq!(GdbServer::encode(&[0x00, 0x1f, 0xa0]), "001fa0");
+    assert_eq!(GdbServer::decode("001fA0"), Some(vec![0x00, 0x1f, 0xa0]));
+    assert_eq!(GdbServer::decode(""), Some(vec![]));
+    assert_eq!(GdbServer::decode("001"), None);
+    assert_eq!(GdbServer::decode("0g"), None);
+    assert_eq!(GdbServer::parse_range("c000,10"), Some((0xc000, 0x10)));
+    assert_eq!(GdbServer::parse_range("c000"), None);
+    assert_eq!(GdbServer::parse_range("10000,1"), None);
+  }
+
+  #[test]
+  fn test_next_packet() {
+    let mut server = GdbServer::bind(0).unwrap();
+    server
+      .buffer
+      .extend_from_slice(b"+$?#3f-junk$m0100,4#8e$g#");
+    assert_eq!(server.next_packet().unwrap().as_deref(), Some("?"));
+    assert_eq!(server.next_packet().unwrap().as_deref(), Some("m0100,4"));
+    // the packet is only taken once its checksum is received
+    assert_eq!(server.next_packet().unwrap(), None);
+    server.buffer.extend_from_slice(b"6");
+    assert_eq!(server.next_packet().unwrap(), None);
+    server.buffer.extend_from_slice(b"7");
+    assert_eq!(server.next_packet().unwrap().as_deref(), Some("g"));
+    assert!(server.buffer.is_empty());
+
+    // packets with a wrong checksum are dropped
+    server.buffer.extend_from_slice(b"$?#00$?#3F");
+    assert_eq!(server.next_packet().unwrap(), None);
+    assert_eq!(server.next_packet().unwrap().as_deref(), Some("?"));
+
+    // packets longer than the maximum size are discarded,
+    // even before they're complete
+    server.buffer.push(b'$');
+    server.buffer.extend_from_slice(&[b'a'; PACKET_SIZE]);
+    assert_eq!(server.next_packet().unwrap(), None);
+    assert!(server.buffer.is_empty());
+    server.buffer.extend_from_slice(b"aaaa#00$?#3f");
+    assert_eq!(server.next_packet().unwrap().as_deref(), Some("?"));
+    server.buffer.push(b'$');
+    server.buffer.extend_from_slice(&[b'a'; PACKET_SIZE - 3]);
+    server.buffer.extend_from_slice(b"#00$?#3f");
+    assert_eq!(server.next_packet().unwrap(), None);
+    assert_eq!(server.next_packet().unwrap().as_deref(), Some("?"));
+
+    // an interrupt request halts the execution
+    assert!(!server.stopped());
+    server.buffer.extend_from_slice(&[0x03]);
+    assert_eq!(server.next_packet().unwrap(), None);
+    assert!(server.stopped());
+  }
+
+  #[test]
+  fn test_poll() {
+    let mut system = GameBoy::new(Model::Dmg);
+    let (mut server, mut client) = connect();
+    assert!(server.stopped());
+
+    // the packets may arrive split and are acknowledged
+    request(&mut server, &mut system, &mut client, b"$?#", "");
+    request(&mut server, &mut system, &mut client, b"3f", "+$S05#b8");
+    request(&mut server, &mut system, &mut client, b"$?#00", "-");
+    request(
+      &mut server,
+      &mut system,
+      &mut client,
+      b"$Z0,150,1#a9",
+      "+$OK#9a",
+    );
+    assert!(server.breakpoints().contains(&0x0150));
+
+    request(
+      &mut server,
+      &mut system,
+      &mut client,
+      b"$QStartNoAckMode#b0",
+      "+$OK#9a",
+    );
+    request(
+      &mut server,
+      &mut system,
+      &mut client,
+      b"$z0,150,1#c9",
+      "$OK#9a",
+    );
+    assert!(server.breakpoints().is_empty());
+
+    drop(client);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.connected() {
+      assert!(Instant::now() < deadline);
+      server.poll(&mut system).unwrap();
+    }
+    assert!(!server.stopped());
+  }
+
+  #[test]
+  fn test_query_features() {
+    let mut system = GameBoy::new(Model::Dmg);
+    let (mut server, mut client) = connect();
+    request(
+      &mut server,
+      &mut system,
+      &mut client,
+      b"$QStartNoAckMode#b0",
+      "+$OK#9a",
+    );
+
+    // ranges past the end of the document (or overflowing) are
+    // clamped to it rather than failing
+    let xml = &TARGET_XML[1..];
+    let reply = format!("l{}", xml);
+    let reply = format!("${}#{:02x}", reply, GdbServer::checksum(reply.as_bytes()));
+    let query = "qXfer:features:read:target.xml:1,ffffffffffffffff";
+    let packet = format!("${}#{:02x}", query, GdbServer::checksum(query.as_bytes()));
+    request(
+      &mut server,
+      &mut system,
+      &mut client,
+      packet.as_bytes(),
+      &reply,
+    );
+
+    let query = "qXfer:features:read:target.xml:ffffffffffffffff,10";
+    let packet = format!("${}#{:02x}", query, GdbServer::checksum(query.as_bytes()));
+    request(
+      &mut server,
+      &mut system,
+      &mut client,
+      packet.as_bytes(),
+      "$l#6c",
+    );
+  }
+}
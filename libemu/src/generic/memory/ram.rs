@@ -17,6 +17,10 @@ impl Ram {
     &self.0
   }
 
+  pub fn inner_mut(&mut self) -> &mut [u8] {
+    &mut self.0
+  }
+
   pub fn set_data(&mut self, data: &[u8]) {
     self.0 = data.to_vec();
   }
@@ -13,6 +13,10 @@ impl Rom {
     Self::default()
   }
 
+  pub fn inner(&self) -> &[u8] {
+    &self.0
+  }
+
   pub fn set_data(&mut self, data: &[u8]) {
     self.0 = data.to_vec();
   }
@@ -20,6 +24,14 @@ impl Rom {
   pub fn reset(&mut self) {
     self.0.clear();
   }
+
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
 }
 
 impl From<&[u8]> for Rom {
@@ -1,9 +1,22 @@
+pub mod archive;
 pub mod boot_data;
 pub mod bus;
 pub mod cartridge;
+pub mod cdl;
+pub mod cheats;
 pub mod error;
 pub mod gb;
+pub mod gdb;
 pub mod generic;
+pub mod memory_map;
+pub mod model;
+pub mod movie;
 pub mod pad;
+pub mod patch;
+pub mod profiler;
+pub mod romdb;
+pub mod sgb;
 pub mod soc;
+pub mod symbols;
+pub mod trace;
 pub mod util;
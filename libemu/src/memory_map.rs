@@ -0,0 +1,159 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Regions of the Game Boy address space, each one backed
+/// by a single device of the system.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MemoryRegion {
+  Boot,
+  Rom0,
+  RomX,
+  Vram,
+  ExternalRam,
+  Wram0,
+  WramX,
+  Echo,
+  Oam,
+  Unusable,
+  Io,
+  Hram,
+  InterruptEnable,
+  Flat,
+}
+
+impl MemoryRegion {
+  /// Resolves the region of the provided address using the
+  /// static memory map (ignoring the boot ROM overlay).
+  pub fn from_addr(addr: u16) -> Self {
+    match addr {
+      0x0000..=0x3fff => MemoryRegion::Rom0,
+      0x4000..=0x7fff => MemoryRegion::RomX,
+      0x8000..=0x9fff => MemoryRegion::Vram,
+      0xa000..=0xbfff => MemoryRegion::ExternalRam,
+      0xc000..=0xcfff => MemoryRegion::Wram0,
+      0xd000..=0xdfff => MemoryRegion::WramX,
+      0xe000..=0xfdff => MemoryRegion::Echo,
+      0xfe00..=0xfe9f => MemoryRegion::Oam,
+      0xfea0..=0xfeff => MemoryRegion::Unusable,
+      0xff00..=0xff7f => MemoryRegion::Io,
+      0xff80..=0xfffe => MemoryRegion::Hram,
+      0xffff => MemoryRegion::InterruptEnable,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      MemoryRegion::Boot => "BOOT",
+      MemoryRegion::Rom0 => "ROM0",
+      MemoryRegion::RomX => "ROMX",
+      MemoryRegion::Vram => "VRAM",
+      MemoryRegion::ExternalRam => "SRAM",
+      MemoryRegion::Wram0 => "WRAM0",
+      MemoryRegion::WramX => "WRAMX",
+      MemoryRegion::Echo => "ECHO",
+      MemoryRegion::Oam => "OAM",
+      MemoryRegion::Unusable => "UNUSABLE",
+      MemoryRegion::Io => "IO",
+      MemoryRegion::Hram => "HRAM",
+      MemoryRegion::InterruptEnable => "IE",
+      MemoryRegion::Flat => "FLAT",
+    }
+  }
+
+  /// Name of the device that handles the accesses to the region.
+  pub fn device(&self) -> &'static str {
+    match self {
+      MemoryRegion::Boot => "Boot ROM",
+      MemoryRegion::Rom0 | MemoryRegion::RomX | MemoryRegion::ExternalRam => "Cartridge",
+      MemoryRegion::Vram | MemoryRegion::Oam => "PPU",
+      MemoryRegion::Wram0 | MemoryRegion::WramX | MemoryRegion::Echo => "WRAM",
+      MemoryRegion::Unusable => "None",
+      MemoryRegion::Io => "I/O Registers",
+      MemoryRegion::Hram => "HRAM",
+      MemoryRegion::InterruptEnable => "CPU",
+      MemoryRegion::Flat => "Flat RAM",
+    }
+  }
+}
+
+impl Display for MemoryRegion {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+/// Description of a mapped region, as currently
+/// present in the address space of the bus.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RegionInfo {
+  pub region: MemoryRegion,
+  pub start: u16,
+  pub end: u16,
+}
+
+impl RegionInfo {
+  pub fn new(region: MemoryRegion, start: u16, end: u16) -> Self {
+    Self { region, start, end }
+  }
+
+  pub fn size(&self) -> usize {
+    self.end as usize - self.start as usize + 1
+  }
+
+  pub fn contains(&self, addr: u16) -> bool {
+    (self.start..=self.end).contains(&addr)
+  }
+}
+
+impl Display for RegionInfo {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "0x{:04x}-0x{:04x} {} ({})",
+      self.start,
+      self.end,
+      self.region.name(),
+      self.region.device()
+    )
+  }
+}
+
+/// The static memory map of the DMG, without the boot ROM overlay.
+pub const MEMORY_MAP: [(MemoryRegion, u16, u16); 12] = [
+  (MemoryRegion::Rom0, 0x0000, 0x3fff),
+  (MemoryRegion::RomX, 0x4000, 0x7fff),
+  (MemoryRegion::Vram, 0x8000, 0x9fff),
+  (MemoryRegion::ExternalRam, 0xa000, 0xbfff),
+  (MemoryRegion::Wram0, 0xc000, 0xcfff),
+  (MemoryRegion::WramX, 0xd000, 0xdfff),
+  (MemoryRegion::Echo, 0xe000, 0xfdff),
+  (MemoryRegion::Oam, 0xfe00, 0xfe9f),
+  (MemoryRegion::Unusable, 0xfea0, 0xfeff),
+  (MemoryRegion::Io, 0xff00, 0xff7f),
+  (MemoryRegion::Hram, 0xff80, 0xfffe),
+  (MemoryRegion::InterruptEnable, 0xffff, 0xffff),
+];
+
+/// Name of the I/O register at the provided address, for
+/// the registers handled by the emulator.
+pub fn io_register_name(addr: u16) -> Option<&'static str> {
+  let name = match addr {
+    0xff00 => "P1",
+    0xff0f => "IF",
+    0xff40 => "LCDC",
+    0xff41 => "STAT",
+    0xff42 => "SCY",
+    0xff43 => "SCX",
+    0xff44 => "LY",
+    0xff45 => "LYC",
+    0xff46 => "DMA",
+    0xff47 => "BGP",
+    0xff48 => "OBP0",
+    0xff49 => "OBP1",
+    0xff4a => "WY",
+    0xff4b => "WX",
+    0xff50 => "BOOT",
+    0xffff => "IE",
+    _ => return None,
+  };
+  Some(name)
+}
@@ -0,0 +1,345 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Size of the boot ROM of the DMG, MGB and SGB models.
+pub const DMG_BOOT_SIZE: usize = 0x100;
+
+/// Size of the boot ROM of the CGB and AGB models, mapped at
+/// 0x0000-0x00FF and 0x0200-0x08FF (around the cartridge header).
+pub const CGB_BOOT_SIZE: usize = 0x900;
+
+/// Values of the IO registers left by the boot ROM of the DMG,
+/// written in order (the APU is powered on through NR52 before
+/// its channels are set), as documented in Pan Docs.
+pub const DMG_BOOT_IO: [(u16, u8); 40] = [
+  (0xff00, 0xcf), // P1
+  (0xff01, 0x00), // SB
+  (0xff02, 0x7e), // SC
+  (0xff04, 0xab), // DIV
+  (0xff05, 0x00), // TIMA
+  (0xff06, 0x00), // TMA
+  (0xff07, 0xf8), // TAC
+  (0xff26, 0xf1), // NR52
+  (0xff10, 0x80), // NR10
+  (0xff11, 0xbf), // NR11
+  (0xff12, 0xf3), // NR12
+  (0xff13, 0xff), // NR13
+  (0xff14, 0xbf), // NR14
+  (0xff16, 0x3f), // NR21
+  (0xff17, 0x00), // NR22
+  (0xff18, 0xff), // NR23
+  (0xff19, 0xbf), // NR24
+  (0xff1a, 0x7f), // NR30
+  (0xff1b, 0xff), // NR31
+  (0xff1c, 0x9f), // NR32
+  (0xff1d, 0xff), // NR33
+  (0xff1e, 0xbf), // NR34
+  (0xff20, 0xff), // NR41
+  (0xff21, 0x00), // NR42
+  (0xff22, 0x00), // NR43
+  (0xff23, 0xbf), // NR44
+  (0xff24, 0x77), // NR50
+  (0xff25, 0xf3), // NR51
+  (0xff40, 0x91), // LCDC
+  (0xff41, 0x85), // STAT
+  (0xff42, 0x00), // SCY
+  (0xff43, 0x00), // SCX
+  (0xff45, 0x00), // LYC
+  (0xff47, 0xfc), // BGP
+  (0xff48, 0xff), // OBP0
+  (0xff49, 0xff), // OBP1
+  (0xff4a, 0x00), // WY
+  (0xff4b, 0x00), // WX
+  (0xff0f, 0xe1), // IF
+  (0xffff, 0x00), // IE
+];
+
+/// Values of the CGB only IO registers left by the boot ROM of the
+/// CGB and AGB, HDMA5 is left alone as writing it starts a transfer.
+pub const CGB_BOOT_IO: [(u16, u8); 9] = [
+  (0xff4d, 0x7e), // KEY1
+  (0xff4f, 0xfe), // VBK
+  (0xff51, 0xff), // HDMA1
+  (0xff52, 0xff), // HDMA2
+  (0xff53, 0xff), // HDMA3
+  (0xff54, 0xff), // HDMA4
+  (0xff68, 0x80), // BCPS
+  (0xff6a, 0x80), // OCPS
+  (0xff70, 0xf8), // SVBK
+];
+
+/// Hardware model of the Game Boy being emulated.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Model {
+  /// Early DMG with the original boot ROM.
+  Dmg0,
+  #[default]
+  Dmg,
+  /// Game Boy Pocket (and Light).
+  Mgb,
+  Sgb,
+  Sgb2,
+  Cgb,
+  /// Game Boy Advance running Game Boy games.
+  Agb,
+}
+
+/// Values of the CPU registers left by the boot ROM.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BootRegisters {
+  pub af: u16,
+  pub bc: u16,
+  pub de: u16,
+  pub hl: u16,
+}
+
+impl Model {
+  pub const ALL: [Model; 7] = [
+    Model::Dmg0,
+    Model::Dmg,
+    Model::Mgb,
+    Model::Sgb,
+    Model::Sgb2,
+    Model::Cgb,
+    Model::Agb,
+  ];
+
+  /// Parses the name of the model (eg: `dmg`, `cgb`), ignoring case.
+  pub fn from_name(name: &str) -> Option<Self> {
+    Self::ALL
+      .iter()
+      .find(|model| model.description().eq_ignore_ascii_case(name))
+      .copied()
+  }
+
+  pub fn description(&self) -> &'static str {
+    match self {
+      Model::Dmg0 => "DMG0",
+      Model::Dmg => "DMG",
+      Model::Mgb => "MGB",
+      Model::Sgb => "SGB",
+      Model::Sgb2 => "SGB2",
+      Model::Cgb => "CGB",
+      Model::Agb => "AGB",
+    }
+  }
+
+  pub fn is_cgb(&self) -> bool {
+    matches!(self, Model::Cgb | Model::Agb)
+  }
+
+  pub fn is_sgb(&self) -> bool {
+    matches!(self, Model::Sgb | Model::Sgb2)
+  }
+
+  /// If the model has the hardware bugs fixed by the CGB
+  /// (eg: the OAM corruption and the STAT write bugs).
+  pub fn has_dmg_quirks(&self) -> bool {
+    !self.is_cgb()
+  }
+
+  /// Size of the work RAM, the CGB has 8 banks of 4 KB
+  /// (selected through SVBK) instead of 2.
+  pub fn wram_size(&self) -> usize {
+    if self.is_cgb() {
+      0x8000
+    } else {
+      0x2000
+    }
+  }
+
+  pub fn boot_size(&self) -> usize {
+    if self.is_cgb() {
+      CGB_BOOT_SIZE
+    } else {
+      DMG_BOOT_SIZE
+    }
+  }
+
+  /// Registers left by the boot ROM of the model, which depend on
+  /// the header checksum (DMG and MGB) and on the game supporting
+  /// the CGB (CGB and AGB), the DMG compatibility mode values of
+  /// the CGB are used with B cleared (it's a hash of the title).
+  pub fn boot_registers(&self, header_checksum: u8, cgb_game: bool) -> BootRegisters {
+    // the H and C flags are set unless the checksum is zero
+    let flags = if header_checksum == 0x00 { 0x80 } else { 0xb0 };
+    match self {
+      Model::Dmg0 => BootRegisters {
+        af: 0x0100,
+        bc: 0xff13,
+        de: 0x00c1,
+        hl: 0x8403,
+      },
+      Model::Dmg => BootRegisters {
+        af: 0x0100 | flags,
+        bc: 0x0013,
+        de: 0x00d8,
+        hl: 0x014d,
+      },
+      Model::Mgb => BootRegisters {
+        af: 0xff00 | flags,
+        bc: 0x0013,
+        de: 0x00d8,
+        hl: 0x014d,
+      },
+      Model::Sgb => BootRegisters {
+        af: 0x0100,
+        bc: 0x0014,
+        de: 0x0000,
+        hl: 0xc060,
+      },
+      Model::Sgb2 => BootRegisters {
+        af: 0xff00,
+        bc: 0x0014,
+        de: 0x0000,
+        hl: 0xc060,
+      },
+      Model::Cgb | Model::Agb => {
+        let mut registers = if cgb_game {
+          BootRegisters {
+            af: 0x1180,
+            bc: 0x0000,
+            de: 0xff56,
+            hl: 0x000d,
+          }
+        } else {
+          BootRegisters {
+            af: 0x1180,
+            bc: 0x0000,
+            de: 0x0008,
+            hl: 0x007c,
+          }
+        };
+        // the AGB boot ROM increments B, clearing the Z flag
+        if *self == Model::Agb {
+          registers.af = 0x1100;
+          registers.bc += 0x0100;
+        }
+        registers
+      },
+    }
+  }
+
+  /// Values of the IO registers left by the boot ROM of the model,
+  /// the DIV value depends on how long the boot ROM takes to run.
+  pub fn boot_io(&self) -> Vec<(u16, u8)> {
+    let mut io: Vec<(u16, u8)> = DMG_BOOT_IO
+      .iter()
+      .map(|&(addr, value)| match (addr, self) {
+        (0xff02, Model::Cgb | Model::Agb) => (addr, 0x7f),
+        (0xff04, Model::Dmg0) => (addr, 0x18),
+        (0xff04, Model::Cgb | Model::Agb) => (addr, 0x1e),
+        // the SGB boot ROM plays no sound, leaving channel 1 off
+        (0xff26, Model::Sgb | Model::Sgb2) => (addr, 0xf0),
+        _ => (addr, value),
+      })
+      .collect();
+    if self.is_cgb() {
+      io.extend_from_slice(&CGB_BOOT_IO);
+    }
+    io
+  }
+}
+
+impl Display for Model {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.description())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn io_value(model: Model, addr: u16) -> Option<u8> {
+    model
+      .boot_io()
+      .iter()
+      .find(|&&(io_addr, _)| io_addr == addr)
+      .map(|&(_, value)| value)
+  }
+
+  #[test]
+  fn test_from_name() {
+    for model in Model::ALL {
+      assert_eq!(Model::from_name(model.description()), Some(model));
+    }
+    assert_eq!(Model::from_name("cgb"), Some(Model::Cgb));
+    assert_eq!(Model::from_name("Sgb2"), Some(Model::Sgb2));
+    assert_eq!(Model::from_name("gba"), None);
+    assert_eq!(Model::default(), Model::Dmg);
+  }
+
+  #[test]
+  fn test_features() {
+    let table = [
+      (Model::Dmg0, false, false, 0x2000, DMG_BOOT_SIZE),
+      (Model::Dmg, false, false, 0x2000, DMG_BOOT_SIZE),
+      (Model::Mgb, false, false, 0x2000, DMG_BOOT_SIZE),
+      (Model::Sgb, false, true, 0x2000, DMG_BOOT_SIZE),
+      (Model::Sgb2, false, true, 0x2000, DMG_BOOT_SIZE),
+      (Model::Cgb, true, false, 0x8000, CGB_BOOT_SIZE),
+      (Model::Agb, true, false, 0x8000, CGB_BOOT_SIZE),
+    ];
+    for (model, cgb, sgb, wram_size, boot_size) in table {
+      assert_eq!(model.is_cgb(), cgb, "{}", model);
+      assert_eq!(model.is_sgb(), sgb, "{}", model);
+      assert_eq!(model.has_dmg_quirks(), !cgb, "{}", model);
+      assert_eq!(model.wram_size(), wram_size, "{}", model);
+      assert_eq!(model.boot_size(), boot_size, "{}", model);
+    }
+  }
+
+  #[test]
+  fn test_boot_registers() {
+    let table = [
+      (Model::Dmg0, 0x00, false, [0x0100, 0xff13, 0x00c1, 0x8403]),
+      (Model::Dmg, 0x00, false, [0x0180, 0x0013, 0x00d8, 0x014d]),
+      (Model::Dmg, 0x3c, false, [0x01b0, 0x0013, 0x00d8, 0x014d]),
+      (Model::Mgb, 0x3c, false, [0xffb0, 0x0013, 0x00d8, 0x014d]),
+      (Model::Sgb, 0x3c, false, [0x0100, 0x0014, 0x0000, 0xc060]),
+      (Model::Sgb2, 0x3c, false, [0xff00, 0x0014, 0x0000, 0xc060]),
+      (Model::Cgb, 0x3c, true, [0x1180, 0x0000, 0xff56, 0x000d]),
+      (Model::Cgb, 0x3c, false, [0x1180, 0x0000, 0x0008, 0x007c]),
+      (Model::Agb, 0x3c, true, [0x1100, 0x0100, 0xff56, 0x000d]),
+      (Model::Agb, 0x3c, false, [0x1100, 0x0100, 0x0008, 0x007c]),
+    ];
+    for (model, header_checksum, cgb_game, [af, bc, de, hl]) in table {
+      assert_eq!(
+        model.boot_registers(header_checksum, cgb_game),
+        BootRegisters { af, bc, de, hl },
+        "{} {:02x} {}",
+        model,
+        header_checksum,
+        cgb_game
+      );
+    }
+  }
+
+  #[test]
+  fn test_boot_io() {
+    let table = [
+      (Model::Dmg0, 0x7e, 0x18, 0xf1, false),
+      (Model::Dmg, 0x7e, 0xab, 0xf1, false),
+      (Model::Mgb, 0x7e, 0xab, 0xf1, false),
+      (Model::Sgb, 0x7e, 0xab, 0xf0, false),
+      (Model::Sgb2, 0x7e, 0xab, 0xf0, false),
+      (Model::Cgb, 0x7f, 0x1e, 0xf1, true),
+      (Model::Agb, 0x7f, 0x1e, 0xf1, true),
+    ];
+    for (model, sc, div, nr52, cgb) in table {
+      assert_eq!(io_value(model, 0xff02), Some(sc), "{}", model);
+      assert_eq!(io_value(model, 0xff04), Some(div), "{}", model);
+      assert_eq!(io_value(model, 0xff26), Some(nr52), "{}", model);
+      assert_eq!(io_value(model, 0xff40), Some(0x91), "{}", model);
+      for (addr, value) in CGB_BOOT_IO {
+        assert_eq!(io_value(model, addr), cgb.then_some(value), "{}", model);
+      }
+    }
+
+    // APU is powered on before its channels are set
+    let io = Model::Dmg.boot_io();
+    let nr52 = io.iter().position(|&(addr, _)| addr == 0xff26).unwrap();
+    let nr10 = io.iter().position(|&(addr, _)| addr == 0xff10).unwrap();
+    assert!(nr52 < nr10);
+  }
+}
@@ -0,0 +1,419 @@
+use std::fmt::Write as _;
+
+use crate::{
+  error::Error,
+  model::Model,
+  pad::{PadKey, MAX_PLAYERS},
+  util::{read_file, write_file},
+};
+
+/// Version of the movie file format, stated in its first line.
+pub const MOVIE_VERSION: u32 = 1;
+
+/// Characters of the buttons in the input log, in the same
+/// order used by the BizHawk (BK2) Game Boy input logs.
+pub const BUTTON_CHARS: [(char, PadKey); 8] = [
+  ('U', PadKey::Up),
+  ('D', PadKey::Down),
+  ('L', PadKey::Left),
+  ('R', PadKey::Right),
+  ('S', PadKey::Start),
+  ('s', PadKey::Select),
+  ('B', PadKey::B),
+  ('A', PadKey::A),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MovieFrame {
+  /// Buttons held by each player during the frame,
+  /// as laid out by `PadKey::mask()`.
+  pub buttons: [u8; MAX_PLAYERS],
+  /// Hash of the emulation state at the end of the frame.
+  pub hash: Option<u32>,
+}
+
+/// Input recording of a game, from power on, made of the buttons
+/// held on each frame, together with the hash of the state at the
+/// end of it so that desyncs can be detected on playback.
+///
+/// Movies are stored as text, with a header identifying the ROM
+/// (by its SHA-1), the model and the cartridge RAM at power on
+/// (always clean, as the save file is not loaded), followed by
+/// one line per frame with a `UDLRSsBA` group per player (`.`
+/// for released buttons) and the (hex) state hash, eg:
+///
+/// ```text
+/// gbremu-movie 1
+/// rom 2a1c7f4e9b0d3c5a8e6f1b2d4c7a9e0f3b5d8c1a
+/// model DMG
+/// sram clean
+/// players 1
+/// |........|3f0a12bc
+/// |U......A|0b7c9d21
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Movie {
+  rom_sha1: String,
+  model: Model,
+  players: usize,
+  frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+  pub fn new(rom_sha1: &str, model: Model, players: usize) -> Self {
+    Self {
+      rom_sha1: rom_sha1.to_lowercase(),
+      model,
+      players: players.clamp(1, MAX_PLAYERS),
+      frames: vec![],
+    }
+  }
+
+  pub fn rom_sha1(&self) -> &str {
+    &self.rom_sha1
+  }
+
+  pub fn model(&self) -> Model {
+    self.model
+  }
+
+  pub fn players(&self) -> usize {
+    self.players
+  }
+
+  pub fn frames(&self) -> &[MovieFrame] {
+    &self.frames
+  }
+
+  pub fn frame(&self, index: usize) -> Option<&MovieFrame> {
+    self.frames.get(index)
+  }
+
+  pub fn push(&mut self, frame: MovieFrame) {
+    self.frames.push(frame);
+  }
+
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+
+  pub fn from_text(text: &str) -> Result<Self, Error> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    let invalid = |line: &str| Error::CustomError(format!("Invalid movie line: {}", line));
+
+    let signature = lines.next().unwrap_or_default();
+    match signature.split_once(' ') {
+      Some(("gbremu-movie", version)) if version.parse() == Ok(MOVIE_VERSION) => (),
+      _ => return Err(Error::CustomError(String::from("Invalid movie file"))),
+    }
+
+    let mut movie = Self::new("", Model::default(), 1);
+    for line in lines {
+      if line.starts_with('|') {
+        movie
+          .frames
+          .push(movie.parse_frame(line).ok_or_else(|| invalid(line))?);
+        continue;
+      }
+      let (name, value) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+      match name {
+        "rom" => movie.rom_sha1 = value.to_lowercase(),
+        "model" => movie.model = Model::from_name(value).ok_or_else(|| invalid(line))?,
+        "sram" if value == "clean" => (),
+        "players" => {
+          movie.players = value
+            .parse()
+            .ok()
+            .filter(|players| (1..=MAX_PLAYERS).contains(players))
+            .ok_or_else(|| invalid(line))?
+        },
+        _ => return Err(invalid(line)),
+      }
+    }
+    Ok(movie)
+  }
+
+  fn parse_frame(&self, line: &str) -> Option<MovieFrame> {
+    let mut groups = line.split('|').skip(1);
+    let mut frame = MovieFrame::default();
+    for buttons in frame.buttons.iter_mut().take(self.players) {
+      let group = groups.next()?;
+      if group.len() != BUTTON_CHARS.len() {
+        return None;
+      }
+      for (value, (button, key)) in group.chars().zip(BUTTON_CHARS) {
+        match value {
+          '.' => (),
+          _ if value == button => *buttons |= key.mask(),
+          _ => return None,
+        }
+      }
+    }
+    frame.hash = match groups.next()? {
+      "" => None,
+      hash => Some(u32::from_str_radix(hash, 16).ok()?),
+    };
+    Some(frame)
+  }
+
+  pub fn to_text(&self) -> String {
+    let mut text = String::new();
+    writeln!(text, "gbremu-movie {}", MOVIE_VERSION).unwrap();
+    writeln!(text, "rom {}", self.rom_sha1).unwrap();
+    writeln!(text, "model {}", self.model).unwrap();
+    writeln!(text, "sram clean").unwrap();
+    writeln!(text, "players {}", self.players).unwrap();
+    for frame in &self.frames {
+      for buttons in frame.buttons.iter().take(self.players) {
+        text.push('|');
+        for (button, key) in BUTTON_CHARS {
+          text.push(if buttons & key.mask() != 0 {
+            button
+          } else {
+            '.'
+          });
+        }
+      }
+      text.push('|');
+      if let Some(hash) = frame.hash {
+        write!(text, "{:08x}", hash).unwrap();
+      }
+      text.push('\n');
+    }
+    text
+  }
+
+  pub fn from_file(path: &str) -> Result<Self, Error> {
+    let text = String::from_utf8(read_file(path)?)
+      .map_err(|_| Error::CustomError(format!("Invalid movie file: {}", path)))?;
+    Self::from_text(&text)
+  }
+
+  pub fn save_file(&self, path: &str) -> Result<(), Error> {
+    write_file(path, self.to_text().as_bytes())
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MovieMode {
+  Recording,
+  Playing,
+}
+
+/// Movie being recorded or played, on recording the input is
+/// latched and only applied at the start of each frame, so that
+/// playback reproduces it exactly.
+#[derive(Clone, Debug)]
+pub struct MovieSession {
+  movie: Movie,
+  mode: MovieMode,
+  /// Index of the frame being emulated.
+  frame: usize,
+  /// Buttons to be held from the start of the next frame.
+  input: [u8; MAX_PLAYERS],
+  /// First frame whose state didn't match the recorded one.
+  desync: Option<usize>,
+}
+
+impl MovieSession {
+  pub fn new(movie: Movie, mode: MovieMode) -> Self {
+    Self {
+      movie,
+      mode,
+      frame: 0,
+      input: [0u8; MAX_PLAYERS],
+      desync: None,
+    }
+  }
+
+  pub fn movie(&self) -> &Movie {
+    &self.movie
+  }
+
+  pub fn into_movie(self) -> Movie {
+    self.movie
+  }
+
+  pub fn mode(&self) -> MovieMode {
+    self.mode
+  }
+
+  pub fn frame(&self) -> usize {
+    self.frame
+  }
+
+  pub fn desync(&self) -> Option<usize> {
+    self.desync
+  }
+
+  /// If the playback went through all of the frames.
+  pub fn finished(&self) -> bool {
+    self.mode == MovieMode::Playing && self.frame >= self.movie.len()
+  }
+
+  pub fn set_key(&mut self, player: usize, key: PadKey, value: bool) {
+    if let Some(buttons) = self.input.get_mut(player) {
+      if value {
+        *buttons |= key.mask();
+      } else {
+        *buttons &= !key.mask();
+      }
+    }
+  }
+
+  /// Buttons to be held during the current frame.
+  pub fn buttons(&self) -> [u8; MAX_PLAYERS] {
+    match self.mode {
+      MovieMode::Recording => self.input,
+      MovieMode::Playing => self
+        .movie
+        .frame(self.frame)
+        .map(|frame| frame.buttons)
+        .unwrap_or_default(),
+    }
+  }
+
+  /// Ends the current frame, which held the provided buttons and
+  /// finished with the provided state hash, either recording it or
+  /// checking it against the recorded one. Returns true for the
+  /// first frame found to be out of sync.
+  pub fn end_frame(&mut self, buttons: [u8; MAX_PLAYERS], hash: u32) -> bool {
+    let mut desync = false;
+    match self.mode {
+      MovieMode::Recording => self.movie.push(MovieFrame {
+        buttons,
+        hash: Some(hash),
+      }),
+      MovieMode::Playing => {
+        let expected = self.movie.frame(self.frame).and_then(|frame| frame.hash);
+        if self.desync.is_none() && expected.is_some_and(|expected| expected != hash) {
+          self.desync = Some(self.frame);
+          desync = true;
+        }
+      },
+    }
+    self.frame += 1;
+    desync
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SHA1: &str = "2a1c7f4e9b0d3c5a8e6f1b2d4c7a9e0f3b5d8c1a";
+
+  fn movie() -> Movie {
+    let mut movie = Movie::new(&SHA1.to_uppercase(), Model::Cgb, 2);
+    movie.push(MovieFrame {
+      buttons: [0x00, 0x00, 0x00, 0x00],
+      hash: Some(0x3f0a12bc),
+    });
+    movie.push(MovieFrame {
+      buttons: [
+        PadKey::Up.mask() | PadKey::A.mask(),
+        PadKey::Select.mask(),
+        0x00,
+        0x00,
+      ],
+      hash: None,
+    });
+    movie.push(MovieFrame {
+      buttons: [0xff, 0x00, 0x00, 0x00],
+      hash: Some(0x00000001),
+    });
+    movie
+  }
+
+  #[test]
+  fn test_text() {
+    let movie = movie();
+    let text = movie.to_text();
+    assert_eq!(
+      text,
+      format!(
+        "gbremu-movie 1\nrom {}\nmodel CGB\nsram clean\nplayers 2\n\
+        |........|........|3f0a12bc\n\
+        |U......A|.....s..|\n\
+        |UDLRSsBA|........|00000001\n",
+        SHA1
+      )
+    );
+    assert_eq!(Movie::from_text(&text).unwrap(), movie);
+  }
+
+  #[test]
+  fn test_text_defaults() {
+    // all of the header lines but the signature are optional
+    let movie = Movie::from_text("gbremu-movie 1\n\n|U.......|\n").unwrap();
+    assert_eq!(movie.rom_sha1(), "");
+    assert_eq!(movie.model(), Model::Dmg);
+    assert_eq!(movie.players(), 1);
+    assert_eq!(
+      movie.frames(),
+      &[MovieFrame {
+        buttons: [PadKey::Up.mask(), 0x00, 0x00, 0x00],
+        hash: None,
+      }]
+    );
+  }
+
+  #[test]
+  fn test_text_invalid() {
+    for text in [
+      "",
+      "gbremu-movie\n",
+      "gbremu-movie 2\n",
+      "bk2-movie 1\n",
+      "gbremu-movie 1\nmodel NES\n",
+      "gbremu-movie 1\nsram dirty\n",
+      "gbremu-movie 1\nplayers 0\n",
+      "gbremu-movie 1\nplayers 5\n",
+      "gbremu-movie 1\nspeed 2\n",
+      "gbremu-movie 1\nrom\n",
+      "gbremu-movie 1\n|U......|\n",
+      "gbremu-movie 1\n|A.......|\n",
+      "gbremu-movie 1\n|........|xyz\n",
+      "gbremu-movie 1\nplayers 2\n|........|\n",
+    ] {
+      assert!(Movie::from_text(text).is_err(), "{:?}", text);
+    }
+  }
+
+  #[test]
+  fn test_recording() {
+    let mut session = MovieSession::new(Movie::new(SHA1, Model::Dmg, 1), MovieMode::Recording);
+    session.set_key(0, PadKey::A, true);
+    // the input is only latched, the keys are not held until applied
+    assert_eq!(session.buttons()[0], PadKey::A.mask());
+    assert!(!session.end_frame([0x00; MAX_PLAYERS], 0x10));
+    session.set_key(0, PadKey::A, false);
+    session.set_key(4, PadKey::B, true);
+    assert!(!session.end_frame([PadKey::A.mask(), 0x00, 0x00, 0x00], 0x20));
+    assert!(!session.finished());
+    assert_eq!(session.frame(), 2);
+
+    let movie = session.into_movie();
+    assert_eq!(movie.len(), 2);
+    assert_eq!(movie.frame(0).unwrap().hash, Some(0x10));
+    assert_eq!(movie.frame(1).unwrap().buttons[0], PadKey::A.mask());
+  }
+
+  #[test]
+  fn test_playing() {
+    let mut session = MovieSession::new(movie(), MovieMode::Playing);
+    assert_eq!(session.buttons(), [0x00; MAX_PLAYERS]);
+    assert!(!session.end_frame(session.buttons(), 0x3f0a12bc));
+    assert_eq!(session.buttons()[1], PadKey::Select.mask());
+    // frames without a hash are never out of sync
+    assert!(!session.end_frame(session.buttons(), 0x12345678));
+    assert!(session.end_frame(session.buttons(), 0x00000002));
+    assert_eq!(session.desync(), Some(2));
+    assert!(session.finished());
+    assert_eq!(session.buttons(), [0x00; MAX_PLAYERS]);
+  }
+}
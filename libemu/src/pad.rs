@@ -1,14 +1,25 @@
 use log::warn;
 
-use crate::generic::{address::Address, device::Device};
+use crate::{
+  generic::{address::Address, device::Device, shared::Shared},
+  sgb::Sgb,
+};
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum PadSelection {
   None,
   Action,
   Direction,
+  /// Both groups selected (P14 and P15 low), with the lines
+  /// low if a button of any of the groups is pressed.
+  Both,
 }
 
+/// Maximum number of controllers, as connected to a SGB
+/// (through a multitap) for multiplayer games.
+pub const MAX_PLAYERS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum PadKey {
   Up,
   Down,
@@ -20,61 +31,190 @@ pub enum PadKey {
   B,
 }
 
+impl PadKey {
+  pub const ALL: [PadKey; 8] = [
+    PadKey::A,
+    PadKey::B,
+    PadKey::Select,
+    PadKey::Start,
+    PadKey::Right,
+    PadKey::Left,
+    PadKey::Up,
+    PadKey::Down,
+  ];
+
+  /// Bit of the key in the button state bitmask, with the action
+  /// buttons in the lower nibble and the directions in the upper
+  /// one, in the same order as their P1 lines.
+  pub fn mask(&self) -> u8 {
+    match self {
+      PadKey::A => 0x01,
+      PadKey::B => 0x02,
+      PadKey::Select => 0x04,
+      PadKey::Start => 0x08,
+      PadKey::Right => 0x10,
+      PadKey::Left => 0x20,
+      PadKey::Up => 0x40,
+      PadKey::Down => 0x80,
+    }
+  }
+}
+
+/// State of the buttons of a single controller.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Controller {
+  pub up: bool,
+  pub down: bool,
+  pub left: bool,
+  pub right: bool,
+  pub start: bool,
+  pub select: bool,
+  pub b: bool,
+  pub a: bool,
+}
+
+impl Controller {
+  pub fn key(&self, key: PadKey) -> bool {
+    match key {
+      PadKey::Up => self.up,
+      PadKey::Down => self.down,
+      PadKey::Left => self.left,
+      PadKey::Right => self.right,
+      PadKey::Start => self.start,
+      PadKey::Select => self.select,
+      PadKey::A => self.a,
+      PadKey::B => self.b,
+    }
+  }
+
+  /// Raw state of the buttons (1 = pressed), as laid out by `PadKey::mask()`.
+  pub fn buttons(&self) -> u8 {
+    PadKey::ALL
+      .iter()
+      .filter(|key| self.key(**key))
+      .fold(0x00, |value, key| value | key.mask())
+  }
+
+  pub fn set_key(&mut self, key: PadKey, value: bool) {
+    match key {
+      PadKey::Up => self.up = value,
+      PadKey::Down => self.down = value,
+      PadKey::Left => self.left = value,
+      PadKey::Right => self.right = value,
+      PadKey::Start => self.start = value,
+      PadKey::Select => self.select = value,
+      PadKey::A => self.a = value,
+      PadKey::B => self.b = value,
+    }
+  }
+
+  /// Lower nibble of P1 for the action buttons (0 = pressed).
+  fn action_bits(&self) -> u8 {
+    (if self.a { 0x00 } else { 0x01 })
+      | if self.b { 0x00 } else { 0x02 }
+      | if self.select { 0x00 } else { 0x04 }
+      | if self.start { 0x00 } else { 0x08 }
+  }
+
+  /// Lower nibble of P1 for the direction buttons (0 = pressed).
+  fn direction_bits(&self) -> u8 {
+    (if self.right { 0x00 } else { 0x01 })
+      | if self.left { 0x00 } else { 0x02 }
+      | if self.up { 0x00 } else { 0x04 }
+      | if self.down { 0x00 } else { 0x08 }
+  }
+}
+
 pub struct Pad {
-  up: bool,
-  down: bool,
-  left: bool,
-  right: bool,
-  start: bool,
-  select: bool,
-  b: bool,
-  a: bool,
+  controllers: [Controller; MAX_PLAYERS],
   selection: PadSelection,
   int_pad: bool,
+  /// Lower nibble of P1 (0 = low), the interrupt is requested
+  /// when any of them goes from high to low.
+  lines: u8,
+
+  /// Number of controllers read by the game, as requested
+  /// through the SGB MLT_REQ command (1, 2 or 4).
+  player_count: usize,
+  /// Controller currently read through P1 (the JOYP ID),
+  /// cycled on every rising edge of P15.
+  player: usize,
+  p15: bool,
+
+  /// SGB receiving the command packets sent through P1,
+  /// when running on one of the SGB models.
+  sgb: Option<Shared<Sgb>>,
 }
 
 impl Pad {
   pub fn new() -> Self {
     Self {
-      down: false,
-      up: false,
-      left: false,
-      right: false,
-      start: false,
-      select: false,
-      b: false,
-      a: false,
+      controllers: [Controller::default(); MAX_PLAYERS],
       selection: PadSelection::None,
       int_pad: false,
+      lines: 0x0f,
+      player_count: 1,
+      player: 0,
+      p15: true,
+      sgb: None,
     }
   }
 
-  pub fn key_press(&mut self, key: PadKey) {
-    match key {
-      PadKey::Up => self.up = true,
-      PadKey::Down => self.down = true,
-      PadKey::Left => self.left = true,
-      PadKey::Right => self.right = true,
-      PadKey::Start => self.start = true,
-      PadKey::Select => self.select = true,
-      PadKey::A => self.a = true,
-      PadKey::B => self.b = true,
-    }
+  /// Releases all of the buttons and clears the selection,
+  /// keeping the SGB the pad is connected to.
+  pub fn reset(&mut self) {
+    let sgb = self.sgb.take();
+    *self = Self::new();
+    self.sgb = sgb;
+  }
 
-    self.int_pad = true;
+  pub fn key_press(&mut self, key: PadKey) {
+    self.key_press_player(0, key);
   }
 
   pub fn key_lift(&mut self, key: PadKey) {
-    match key {
-      PadKey::Up => self.up = false,
-      PadKey::Down => self.down = false,
-      PadKey::Left => self.left = false,
-      PadKey::Right => self.right = false,
-      PadKey::Start => self.start = false,
-      PadKey::Select => self.select = false,
-      PadKey::A => self.a = false,
-      PadKey::B => self.b = false,
+    self.key_lift_player(0, key);
+  }
+
+  pub fn key_press_player(&mut self, player: usize, key: PadKey) {
+    match self.controllers.get_mut(player) {
+      Some(controller) => controller.set_key(key, true),
+      None => warn!("Invalid player {} for key press", player),
+    }
+    self.update_lines();
+  }
+
+  pub fn key_lift_player(&mut self, player: usize, key: PadKey) {
+    match self.controllers.get_mut(player) {
+      Some(controller) => controller.set_key(key, false),
+      None => warn!("Invalid player {} for key lift", player),
     }
+    self.update_lines();
+  }
+
+  /// Raw state of the buttons of the first controller, meant
+  /// for input display (see `PadKey::mask()` for the layout).
+  pub fn buttons(&self) -> u8 {
+    self.controllers[0].buttons()
+  }
+
+  pub fn buttons_player(&self, player: usize) -> u8 {
+    self
+      .controllers
+      .get(player)
+      .map_or(0x00, |controller| controller.buttons())
+  }
+
+  pub fn controller(&self, player: usize) -> Option<&Controller> {
+    self.controllers.get(player)
+  }
+
+  pub fn player_count(&self) -> usize {
+    self.player_count
+  }
+
+  pub fn player(&self) -> usize {
+    self.player
   }
 
   pub fn int_pad(&self) -> bool {
@@ -88,6 +228,58 @@ impl Pad {
   pub fn ack_pad(&mut self) {
     self.set_int_pad(false);
   }
+
+  pub fn set_sgb(&mut self, sgb: Option<Shared<Sgb>>) {
+    self.sgb = sgb;
+  }
+
+  /// Lower nibble of P1 for the current selection, a line is
+  /// low when a button of any of the selected groups is pressed.
+  fn selected_lines(&self) -> u8 {
+    let controller = &self.controllers[self.player];
+    match self.selection {
+      PadSelection::Action => controller.action_bits(),
+      PadSelection::Direction => controller.direction_bits(),
+      PadSelection::Both => controller.action_bits() & controller.direction_bits(),
+      // with no buttons selected the multiplayer SGB returns the
+      // ID of the current controller (0xF for the first one)
+      PadSelection::None if self.player_count > 1 => 0x0f - self.player as u8,
+      PadSelection::None => 0x0f,
+    }
+  }
+
+  /// Requests the joypad interrupt when any of the lines visible
+  /// through the selection goes from high to low, as it happens
+  /// on key presses but also on selecting a group with a button
+  /// already pressed.
+  fn update_lines(&mut self) {
+    let lines = match self.selection {
+      PadSelection::None => 0x0f,
+      _ => self.selected_lines(),
+    };
+    if self.lines & !lines & 0x0f != 0x00 {
+      self.int_pad = true;
+    }
+    self.lines = lines;
+  }
+
+  /// Updates the multiplayer state after a write to P1, the ID is
+  /// reset by MLT_REQ and then cycled on the rising edges of P15.
+  fn update_player(&mut self, value: u8) {
+    let player_count = match &self.sgb {
+      Some(sgb) => sgb.borrow().player_count(),
+      None => 1,
+    };
+    if player_count != self.player_count {
+      self.player_count = player_count;
+      self.player = 0;
+    }
+    let p15 = value & 0x20 == 0x20;
+    if p15 && !self.p15 && self.player_count > 1 {
+      self.player = (self.player + 1) % self.player_count;
+    }
+    self.p15 = p15;
+  }
 }
 
 impl Default for Pad {
@@ -101,25 +293,12 @@ impl Address for Pad {
     match addr {
       // 0xFF00 — P1/JOYP: Joypad
       0xff00 => {
-        let mut value = match self.selection {
-          PadSelection::Action => {
-            (if self.a { 0x00 } else { 0x01 }
-              | if self.b { 0x00 } else { 0x02 }
-              | if self.select { 0x00 } else { 0x04 }
-              | if self.start { 0x00 } else { 0x08 })
-          },
-          PadSelection::Direction => {
-            (if self.right { 0x00 } else { 0x01 }
-              | if self.left { 0x00 } else { 0x02 }
-              | if self.up { 0x00 } else { 0x04 }
-              | if self.down { 0x00 } else { 0x08 })
-          },
-          PadSelection::None => 0x0f,
-        };
+        let mut value = self.selected_lines();
         value |= match self.selection {
           PadSelection::Action => 0x10,
           PadSelection::Direction => 0x20,
           PadSelection::None => 0x30,
+          PadSelection::Both => 0x00,
         };
         value
       },
@@ -138,8 +317,13 @@ impl Address for Pad {
           0x10 => PadSelection::Action,
           0x20 => PadSelection::Direction,
           0x30 => PadSelection::None,
-          _ => PadSelection::None,
+          _ => PadSelection::Both,
         };
+        if let Some(sgb) = &self.sgb {
+          sgb.borrow_mut().write_p1(value);
+        }
+        self.update_player(value);
+        self.update_lines();
       },
       _ => warn!("Writing to unknown Pad location 0x{:04x}", addr),
     }
@@ -147,3 +331,135 @@ impl Address for Pad {
 }
 
 impl Device for Pad {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Sends the MLT_REQ command to the SGB through P1, pulsing
+  /// P14 (0) and P15 (1) for each of the bits of the packet.
+  fn mlt_req(pad: &mut Pad, value: u8) {
+    let mut packet = [0u8; 16];
+    packet[0] = 0x11 << 3 | 0x01;
+    packet[1] = value;
+    pad.write(0xff00, 0x00);
+    pad.write(0xff00, 0x30);
+    for index in 0..packet.len() * 8 + 1 {
+      let bit = packet
+        .get(index / 8)
+        .is_some_and(|byte| byte >> (index % 8) & 0x01 == 0x01);
+      pad.write(0xff00, if bit { 0x10 } else { 0x20 });
+      pad.write(0xff00, 0x30);
+    }
+  }
+
+  fn sgb_pad() -> Pad {
+    let mut sgb = Sgb::new();
+    sgb.set_enabled(true);
+    let mut pad = Pad::new();
+    pad.set_sgb(Some(Shared::new(sgb)));
+    pad
+  }
+
+  #[test]
+  fn test_mlt_req() {
+    let mut pad = sgb_pad();
+    pad.key_press_player(1, PadKey::A);
+    mlt_req(&mut pad, 0x01);
+    assert_eq!(pad.player_count(), 2);
+    assert_eq!(pad.player(), 0);
+    assert_eq!(pad.read(0xff00) & 0x0f, 0x0f);
+
+    // the ID is cycled on each rising edge of P15
+    pad.write(0xff00, 0x10);
+    assert_eq!(pad.player(), 0);
+    pad.write(0xff00, 0x30);
+    assert_eq!(pad.player(), 1);
+    assert_eq!(pad.read(0xff00) & 0x0f, 0x0e);
+    pad.write(0xff00, 0x10);
+    assert_eq!(pad.read(0xff00) & 0x0f, 0x0e);
+    pad.write(0xff00, 0x30);
+    assert_eq!(pad.player(), 0);
+
+    // the ID is reset on a new request
+    pad.write(0xff00, 0x10);
+    pad.write(0xff00, 0x30);
+    assert_eq!(pad.player(), 1);
+    mlt_req(&mut pad, 0x03);
+    assert_eq!(pad.player_count(), 4);
+    // pulses of P14 alone don't cycle the ID
+    pad.write(0xff00, 0x20);
+    pad.write(0xff00, 0x30);
+    assert_eq!(pad.player(), 0);
+    for player in [1, 2, 3, 0, 1] {
+      pad.write(0xff00, 0x10);
+      pad.write(0xff00, 0x30);
+      assert_eq!(pad.player(), player);
+      assert_eq!(pad.read(0xff00) & 0x0f, 0x0f - player as u8);
+    }
+    mlt_req(&mut pad, 0x00);
+    assert_eq!(pad.player_count(), 1);
+    assert_eq!(pad.player(), 0);
+    pad.write(0xff00, 0x10);
+    pad.write(0xff00, 0x30);
+    assert_eq!(pad.player(), 0);
+  }
+
+  #[test]
+  fn test_read() {
+    let mut pad = Pad::new();
+    pad.key_press(PadKey::A);
+    pad.key_press(PadKey::Start);
+    pad.key_press(PadKey::Left);
+    pad.write(0xff00, 0x10);
+    assert_eq!(pad.read(0xff00), 0x16);
+    pad.write(0xff00, 0x20);
+    assert_eq!(pad.read(0xff00), 0x2d);
+    pad.write(0xff00, 0x30);
+    assert_eq!(pad.read(0xff00), 0x3f);
+
+    // with both groups selected a line is low if the button
+    // of any of the groups is pressed
+    pad.write(0xff00, 0x00);
+    assert_eq!(pad.read(0xff00), 0x04);
+    pad.key_lift(PadKey::Start);
+    assert_eq!(pad.read(0xff00), 0x0c);
+    pad.key_lift(PadKey::A);
+    pad.key_lift(PadKey::Left);
+    assert_eq!(pad.read(0xff00), 0x0f);
+  }
+
+  #[test]
+  fn test_interrupt() {
+    let mut pad = Pad::new();
+    pad.write(0xff00, 0x20);
+    pad.key_press(PadKey::Up);
+    assert!(pad.int_pad());
+    pad.ack_pad();
+
+    // buttons of the unselected group, and keeping or releasing
+    // a button, don't request the interrupt
+    pad.key_press(PadKey::B);
+    pad.key_press(PadKey::Up);
+    pad.key_lift(PadKey::Up);
+    assert!(!pad.int_pad());
+
+    // selecting a group with a button already pressed does
+    pad.write(0xff00, 0x30);
+    assert!(!pad.int_pad());
+    pad.write(0xff00, 0x10);
+    assert!(pad.int_pad());
+    pad.ack_pad();
+
+    // as does adding a group with another line pressed
+    pad.key_press(PadKey::Right);
+    assert!(!pad.int_pad());
+    pad.write(0xff00, 0x00);
+    assert!(pad.int_pad());
+    pad.ack_pad();
+    pad.write(0xff00, 0x20);
+    assert!(!pad.int_pad());
+    pad.write(0xff00, 0x00);
+    assert!(pad.int_pad());
+  }
+}
@@ -0,0 +1,513 @@
+use std::path::Path;
+
+use crate::{
+  cartridge::{header::Header, MAX_ROM_SIZE},
+  error::Error,
+  util::{crc32, read_file, replace_ext},
+};
+
+/// Size of the footer of the BPS and UPS patches, holding the
+/// CRC32 of the source, of the target and of the patch itself.
+const FOOTER_SIZE: usize = 12;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PatchFormat {
+  Ips,
+  Bps,
+  Ups,
+}
+
+impl PatchFormat {
+  /// Formats in the order in which patch files next
+  /// to a ROM are looked for.
+  pub const ALL: [PatchFormat; 3] = [PatchFormat::Ips, PatchFormat::Bps, PatchFormat::Ups];
+
+  pub fn from_data(data: &[u8]) -> Option<Self> {
+    if data.starts_with(b"PATCH") {
+      Some(PatchFormat::Ips)
+    } else if data.starts_with(b"BPS1") {
+      Some(PatchFormat::Bps)
+    } else if data.starts_with(b"UPS1") {
+      Some(PatchFormat::Ups)
+    } else {
+      None
+    }
+  }
+
+  pub fn extension(&self) -> &'static str {
+    match self {
+      PatchFormat::Ips => "ips",
+      PatchFormat::Bps => "bps",
+      PatchFormat::Ups => "ups",
+    }
+  }
+
+  pub fn description(&self) -> &'static str {
+    match self {
+      PatchFormat::Ips => "IPS",
+      PatchFormat::Bps => "BPS",
+      PatchFormat::Ups => "UPS",
+    }
+  }
+}
+
+/// Finds a patch file with the same name of the ROM (and
+/// one of the supported extensions) next to it.
+pub fn find_patch(rom_path: &str) -> Option<String> {
+  PatchFormat::ALL
+    .iter()
+    .filter_map(|format| replace_ext(rom_path, format.extension()))
+    .find(|path| Path::new(path).exists())
+}
+
+/// Applies the patch (in any of the supported formats) to the
+/// ROM data, returning the patched data.
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+  match PatchFormat::from_data(patch) {
+    Some(PatchFormat::Ips) => apply_ips(rom, patch),
+    Some(PatchFormat::Bps) => apply_bps(rom, patch),
+    Some(PatchFormat::Ups) => apply_ups(rom, patch),
+    None => Err(Error::CustomError(String::from("Unknown patch format"))),
+  }
+}
+
+/// Applies the patch file to the ROM data, returning the
+/// patched data and the format of the patch.
+pub fn apply_patch_file(rom: &[u8], path: &str) -> Result<(Vec<u8>, PatchFormat), Error> {
+  let patch = read_file(path)?;
+  let format = PatchFormat::from_data(&patch)
+    .ok_or_else(|| Error::CustomError(format!("Unknown patch format: {}", path)))?;
+  Ok((apply_patch(rom, &patch)?, format))
+}
+
+/// Describes the header and global checksums of the (patched)
+/// ROM, as recomputed from its contents, against the ones
+/// stored in its header.
+pub fn checksum_report(rom: &[u8]) -> String {
+  if rom.len() < 0x150 {
+    return String::from("ROM too small for a header");
+  }
+  let header = Header::header_checksum(rom);
+  let global = Header::global_checksum(rom);
+  let stored_global = u16::from_be_bytes([rom[0x14e], rom[0x14f]]);
+  let status = |valid: bool| if valid { "OK" } else { "MISMATCH" };
+  format!(
+    "Header checksum: {:#04x} (stored {:#04x}) {}, Global checksum: {:#06x} (stored {:#06x}) {}",
+    header,
+    rom[0x14d],
+    status(header == rom[0x14d]),
+    global,
+    stored_global,
+    status(global == stored_global)
+  )
+}
+
+/// Applies an IPS patch, made of records with a 24-bit offset and a
+/// 16-bit size (a zero size denoting a run-length encoded record)
+/// terminated by `EOF` and an optional 24-bit truncation size.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+  let mut reader = PatchReader::new(patch, 5, PatchFormat::Ips);
+  let mut data = rom.to_vec();
+  let overflow = || Error::CustomError(String::from("Invalid IPS patch"));
+  loop {
+    let offset = reader.read_be(3)?;
+    if offset == 0x454f46 {
+      break;
+    }
+    let size = reader.read_be(2)?;
+    let (size, bytes) = if size == 0 {
+      let size = reader.read_be(2)?;
+      (size, vec![reader.read_byte()?; size])
+    } else {
+      (size, reader.read_bytes(size)?.to_vec())
+    };
+    // the records are limited to 24-bit offsets but may still
+    // grow the ROM beyond the size of the largest one
+    if offset + size > MAX_ROM_SIZE {
+      return Err(overflow());
+    }
+    if data.len() < offset + size {
+      data.resize(offset + size, 0x00);
+    }
+    data[offset..offset + size].copy_from_slice(&bytes);
+  }
+  if reader.remaining() >= 3 {
+    data.truncate(reader.read_be(3)?);
+  }
+  Ok(data)
+}
+
+/// Applies a BPS patch, validating the CRC32 of the source, of the
+/// target and of the patch stored in its footer.
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+  let (source_crc, target_crc) = check_footer(patch)?;
+  let end = patch.len() - FOOTER_SIZE;
+  let mut reader = PatchReader::new(&patch[..end], 4, PatchFormat::Bps);
+  let source_size = reader.read_number()?;
+  let target_size = reader.read_number()?;
+  let metadata_size = reader.read_number()?;
+  reader.read_bytes(metadata_size)?;
+  if source_size != rom.len() || source_crc != crc32(rom) {
+    return Err(Error::CustomError(String::from(
+      "BPS patch does not match the ROM",
+    )));
+  }
+  let overflow = || Error::CustomError(String::from("Invalid BPS patch"));
+  if target_size > MAX_ROM_SIZE {
+    return Err(overflow());
+  }
+
+  let mut target = vec![0x00; target_size];
+  let mut output = 0usize;
+  let mut source_offset = 0isize;
+  let mut target_offset = 0isize;
+  while reader.remaining() > 0 {
+    let action = reader.read_number()?;
+    let length = (action >> 2) + 1;
+    if length > target_size - output {
+      return Err(overflow());
+    }
+    match action & 0x03 {
+      // source read
+      0 => {
+        let bytes = rom.get(output..output + length).ok_or_else(overflow)?;
+        target[output..output + length].copy_from_slice(bytes);
+        output += length;
+      },
+      // target read
+      1 => {
+        target[output..output + length].copy_from_slice(reader.read_bytes(length)?);
+        output += length;
+      },
+      // source copy
+      2 => {
+        source_offset = source_offset
+          .checked_add(reader.read_signed()?)
+          .ok_or_else(overflow)?;
+        let start = usize::try_from(source_offset).map_err(|_| overflow())?;
+        let end = start.checked_add(length).ok_or_else(overflow)?;
+        let bytes = rom.get(start..end).ok_or_else(overflow)?;
+        target[output..output + length].copy_from_slice(bytes);
+        source_offset = end as isize;
+        output += length;
+      },
+      // target copy, which may overlap with the output
+      // so it must be made byte by byte
+      _ => {
+        target_offset = target_offset
+          .checked_add(reader.read_signed()?)
+          .ok_or_else(overflow)?;
+        for _ in 0..length {
+          let index = usize::try_from(target_offset).map_err(|_| overflow())?;
+          if index >= output {
+            return Err(overflow());
+          }
+          target[output] = target[index];
+          target_offset += 1;
+          output += 1;
+        }
+      },
+    }
+  }
+
+  if crc32(&target) != target_crc {
+    return Err(Error::CustomError(String::from(
+      "BPS patch target checksum mismatch",
+    )));
+  }
+  Ok(target)
+}
+
+/// Applies an UPS patch, made of XOR runs at relative offsets,
+/// validating the CRC32 values stored in its footer.
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+  let (source_crc, target_crc) = check_footer(patch)?;
+  let end = patch.len() - FOOTER_SIZE;
+  let mut reader = PatchReader::new(&patch[..end], 4, PatchFormat::Ups);
+  let source_size = reader.read_number()?;
+  let target_size = reader.read_number()?;
+  if source_size != rom.len() || source_crc != crc32(rom) {
+    return Err(Error::CustomError(String::from(
+      "UPS patch does not match the ROM",
+    )));
+  }
+  let overflow = || Error::CustomError(String::from("Invalid UPS patch"));
+  if target_size > MAX_ROM_SIZE {
+    return Err(overflow());
+  }
+
+  let mut target = rom.to_vec();
+  target.resize(target_size, 0x00);
+  let mut offset = 0usize;
+  while reader.remaining() > 0 {
+    offset = offset
+      .checked_add(reader.read_number()?)
+      .ok_or_else(overflow)?;
+    loop {
+      let value = reader.read_byte()?;
+      if let Some(byte) = target.get_mut(offset) {
+        *byte ^= value;
+      }
+      offset = offset.checked_add(1).ok_or_else(overflow)?;
+      if value == 0x00 {
+        break;
+      }
+    }
+  }
+
+  if crc32(&target) != target_crc {
+    return Err(Error::CustomError(String::from(
+      "UPS patch target checksum mismatch",
+    )));
+  }
+  Ok(target)
+}
+
+/// Validates the CRC32 of the patch itself, returning the
+/// ones of the source and target stored in the footer.
+fn check_footer(patch: &[u8]) -> Result<(u32, u32), Error> {
+  if patch.len() < 4 + FOOTER_SIZE {
+    return Err(Error::CustomError(String::from("Invalid patch size")));
+  }
+  let footer = &patch[patch.len() - FOOTER_SIZE..];
+  let crc = |index: usize| {
+    u32::from_le_bytes([
+      footer[index],
+      footer[index + 1],
+      footer[index + 2],
+      footer[index + 3],
+    ])
+  };
+  if crc(8) != crc32(&patch[..patch.len() - 4]) {
+    return Err(Error::CustomError(String::from("Patch checksum mismatch")));
+  }
+  Ok((crc(0), crc(4)))
+}
+
+struct PatchReader<'a> {
+  data: &'a [u8],
+  offset: usize,
+  format: PatchFormat,
+}
+
+impl<'a> PatchReader<'a> {
+  fn new(data: &'a [u8], offset: usize, format: PatchFormat) -> Self {
+    Self {
+      data,
+      offset,
+      format,
+    }
+  }
+
+  fn remaining(&self) -> usize {
+    self.data.len().saturating_sub(self.offset)
+  }
+
+  fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], Error> {
+    let bytes = self
+      .offset
+      .checked_add(count)
+      .and_then(|end| self.data.get(self.offset..end))
+      .ok_or_else(|| Error::CustomError(String::from("Unexpected end of patch")))?;
+    self.offset += count;
+    Ok(bytes)
+  }
+
+  fn read_byte(&mut self) -> Result<u8, Error> {
+    Ok(self.read_bytes(1)?[0])
+  }
+
+  fn read_be(&mut self, count: usize) -> Result<usize, Error> {
+    Ok(
+      self
+        .read_bytes(count)?
+        .iter()
+        .fold(0, |value, &byte| value << 8 | byte as usize),
+    )
+  }
+
+  /// Reads a variable length number, as encoded by BPS and UPS,
+  /// failing for the numbers that don't fit in an `usize`.
+  fn read_number(&mut self) -> Result<usize, Error> {
+    let format = self.format;
+    let overflow = || Error::CustomError(format!("Invalid {} patch", format.description()));
+    let mut value = 0usize;
+    let mut shift = 1usize;
+    loop {
+      let byte = self.read_byte()?;
+      value = ((byte & 0x7f) as usize)
+        .checked_mul(shift)
+        .and_then(|digit| value.checked_add(digit))
+        .ok_or_else(overflow)?;
+      if byte & 0x80 != 0 {
+        return Ok(value);
+      }
+      shift = shift.checked_mul(0x80).ok_or_else(overflow)?;
+      value = value.checked_add(shift).ok_or_else(overflow)?;
+    }
+  }
+
+  /// Reads a signed relative offset, with the sign in the lowest bit.
+  fn read_signed(&mut self) -> Result<isize, Error> {
+    let value = self.read_number()?;
+    let magnitude = (value >> 1) as isize;
+    Ok(if value & 1 == 1 {
+      -magnitude
+    } else {
+      magnitude
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ROM: &[u8] = b"ABCDEFGH";
+
+  fn number(mut value: usize, patch: &mut Vec<u8>) {
+    loop {
+      let digit = (value & 0x7f) as u8;
+      value >>= 7;
+      if value == 0 {
+        patch.push(0x80 | digit);
+        return;
+      }
+      patch.push(digit);
+      value -= 1;
+    }
+  }
+
+  fn footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let crc = crc32(&patch);
+    patch.extend_from_slice(&crc.to_le_bytes());
+    patch
+  }
+
+  fn bps(target_size: usize, actions: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"BPS1".to_vec();
+    number(ROM.len(), &mut patch);
+    number(target_size, &mut patch);
+    number(0, &mut patch);
+    patch.extend_from_slice(actions);
+    footer(patch, ROM, target)
+  }
+
+  fn ups(target_size: usize, hunks: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"UPS1".to_vec();
+    number(ROM.len(), &mut patch);
+    number(target_size, &mut patch);
+    patch.extend_from_slice(hunks);
+    footer(patch, ROM, target)
+  }
+
+  /// Source read of 4, target read of "xy" and source copy of 2
+  /// from offset 2, resulting in `ABCDxyCD`.
+  fn bps_actions() -> Vec<u8> {
+    let mut actions = vec![];
+    number(3 << 2, &mut actions);
+    number(1 << 2 | 1, &mut actions);
+    actions.extend_from_slice(b"xy");
+    number(1 << 2 | 2, &mut actions);
+    number(2 << 1, &mut actions);
+    actions
+  }
+
+  #[test]
+  fn test_ips() {
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, b'x', b'y']);
+    // run-length encoded record growing the ROM
+    patch.extend_from_slice(&[0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, b'z']);
+    patch.extend_from_slice(b"EOF");
+    assert_eq!(apply_patch(ROM, &patch).unwrap(), b"ABxyEFGzzz");
+
+    patch.extend_from_slice(&[0x00, 0x00, 0x05]);
+    assert_eq!(apply_patch(ROM, &patch).unwrap(), b"ABxyE");
+  }
+
+  #[test]
+  fn test_ips_invalid() {
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x04, b'x']);
+    assert!(apply_ips(ROM, &patch).is_err());
+
+    // missing the EOF marker
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x01, b'x']);
+    assert!(apply_ips(ROM, &patch).is_err());
+
+    // run growing the ROM beyond the largest size
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(&[0xff, 0xff, 0xfe, 0x00, 0x00, 0xff, 0xff, b'z']);
+    patch.extend_from_slice(b"EOF");
+    assert!(apply_ips(ROM, &patch).is_err());
+  }
+
+  #[test]
+  fn test_bps() {
+    let patch = bps(8, &bps_actions(), b"ABCDxyCD");
+    assert_eq!(PatchFormat::from_data(&patch), Some(PatchFormat::Bps));
+    assert_eq!(apply_patch(ROM, &patch).unwrap(), b"ABCDxyCD");
+  }
+
+  #[test]
+  fn test_bps_checksums() {
+    let patch = bps(8, &bps_actions(), b"ABCDxyCE");
+    assert!(apply_bps(ROM, &patch).is_err());
+
+    let patch = bps(8, &bps_actions(), b"ABCDxyCD");
+    assert!(apply_bps(b"ABCDEFGX", &patch).is_err());
+
+    let mut corrupt = patch.clone();
+    corrupt[6] ^= 0x01;
+    assert!(apply_bps(ROM, &corrupt).is_err());
+  }
+
+  #[test]
+  fn test_bps_invalid() {
+    // truncated target read
+    let mut actions = vec![];
+    number(7 << 2 | 1, &mut actions);
+    actions.extend_from_slice(b"xy");
+    assert!(apply_bps(ROM, &bps(8, &actions, b"")).is_err());
+
+    // writes beyond the target size
+    assert!(apply_bps(ROM, &bps(4, &bps_actions(), b"")).is_err());
+
+    // source copy before the start of the source
+    let mut actions = vec![];
+    number(1 << 2 | 2, &mut actions);
+    number(4 << 1 | 1, &mut actions);
+    assert!(apply_bps(ROM, &bps(2, &actions, b"")).is_err());
+
+    // target size beyond the largest ROM
+    assert!(apply_bps(ROM, &bps(usize::MAX >> 1, &[], b"")).is_err());
+
+    // number overflowing an usize
+    assert!(apply_bps(ROM, &bps(0, &[0x7f; 12], b"")).is_err());
+
+    assert!(apply_bps(ROM, b"BPS1").is_err());
+  }
+
+  #[test]
+  fn test_ups() {
+    // XORs `C` into `x` and appends `IJ` to the ROM
+    let hunks = [0x82, b'C' ^ b'x', 0x00, 0x84, b'I', b'J', 0x00];
+    let patch = ups(10, &hunks, b"ABxDEFGHIJ");
+    assert_eq!(apply_patch(ROM, &patch).unwrap(), b"ABxDEFGHIJ");
+
+    let patch = ups(10, &hunks, b"ABxDEFGHIK");
+    assert!(apply_ups(ROM, &patch).is_err());
+  }
+
+  #[test]
+  fn test_ups_invalid() {
+    // hunk without the terminator
+    assert!(apply_ups(ROM, &ups(8, &[0x82, 0x01], b"")).is_err());
+    assert!(apply_ups(ROM, &ups(usize::MAX >> 1, &[], b"")).is_err());
+    assert!(apply_ups(ROM, &ups(8, &[0x7f; 12], b"")).is_err());
+  }
+}
@@ -0,0 +1,336 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use crate::{error::Error, memory_map::MemoryRegion, symbols::SymbolTable, util::write_file};
+
+/// Location of code in the cartridge ROM, the bank is only
+/// relevant for the switchable area (0x4000-0x7FFF).
+pub type CodeLocation = (u16, u16);
+
+/// State of the CPU before and after the execution of a single
+/// instruction (or interrupt dispatch), as seen by the profiler.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ProfileStep {
+  pub pc: u16,
+  pub sp: u16,
+  pub bank: u16,
+  pub opcode: u8,
+  pub cycles: u8,
+  pub next_pc: u16,
+  pub next_sp: u16,
+  pub next_bank: u16,
+
+  /// If an interrupt was dispatched instead of executing the
+  /// instruction, in which case the opcode is the one of the
+  /// instruction that is yet to be executed.
+  pub interrupt: bool,
+}
+
+/// Cycles attributed to a function, as identified by the
+/// entry point of a call (or of an interrupt handler).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FunctionProfile {
+  pub location: CodeLocation,
+  pub calls: u64,
+  pub self_cycles: u64,
+  pub total_cycles: u64,
+}
+
+struct Frame {
+  location: CodeLocation,
+
+  /// Value of SP right after the return address has been
+  /// pushed, the frame is left once SP goes above it.
+  sp: u16,
+}
+
+/// Execution profiler that attributes the cycles spent by the CPU to
+/// the PC of the instructions, to the ROM banks and to call stacks.
+///
+/// Call stacks are built from CALL, RST and interrupt dispatches, with
+/// frames being dropped once the stack is unwound past them (by RET,
+/// RETI or any other manipulation of SP), making it robust against
+/// code that doesn't return in the conventional way.
+#[derive(Default)]
+pub struct Profiler {
+  frames: Vec<Frame>,
+  stack: usize,
+  stacks: Vec<(Vec<CodeLocation>, u64)>,
+  stack_ids: HashMap<Vec<CodeLocation>, usize>,
+  pc_cycles: HashMap<CodeLocation, u64>,
+  bank_cycles: HashMap<u16, u64>,
+  calls: HashMap<CodeLocation, u64>,
+  total_cycles: u64,
+  instructions: u64,
+}
+
+impl Profiler {
+  pub fn new() -> Self {
+    let mut profiler = Self::default();
+    profiler.update_stack();
+    profiler
+  }
+
+  pub fn reset(&mut self) {
+    *self = Self::new();
+  }
+
+  /// Location of the code at the address, given the ROM bank
+  /// currently mapped, code outside of the switchable ROM area
+  /// is considered to be in bank 0.
+  pub fn location(addr: u16, rom_bank: u16) -> CodeLocation {
+    match MemoryRegion::from_addr(addr) {
+      MemoryRegion::RomX => (rom_bank, addr),
+      _ => (0, addr),
+    }
+  }
+
+  pub fn record(&mut self, step: &ProfileStep) {
+    let cycles = step.cycles as u64;
+    let location = Self::location(step.pc, step.bank);
+    self.total_cycles += cycles;
+    self.instructions += 1;
+    self.stacks[self.stack].1 += cycles;
+    *self.pc_cycles.entry(location).or_default() += cycles;
+    if step.pc < 0x8000 {
+      *self.bank_cycles.entry(location.0).or_default() += cycles;
+    }
+
+    // drops the frames whose return address has been popped
+    // from the stack, then pushes the one of the new call
+    let mut changed = false;
+    while self
+      .frames
+      .last()
+      .is_some_and(|frame| step.next_sp > frame.sp)
+    {
+      self.frames.pop();
+      changed = true;
+    }
+    if Self::is_call(step) {
+      let location = Self::location(step.next_pc, step.next_bank);
+      *self.calls.entry(location).or_default() += 1;
+      self.frames.push(Frame {
+        location,
+        sp: step.next_sp,
+      });
+      changed = true;
+    }
+    if changed {
+      self.update_stack();
+    }
+  }
+
+  pub fn total_cycles(&self) -> u64 {
+    self.total_cycles
+  }
+
+  pub fn instructions(&self) -> u64 {
+    self.instructions
+  }
+
+  /// Number of frames in the current call stack.
+  pub fn depth(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn pc_cycles(&self, bank: u16, pc: u16) -> u64 {
+    self
+      .pc_cycles
+      .get(&Self::location(pc, bank))
+      .copied()
+      .unwrap_or(0)
+  }
+
+  /// Cycles spent executing instructions in the (inclusive) range.
+  pub fn range_cycles(&self, bank: u16, start: u16, end: u16) -> u64 {
+    (start..=end).map(|pc| self.pc_cycles(bank, pc)).sum()
+  }
+
+  /// Cycles spent executing code of each of the ROM banks,
+  /// sorted by bank number.
+  pub fn bank_cycles(&self) -> Vec<(u16, u64)> {
+    let mut banks: Vec<(u16, u64)> = self
+      .bank_cycles
+      .iter()
+      .map(|(&bank, &cycles)| (bank, cycles))
+      .collect();
+    banks.sort();
+    banks
+  }
+
+  /// Cycles attributed to each function called during the
+  /// profiling, sorted by the total (inclusive) cycles.
+  pub fn functions(&self) -> Vec<FunctionProfile> {
+    let mut functions: HashMap<CodeLocation, FunctionProfile> = HashMap::new();
+    for (stack, cycles) in &self.stacks {
+      for (index, &location) in stack.iter().enumerate() {
+        let function = functions.entry(location).or_insert(FunctionProfile {
+          location,
+          calls: self.calls.get(&location).copied().unwrap_or(0),
+          self_cycles: 0,
+          total_cycles: 0,
+        });
+        // recursive calls must only be accounted once
+        if !stack[..index].contains(&location) {
+          function.total_cycles += cycles;
+        }
+        if index == stack.len() - 1 {
+          function.self_cycles += cycles;
+        }
+      }
+    }
+    let mut functions: Vec<FunctionProfile> = functions.into_values().collect();
+    functions.sort_by_key(|function| Reverse(function.total_cycles));
+    functions
+  }
+
+  /// Dumps the call stacks in the folded format used by the
+  /// flamegraph tools, one `root;caller;callee cycles` per line.
+  pub fn folded(&self, symbols: &SymbolTable) -> String {
+    let mut lines: Vec<String> = self
+      .stacks
+      .iter()
+      .filter(|(_, cycles)| *cycles > 0)
+      .map(|(stack, cycles)| {
+        let mut names = vec![String::from("root")];
+        names.extend(stack.iter().map(|&location| Self::name(symbols, location)));
+        format!("{} {}", names.join(";"), cycles)
+      })
+      .collect();
+    lines.sort();
+    lines.join("\n") + "\n"
+  }
+
+  pub fn write_folded(&self, path: &str, symbols: &SymbolTable) -> Result<(), Error> {
+    write_file(path, self.folded(symbols).as_bytes())
+  }
+
+  /// Name of the code location, using the symbol defined
+  /// at it or its `bank:addr` representation otherwise.
+  pub fn name(symbols: &SymbolTable, location: CodeLocation) -> String {
+    let (bank, addr) = location;
+    match symbols.name(bank, addr) {
+      Some(name) => String::from(name),
+      None => format!("{:02X}:{:04X}", bank, addr),
+    }
+  }
+
+  /// Checks if the step entered a new function, either by a taken
+  /// CALL or RST or by the dispatch of an interrupt.
+  fn is_call(step: &ProfileStep) -> bool {
+    if step.interrupt {
+      return true;
+    }
+    if step.next_sp != step.sp.wrapping_sub(2) {
+      return false;
+    }
+    let opcode = step.opcode;
+    let call = matches!(opcode, 0xc4 | 0xcc | 0xcd | 0xd4 | 0xdc);
+    let rst = opcode & 0xc7 == 0xc7;
+    call || rst
+  }
+
+  fn update_stack(&mut self) {
+    let stack: Vec<CodeLocation> = self.frames.iter().map(|frame| frame.location).collect();
+    self.stack = match self.stack_ids.get(&stack) {
+      Some(&id) => id,
+      None => {
+        let id = self.stacks.len();
+        self.stacks.push((stack.clone(), 0));
+        self.stack_ids.insert(stack, id);
+        id
+      },
+    };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn step(pc: u16, sp: u16, opcode: u8, cycles: u8, next_pc: u16, next_sp: u16) -> ProfileStep {
+    ProfileStep {
+      pc,
+      sp,
+      bank: 1,
+      opcode,
+      cycles,
+      next_pc,
+      next_sp,
+      next_bank: 1,
+      interrupt: false,
+    }
+  }
+
+  #[test]
+  fn test_folded() {
+    let mut symbols = SymbolTable::new();
+    symbols.insert(0, 0x0200, "Func");
+    symbols.insert(0, 0x0040, "VBlank");
+
+    let mut profiler = Profiler::new();
+    let steps = [
+      // NOP; CALL Func
+      step(0x0150, 0xfffe, 0x00, 4, 0x0151, 0xfffe),
+      step(0x0151, 0xfffe, 0xcd, 24, 0x0200, 0xfffc),
+      // PUSH BC, then an interrupt is dispatched
+      // right before the POP BC that follows it
+      step(0x0200, 0xfffc, 0xc5, 16, 0x0201, 0xfffa),
+      ProfileStep {
+        interrupt: true,
+        ..step(0x0201, 0xfffa, 0xc5, 20, 0x0040, 0xfff8)
+      },
+      // RETI
+      step(0x0040, 0xfff8, 0xd9, 16, 0x0201, 0xfffa),
+      // POP BC; RST 0x38; RET
+      step(0x0201, 0xfffa, 0xc1, 12, 0x0202, 0xfffc),
+      step(0x0202, 0xfffc, 0xff, 16, 0x0038, 0xfffa),
+      step(0x0038, 0xfffa, 0xc9, 16, 0x0203, 0xfffc),
+      // RET; NOP
+      step(0x0203, 0xfffc, 0xc9, 16, 0x0152, 0xfffe),
+      step(0x0152, 0xfffe, 0x00, 4, 0x0153, 0xfffe),
+    ];
+    for step in &steps {
+      profiler.record(step);
+    }
+
+    assert_eq!(profiler.depth(), 0);
+    assert_eq!(profiler.instructions(), 10);
+    assert_eq!(profiler.total_cycles(), 144);
+    assert_eq!(profiler.pc_cycles(0, 0x0201), 32);
+    assert_eq!(profiler.bank_cycles(), vec![(0, 144)]);
+    assert_eq!(
+      profiler.folded(&symbols),
+      "root 32\nroot;Func 80\nroot;Func;00:0038 16\nroot;Func;VBlank 16\n"
+    );
+
+    let functions = profiler.functions();
+    assert_eq!(functions[0].location, (0, 0x0200));
+    assert_eq!(functions[0].calls, 1);
+    assert_eq!(functions[0].self_cycles, 80);
+    assert_eq!(functions[0].total_cycles, 112);
+  }
+
+  #[test]
+  fn test_banked_call() {
+    let mut profiler = Profiler::new();
+    profiler.record(&ProfileStep {
+      next_bank: 3,
+      ..step(0x0150, 0xfffe, 0xcd, 24, 0x4000, 0xfffc)
+    });
+    profiler.record(&ProfileStep {
+      bank: 3,
+      next_bank: 3,
+      ..step(0x4000, 0xfffc, 0x00, 4, 0x4001, 0xfffc)
+    });
+
+    assert_eq!(profiler.depth(), 1);
+    assert_eq!(profiler.pc_cycles(3, 0x4000), 4);
+    assert_eq!(profiler.pc_cycles(1, 0x4000), 0);
+    assert_eq!(profiler.bank_cycles(), vec![(0, 24), (3, 4)]);
+    assert_eq!(
+      profiler.folded(&SymbolTable::new()),
+      "root 24\nroot;03:4000 4\n"
+    );
+  }
+}
@@ -0,0 +1,390 @@
+use std::{
+  collections::HashMap,
+  fmt::{self, Display, Formatter},
+  path::Path,
+};
+
+use crate::{
+  cartridge::CartType,
+  error::Error,
+  model::Model,
+  util::{crc32, read_file, replace_ext},
+};
+
+/// Status of the dump of a ROM, as stated by the database.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DumpStatus {
+  Verified,
+  Good,
+  BadDump,
+}
+
+impl DumpStatus {
+  pub fn description(&self) -> &'static str {
+    match self {
+      DumpStatus::Verified => "Verified",
+      DumpStatus::Good => "Good",
+      DumpStatus::BadDump => "Bad dump",
+    }
+  }
+}
+
+impl Display for DumpStatus {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.description())
+  }
+}
+
+/// Game of the database matching a ROM dump.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RomEntry {
+  pub name: String,
+  pub region: Option<String>,
+  pub size: usize,
+  pub crc32: u32,
+  pub sha1: Option<String>,
+  pub status: DumpStatus,
+}
+
+impl Display for RomEntry {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} [{}] ({})",
+      self.name,
+      self.region.as_deref().unwrap_or("Unknown"),
+      self.status
+    )
+  }
+}
+
+/// Per-game settings that take precedence over the ones
+/// in the header of the ROM, for the games whose header
+/// is wrong (or for homebrew without a proper one).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct RomOverrides {
+  /// Cartridge type (and so mapper) to be used.
+  pub cart: Option<CartType>,
+  /// Model the game is forced to run on.
+  pub model: Option<Model>,
+  /// Presence of the real time clock.
+  pub rtc: Option<bool>,
+}
+
+impl RomOverrides {
+  pub fn is_empty(&self) -> bool {
+    *self == Self::default()
+  }
+}
+
+/// Database of the known ROM dumps, loaded from a No-Intro
+/// DAT (XML) file, with games looked up by the CRC32 of the
+/// ROM data and confirmed by its SHA-1 (when available).
+///
+/// The overrides are loaded from a text file with one game
+/// per line, made of its CRC32 or SHA-1 followed by the
+/// overrides, anything after a `;` being a comment, eg:
+///
+/// ```text
+/// ; MBC1 game with the wrong type in the header
+/// 46DF91AD cart=01 model=dmg rtc=no
+/// ```
+#[derive(Clone, Default)]
+pub struct RomDatabase {
+  entries: Vec<RomEntry>,
+  by_crc32: HashMap<u32, Vec<usize>>,
+  by_sha1: HashMap<String, usize>,
+  overrides: HashMap<String, RomOverrides>,
+}
+
+impl RomDatabase {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn from_dat(text: &str) -> Result<Self, Error> {
+    // No-Intro DAT files start with a DOCTYPE declaration
+    let options = roxmltree::ParsingOptions {
+      allow_dtd: true,
+      ..Default::default()
+    };
+    let document = roxmltree::Document::parse_with_options(text, options)
+      .map_err(|err| Error::CustomError(format!("Invalid DAT file: {}", err)))?;
+    let mut database = Self::new();
+    for game in document
+      .descendants()
+      .filter(|node| node.has_tag_name("game"))
+    {
+      let name = game.attribute("name").unwrap_or_default();
+      // the region is either stated by a release or is the
+      // first tag of the name, eg: `Tetris (World) (Rev 1)`
+      let region = game
+        .children()
+        .find(|node| node.has_tag_name("release"))
+        .and_then(|release| release.attribute("region"))
+        .or_else(|| {
+          name
+            .split_once('(')
+            .and_then(|(_, tags)| tags.split_once(')'))
+            .map(|(region, _)| region)
+        })
+        .map(String::from);
+      for rom in game.children().filter(|node| node.has_tag_name("rom")) {
+        let crc32 = rom
+          .attribute("crc")
+          .and_then(|crc| u32::from_str_radix(crc, 16).ok())
+          .ok_or_else(|| Error::CustomError(format!("Invalid CRC32 for {}", name)))?;
+        database.insert(RomEntry {
+          name: String::from(name),
+          region: region.clone(),
+          size: rom
+            .attribute("size")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or_default(),
+          crc32,
+          sha1: rom.attribute("sha1").map(str::to_lowercase),
+          status: match rom.attribute("status") {
+            Some("verified") => DumpStatus::Verified,
+            Some("baddump") => DumpStatus::BadDump,
+            _ => DumpStatus::Good,
+          },
+        });
+      }
+    }
+    Ok(database)
+  }
+
+  /// Loads the DAT file, together with the overrides file with
+  /// the same name (eg: `gb.ovr` for `gb.dat`) if there's one.
+  pub fn from_file(path: &str) -> Result<Self, Error> {
+    let text = String::from_utf8(read_file(path)?)
+      .map_err(|_| Error::CustomError(format!("Invalid DAT file: {}", path)))?;
+    let mut database = Self::from_dat(&text)?;
+    if let Some(overrides_path) = replace_ext(path, "ovr") {
+      if Path::new(&overrides_path).exists() {
+        database.load_overrides_file(&overrides_path)?;
+      }
+    }
+    Ok(database)
+  }
+
+  pub fn load_overrides(&mut self, text: &str) -> Result<(), Error> {
+    for line in text.lines() {
+      let line = line.split(';').next().unwrap_or("").trim();
+      let mut fields = line.split_whitespace();
+      let key = match fields.next() {
+        Some(key) => key.to_lowercase(),
+        None => continue,
+      };
+      if key.len() != 8 && key.len() != 40 {
+        return Err(Error::CustomError(format!(
+          "Invalid CRC32 or SHA-1: {}",
+          key
+        )));
+      }
+      let mut overrides = RomOverrides::default();
+      for field in fields {
+        let invalid = || Error::CustomError(format!("Invalid override: {}", field));
+        let (name, value) = field.split_once('=').ok_or_else(invalid)?;
+        match name {
+          "cart" => {
+            let byte =
+              u8::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+            overrides.cart = Some(CartType::try_from(byte)?);
+          },
+          "model" => overrides.model = Some(Model::from_name(value).ok_or_else(invalid)?),
+          "rtc" => {
+            overrides.rtc = Some(match value {
+              "yes" => true,
+              "no" => false,
+              _ => return Err(invalid()),
+            })
+          },
+          _ => return Err(invalid()),
+        }
+      }
+      self.overrides.insert(key, overrides);
+    }
+    Ok(())
+  }
+
+  pub fn load_overrides_file(&mut self, path: &str) -> Result<(), Error> {
+    let text = String::from_utf8(read_file(path)?)
+      .map_err(|_| Error::CustomError(format!("Invalid overrides file: {}", path)))?;
+    self.load_overrides(&text)
+  }
+
+  pub fn insert(&mut self, entry: RomEntry) {
+    let index = self.entries.len();
+    self.by_crc32.entry(entry.crc32).or_default().push(index);
+    if let Some(sha1) = &entry.sha1 {
+      self.by_sha1.insert(sha1.clone(), index);
+    }
+    self.entries.push(entry);
+  }
+
+  pub fn entries(&self) -> &[RomEntry] {
+    &self.entries
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// Finds the game the ROM data is a dump of, the CRC32 match
+  /// has to be confirmed by the SHA-1 for the entries having one.
+  pub fn lookup(&self, data: &[u8]) -> Option<&RomEntry> {
+    let candidates = self.by_crc32.get(&crc32(data))?;
+    let sha1 = sha1_hex(data);
+    candidates
+      .iter()
+      .map(|&index| &self.entries[index])
+      .find(|entry| entry.sha1.as_ref().is_none_or(|value| *value == sha1))
+  }
+
+  pub fn lookup_sha1(&self, sha1: &str) -> Option<&RomEntry> {
+    self
+      .by_sha1
+      .get(&sha1.to_lowercase())
+      .map(|&index| &self.entries[index])
+  }
+
+  /// Overrides for the ROM data, the ones keyed by the
+  /// SHA-1 take precedence over the ones by the CRC32.
+  pub fn overrides(&self, data: &[u8]) -> RomOverrides {
+    self
+      .overrides
+      .get(&sha1_hex(data))
+      .or_else(|| self.overrides.get(&format!("{:08x}", crc32(data))))
+      .copied()
+      .unwrap_or_default()
+  }
+}
+
+/// SHA-1 of the data as a lowercase hex string.
+pub fn sha1_hex(data: &[u8]) -> String {
+  sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const GAME: &[u8] = b"GAME";
+  const HOMEBREW: &[u8] = b"HOMEBREW";
+
+  fn dat() -> String {
+    format!(
+      r#"<?xml version="1.0"?>
+<!DOCTYPE datafile PUBLIC "-//Logiqx//DTD ROM Management Datafile//EN" "http://www.logiqx.com/Dats/datafile.dtd">
+<datafile>
+  <header><name>Nintendo - Game Boy</name></header>
+  <game name="Clash (Japan)">
+    <rom name="Clash.gb" size="4" crc="{:08X}" sha1="{}" status="baddump"/>
+  </game>
+  <game name="Game (Europe) (Rev 1)">
+    <rom name="Game.gb" size="4" crc="{:08X}" sha1="{}" status="verified"/>
+  </game>
+  <game name="Homebrew">
+    <release name="Homebrew" region="USA"/>
+    <rom name="Homebrew.gb" size="8" crc="{:08x}"/>
+  </game>
+</datafile>"#,
+      crc32(GAME),
+      "0".repeat(40),
+      crc32(GAME),
+      sha1_hex(GAME).to_uppercase(),
+      crc32(HOMEBREW),
+    )
+  }
+
+  #[test]
+  fn test_from_dat() {
+    let database = RomDatabase::from_dat(&dat()).unwrap();
+    assert_eq!(database.len(), 3);
+
+    // the CRC32 clash is told apart by the SHA-1
+    let entry = database.lookup(GAME).unwrap();
+    assert_eq!(entry.name, "Game (Europe) (Rev 1)");
+    assert_eq!(entry.region.as_deref(), Some("Europe"));
+    assert_eq!(entry.size, 4);
+    assert_eq!(entry.sha1, Some(sha1_hex(GAME)));
+    assert_eq!(entry.status, DumpStatus::Verified);
+    assert_eq!(
+      entry.to_string(),
+      "Game (Europe) (Rev 1) [Europe] (Verified)"
+    );
+    assert_eq!(
+      database.lookup_sha1(&sha1_hex(GAME).to_uppercase()),
+      Some(entry)
+    );
+
+    // entries without a SHA-1 are matched by the CRC32 alone
+    let entry = database.lookup(HOMEBREW).unwrap();
+    assert_eq!(entry.name, "Homebrew");
+    assert_eq!(entry.region.as_deref(), Some("USA"));
+    assert_eq!(entry.status, DumpStatus::Good);
+
+    assert_eq!(database.lookup(b"OTHER"), None);
+    assert_eq!(database.lookup_sha1(&sha1_hex(HOMEBREW)), None);
+  }
+
+  #[test]
+  fn test_from_dat_invalid() {
+    assert!(RomDatabase::from_dat("<datafile>").is_err());
+    assert!(RomDatabase::from_dat("<datafile><game name=\"A\"><rom/></game></datafile>").is_err());
+    assert!(RomDatabase::from_dat(
+      "<datafile><game name=\"A\"><rom crc=\"XYZ\"/></game></datafile>"
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn test_overrides() {
+    let mut database = RomDatabase::new();
+    database
+      .load_overrides(&format!(
+        "; homebrew with an invalid header\n{:08X} cart=0x01 model=cgb\n\n{} rtc=no ; by SHA-1\n{:08x} rtc=yes\n",
+        crc32(HOMEBREW),
+        sha1_hex(GAME),
+        crc32(GAME),
+      ))
+      .unwrap();
+    assert_eq!(
+      database.overrides(HOMEBREW),
+      RomOverrides {
+        cart: Some(CartType::Mbc1),
+        model: Some(Model::Cgb),
+        rtc: None,
+      }
+    );
+    // the SHA-1 overrides take precedence over the CRC32 ones
+    assert_eq!(
+      database.overrides(GAME),
+      RomOverrides {
+        rtc: Some(false),
+        ..Default::default()
+      }
+    );
+    assert!(database.overrides(b"OTHER").is_empty());
+  }
+
+  #[test]
+  fn test_overrides_invalid() {
+    for text in [
+      "1234567 model=dmg",
+      "12345678 model=nes",
+      "12345678 cart=xx",
+      "12345678 cart=04",
+      "12345678 rtc=maybe",
+      "12345678 speed=2",
+      "12345678 model",
+    ] {
+      assert!(RomDatabase::new().load_overrides(text).is_err(), "{}", text);
+    }
+  }
+}
@@ -0,0 +1,902 @@
+use log::debug;
+
+use crate::soc::ppu::{Pixel, DISPLAY_HEIGHT, DISPLAY_WIDTH, RGB_SIZE, SHADE_BUFFER_SIZE};
+
+/// The SGB output (border included) is 32x28 tiles.
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+pub const SGB_FRAME_BUFFER_SIZE: usize = SGB_WIDTH * SGB_HEIGHT * RGB_SIZE;
+
+/// Position of the Game Boy screen inside of the border.
+pub const SCREEN_X: usize = 48;
+pub const SCREEN_Y: usize = 40;
+
+pub const PACKET_SIZE: usize = 16;
+
+/// Size of the data sent through a VRAM transfer, which is the
+/// data of the first 256 tiles displayed by the background.
+pub const TRANSFER_SIZE: usize = 0x1000;
+
+/// The attributes assign one of the four palettes to each
+/// of the 20x18 tiles of the screen.
+pub const ATTR_WIDTH: usize = 20;
+pub const ATTR_HEIGHT: usize = 18;
+pub const ATTR_SIZE: usize = ATTR_WIDTH * ATTR_HEIGHT;
+
+pub const SYSTEM_PALETTE_COUNT: usize = 512;
+pub const ATTR_FILE_COUNT: usize = 45;
+/// Attribute files have 2 bits per tile.
+pub const ATTR_FILE_SIZE: usize = ATTR_SIZE / 4;
+
+/// The border tiles are 4 bits per pixel SNES tiles.
+pub const BORDER_TILE_SIZE: usize = 32;
+pub const BORDER_TILE_COUNT: usize = 256;
+pub const BORDER_MAP_SIZE: usize = 32 * 32 * 2;
+pub const BORDER_PALETTE_COUNT: usize = 4;
+
+/// Palette used until the game sets its own one, as RGB555.
+pub const DEFAULT_PALETTE: SgbPalette = [0x67bf, 0x265b, 0x10b5, 0x2866];
+
+pub type SgbPalette = [u16; 4];
+pub type BorderPalette = [u16; 16];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SgbCommand {
+  Pal01,
+  Pal23,
+  Pal03,
+  Pal12,
+  AttrBlk,
+  AttrLin,
+  AttrDiv,
+  AttrChr,
+  Sound,
+  SouTrn,
+  PalSet,
+  PalTrn,
+  AtrcEn,
+  TestEn,
+  IconEn,
+  DataSnd,
+  DataTrn,
+  MltReq,
+  Jump,
+  ChrTrn,
+  PctTrn,
+  AttrTrn,
+  AttrSet,
+  MaskEn,
+  ObjTrn,
+}
+
+impl SgbCommand {
+  pub fn from_code(code: u8) -> Option<Self> {
+    match code {
+      0x00 => Some(SgbCommand::Pal01),
+      0x01 => Some(SgbCommand::Pal23),
+      0x02 => Some(SgbCommand::Pal03),
+      0x03 => Some(SgbCommand::Pal12),
+      0x04 => Some(SgbCommand::AttrBlk),
+      0x05 => Some(SgbCommand::AttrLin),
+      0x06 => Some(SgbCommand::AttrDiv),
+      0x07 => Some(SgbCommand::AttrChr),
+      0x08 => Some(SgbCommand::Sound),
+      0x09 => Some(SgbCommand::SouTrn),
+      0x0a => Some(SgbCommand::PalSet),
+      0x0b => Some(SgbCommand::PalTrn),
+      0x0c => Some(SgbCommand::AtrcEn),
+      0x0d => Some(SgbCommand::TestEn),
+      0x0e => Some(SgbCommand::IconEn),
+      0x0f => Some(SgbCommand::DataSnd),
+      0x10 => Some(SgbCommand::DataTrn),
+      0x11 => Some(SgbCommand::MltReq),
+      0x12 => Some(SgbCommand::Jump),
+      0x13 => Some(SgbCommand::ChrTrn),
+      0x14 => Some(SgbCommand::PctTrn),
+      0x15 => Some(SgbCommand::AttrTrn),
+      0x16 => Some(SgbCommand::AttrSet),
+      0x17 => Some(SgbCommand::MaskEn),
+      0x18 => Some(SgbCommand::ObjTrn),
+      _ => None,
+    }
+  }
+
+  pub fn description(&self) -> &'static str {
+    match self {
+      SgbCommand::Pal01 => "PAL01",
+      SgbCommand::Pal23 => "PAL23",
+      SgbCommand::Pal03 => "PAL03",
+      SgbCommand::Pal12 => "PAL12",
+      SgbCommand::AttrBlk => "ATTR_BLK",
+      SgbCommand::AttrLin => "ATTR_LIN",
+      SgbCommand::AttrDiv => "ATTR_DIV",
+      SgbCommand::AttrChr => "ATTR_CHR",
+      SgbCommand::Sound => "SOUND",
+      SgbCommand::SouTrn => "SOU_TRN",
+      SgbCommand::PalSet => "PAL_SET",
+      SgbCommand::PalTrn => "PAL_TRN",
+      SgbCommand::AtrcEn => "ATRC_EN",
+      SgbCommand::TestEn => "TEST_EN",
+      SgbCommand::IconEn => "ICON_EN",
+      SgbCommand::DataSnd => "DATA_SND",
+      SgbCommand::DataTrn => "DATA_TRN",
+      SgbCommand::MltReq => "MLT_REQ",
+      SgbCommand::Jump => "JUMP",
+      SgbCommand::ChrTrn => "CHR_TRN",
+      SgbCommand::PctTrn => "PCT_TRN",
+      SgbCommand::AttrTrn => "ATTR_TRN",
+      SgbCommand::AttrSet => "ATTR_SET",
+      SgbCommand::MaskEn => "MASK_EN",
+      SgbCommand::ObjTrn => "OBJ_TRN",
+    }
+  }
+
+  /// If the command takes its data through a VRAM transfer.
+  pub fn is_transfer(&self) -> bool {
+    matches!(
+      self,
+      SgbCommand::SouTrn
+        | SgbCommand::PalTrn
+        | SgbCommand::DataTrn
+        | SgbCommand::ChrTrn
+        | SgbCommand::PctTrn
+        | SgbCommand::AttrTrn
+        | SgbCommand::ObjTrn
+    )
+  }
+}
+
+/// Masking of the Game Boy screen, as set by MASK_EN.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SgbMask {
+  #[default]
+  Cancel,
+  /// Keeps displaying the last frame.
+  Freeze,
+  Black,
+  /// Fills the screen with color 0.
+  Color0,
+}
+
+impl SgbMask {
+  pub fn from_value(value: u8) -> Self {
+    match value & 0x03 {
+      0x01 => SgbMask::Freeze,
+      0x02 => SgbMask::Black,
+      0x03 => SgbMask::Color0,
+      _ => SgbMask::Cancel,
+    }
+  }
+}
+
+/// Super Game Boy, receiving the command packets sent by the
+/// game through the P1 register and colorizing (and bordering)
+/// the frames output by the PPU accordingly.
+///
+/// A packet is started by a reset pulse (P14 and P15 low), followed
+/// by its 128 bits (LSB first), each sent as a P14 (0) or P15 (1)
+/// low pulse, and a final 0 stop bit. The first byte of the first
+/// packet holds the command (bits 3-7) and the number of packets.
+pub struct Sgb {
+  /// If the commands are executed, which only happens for
+  /// games flagged as supporting the SGB in their header.
+  enabled: bool,
+
+  p1: u8,
+  receiving: bool,
+  bit_index: usize,
+  packet: [u8; PACKET_SIZE],
+  packets: Vec<[u8; PACKET_SIZE]>,
+
+  /// Command waiting for its VRAM transfer, made on the
+  /// next frame, together with its first packet.
+  transfer: Option<(SgbCommand, [u8; PACKET_SIZE])>,
+
+  palettes: [SgbPalette; 4],
+  system_palettes: Vec<SgbPalette>,
+  attr_map: [u8; ATTR_SIZE],
+  attr_files: Vec<[u8; ATTR_FILE_SIZE]>,
+  mask: SgbMask,
+  frozen: Option<Box<[u8; SHADE_BUFFER_SIZE]>>,
+
+  /// Number of controllers requested through MLT_REQ.
+  player_count: usize,
+
+  border_tiles: Vec<u8>,
+  border_map: Vec<u8>,
+  border_palettes: [BorderPalette; BORDER_PALETTE_COUNT],
+
+  frame_buffer: Vec<u8>,
+}
+
+impl Sgb {
+  pub fn new() -> Self {
+    Self {
+      enabled: false,
+      p1: 0x30,
+      receiving: false,
+      bit_index: 0,
+      packet: [0u8; PACKET_SIZE],
+      packets: vec![],
+      transfer: None,
+      palettes: [DEFAULT_PALETTE; 4],
+      system_palettes: vec![[0u16; 4]; SYSTEM_PALETTE_COUNT],
+      attr_map: [0u8; ATTR_SIZE],
+      attr_files: vec![[0u8; ATTR_FILE_SIZE]; ATTR_FILE_COUNT],
+      mask: SgbMask::Cancel,
+      frozen: None,
+      player_count: 1,
+      border_tiles: vec![0u8; BORDER_TILE_COUNT * BORDER_TILE_SIZE],
+      border_map: vec![0u8; BORDER_MAP_SIZE],
+      border_palettes: [[0u16; 16]; BORDER_PALETTE_COUNT],
+      frame_buffer: vec![],
+    }
+  }
+
+  pub fn reset(&mut self) {
+    let enabled = self.enabled;
+    *self = Self::new();
+    self.enabled = enabled;
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  pub fn set_enabled(&mut self, value: bool) {
+    self.enabled = value;
+  }
+
+  pub fn palettes(&self) -> &[SgbPalette; 4] {
+    &self.palettes
+  }
+
+  pub fn system_palettes(&self) -> &[SgbPalette] {
+    &self.system_palettes
+  }
+
+  /// Palette (0-3) used by each of the 20x18 tiles of the screen.
+  pub fn attr_map(&self) -> &[u8; ATTR_SIZE] {
+    &self.attr_map
+  }
+
+  pub fn mask(&self) -> SgbMask {
+    self.mask
+  }
+
+  pub fn player_count(&self) -> usize {
+    self.player_count
+  }
+
+  pub fn border_tiles(&self) -> &[u8] {
+    &self.border_tiles
+  }
+
+  pub fn border_map(&self) -> &[u8] {
+    &self.border_map
+  }
+
+  pub fn border_palettes(&self) -> &[BorderPalette; BORDER_PALETTE_COUNT] {
+    &self.border_palettes
+  }
+
+  /// Handles a write to the P1 register, receiving the bits of
+  /// the packets from the P14 and P15 pulses.
+  pub fn write_p1(&mut self, value: u8) {
+    let value = value & 0x30;
+    let previous = self.p1;
+    self.p1 = value;
+    if !self.enabled {
+      return;
+    }
+
+    // a reset pulse (re)starts the reception of a packet
+    if value == 0x00 {
+      self.receiving = true;
+      self.bit_index = 0;
+      self.packet = [0u8; PACKET_SIZE];
+      return;
+    }
+
+    // the bits are only taken on the pulses following a release
+    if !self.receiving || previous != 0x30 || value == 0x30 {
+      return;
+    }
+    let bit = value == 0x10;
+
+    if self.bit_index == PACKET_SIZE * 8 {
+      self.receiving = false;
+      if bit {
+        debug!("Invalid SGB packet stop bit");
+        self.packets.clear();
+        return;
+      }
+      self.receive_packet();
+      return;
+    }
+
+    if bit {
+      self.packet[self.bit_index / 8] |= 1 << (self.bit_index % 8);
+    }
+    self.bit_index += 1;
+  }
+
+  fn receive_packet(&mut self) {
+    self.packets.push(self.packet);
+    let count = (self.packets[0][0] & 0x07).max(1) as usize;
+    if self.packets.len() < count {
+      return;
+    }
+    let packets = std::mem::take(&mut self.packets);
+    self.execute(&packets);
+  }
+
+  fn execute(&mut self, packets: &[[u8; PACKET_SIZE]]) {
+    let code = packets[0][0] >> 3;
+    let command = match SgbCommand::from_code(code) {
+      Some(command) => command,
+      None => {
+        debug!("Unknown SGB command 0x{:02x}", code);
+        return;
+      },
+    };
+    debug!("SGB command {}", command.description());
+
+    if command.is_transfer() {
+      self.transfer = Some((command, packets[0]));
+      return;
+    }
+
+    // the data of the command continues through all of
+    // its packets, after the command byte
+    let data: Vec<u8> = packets.iter().flatten().skip(1).copied().collect();
+    match command {
+      SgbCommand::Pal01 => self.set_palettes(0, 1, &data),
+      SgbCommand::Pal23 => self.set_palettes(2, 3, &data),
+      SgbCommand::Pal03 => self.set_palettes(0, 3, &data),
+      SgbCommand::Pal12 => self.set_palettes(1, 2, &data),
+      SgbCommand::AttrBlk => self.attr_blk(&data),
+      SgbCommand::AttrLin => self.attr_lin(&data),
+      SgbCommand::AttrDiv => self.attr_div(&data),
+      SgbCommand::AttrChr => self.attr_chr(&data),
+      SgbCommand::PalSet => self.pal_set(&data),
+      SgbCommand::AttrSet => self.attr_set(data[0]),
+      SgbCommand::MaskEn => self.set_mask(SgbMask::from_value(data[0])),
+      SgbCommand::MltReq => self.mlt_req(data[0]),
+      _ => debug!("Unsupported SGB command {}", command.description()),
+    }
+  }
+
+  /// If there's a command waiting for a VRAM transfer.
+  pub fn pending_transfer(&self) -> bool {
+    self.transfer.is_some()
+  }
+
+  /// Completes the VRAM transfer of the pending command, with the
+  /// data of the tiles being displayed (as captured by the SGB).
+  pub fn vram_transfer(&mut self, data: &[u8]) {
+    let (command, packet) = match self.transfer.take() {
+      Some(transfer) => transfer,
+      None => return,
+    };
+    if data.len() < TRANSFER_SIZE {
+      return;
+    }
+    match command {
+      SgbCommand::PalTrn => {
+        for (index, palette) in self.system_palettes.iter_mut().enumerate() {
+          *palette = read_palette(&data[index * 8..]);
+        }
+      },
+      SgbCommand::AttrTrn => {
+        for (index, file) in self.attr_files.iter_mut().enumerate() {
+          let offset = index * ATTR_FILE_SIZE;
+          file.copy_from_slice(&data[offset..offset + ATTR_FILE_SIZE]);
+        }
+      },
+      // the tiles are sent in two halves, 0x00-0x7F and 0x80-0xFF
+      SgbCommand::ChrTrn => {
+        let offset = (packet[1] & 0x01) as usize * TRANSFER_SIZE;
+        self.border_tiles[offset..offset + TRANSFER_SIZE].copy_from_slice(&data[..TRANSFER_SIZE]);
+      },
+      // the map is followed by the palettes 4-7 of the SNES
+      SgbCommand::PctTrn => {
+        self.border_map.copy_from_slice(&data[..BORDER_MAP_SIZE]);
+        for (index, palette) in self.border_palettes.iter_mut().enumerate() {
+          for (color_index, color) in palette.iter_mut().enumerate() {
+            *color = read_u16(data, BORDER_MAP_SIZE + index * 32 + color_index * 2);
+          }
+        }
+      },
+      _ => debug!("Unsupported SGB transfer {}", command.description()),
+    }
+  }
+
+  /// Sets the colors 1-3 of two of the palettes, together
+  /// with the color 0 shared by all of them.
+  fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+    let color0 = read_u16(data, 0);
+    for palette in self.palettes.iter_mut() {
+      palette[0] = color0;
+    }
+    for index in 1..4 {
+      self.palettes[first][index] = read_u16(data, index * 2);
+      self.palettes[second][index] = read_u16(data, 6 + index * 2);
+    }
+  }
+
+  fn attr_blk(&mut self, data: &[u8]) {
+    let count = data[0] as usize;
+    for set in data[1..].chunks_exact(6).take(count) {
+      let control = set[0] & 0x07;
+      let inside = set[1] & 0x03;
+      let line = (set[1] >> 2) & 0x03;
+      let outside = (set[1] >> 4) & 0x03;
+      let (x1, y1, x2, y2) = (
+        set[2] as usize,
+        set[3] as usize,
+        set[4] as usize,
+        set[5] as usize,
+      );
+      // when only the inside or the outside is changed the
+      // surrounding line is changed together with it
+      let line = match control {
+        0x01 => Some(inside),
+        0x04 => Some(outside),
+        _ if control & 0x02 == 0x02 => Some(line),
+        _ => None,
+      };
+      for y in 0..ATTR_HEIGHT {
+        for x in 0..ATTR_WIDTH {
+          let palette = if x > x1 && x < x2 && y > y1 && y < y2 {
+            (control & 0x01 == 0x01).then_some(inside)
+          } else if x >= x1 && x <= x2 && y >= y1 && y <= y2 {
+            line
+          } else {
+            (control & 0x04 == 0x04).then_some(outside)
+          };
+          if let Some(palette) = palette {
+            self.attr_map[y * ATTR_WIDTH + x] = palette;
+          }
+        }
+      }
+    }
+  }
+
+  fn attr_lin(&mut self, data: &[u8]) {
+    let count = data[0] as usize;
+    for &line in data[1..].iter().take(count) {
+      let index = (line & 0x1f) as usize;
+      let palette = (line >> 5) & 0x03;
+      if line & 0x80 == 0x80 {
+        if index < ATTR_HEIGHT {
+          self.attr_map[index * ATTR_WIDTH..(index + 1) * ATTR_WIDTH].fill(palette);
+        }
+      } else if index < ATTR_WIDTH {
+        for y in 0..ATTR_HEIGHT {
+          self.attr_map[y * ATTR_WIDTH + index] = palette;
+        }
+      }
+    }
+  }
+
+  fn attr_div(&mut self, data: &[u8]) {
+    let after = data[0] & 0x03;
+    let before = (data[0] >> 2) & 0x03;
+    let line = (data[0] >> 4) & 0x03;
+    let horizontal = data[0] & 0x40 == 0x40;
+    let coordinate = data[1] as usize;
+    for y in 0..ATTR_HEIGHT {
+      for x in 0..ATTR_WIDTH {
+        let position = if horizontal { y } else { x };
+        self.attr_map[y * ATTR_WIDTH + x] = match position.cmp(&coordinate) {
+          std::cmp::Ordering::Less => before,
+          std::cmp::Ordering::Equal => line,
+          std::cmp::Ordering::Greater => after,
+        };
+      }
+    }
+  }
+
+  fn attr_chr(&mut self, data: &[u8]) {
+    let (mut x, mut y) = (data[0] as usize, data[1] as usize);
+    let count = (read_u16(data, 2) as usize).min(ATTR_SIZE);
+    let vertical = data[4] & 0x01 == 0x01;
+    for index in 0..count {
+      if x >= ATTR_WIDTH || y >= ATTR_HEIGHT {
+        break;
+      }
+      let byte = match data.get(5 + index / 4) {
+        Some(&byte) => byte,
+        None => break,
+      };
+      self.attr_map[y * ATTR_WIDTH + x] = (byte >> (6 - (index % 4) * 2)) & 0x03;
+      if vertical {
+        y += 1;
+        if y == ATTR_HEIGHT {
+          y = 0;
+          x += 1;
+        }
+      } else {
+        x += 1;
+        if x == ATTR_WIDTH {
+          x = 0;
+          y += 1;
+        }
+      }
+    }
+  }
+
+  /// Sets the four palettes from the system ones (sent through
+  /// PAL_TRN), with the color 0 of the first one being shared.
+  fn pal_set(&mut self, data: &[u8]) {
+    for index in 0..4 {
+      let system = (read_u16(data, index * 2) as usize) % SYSTEM_PALETTE_COUNT;
+      self.palettes[index] = self.system_palettes[system];
+    }
+    let color0 = self.palettes[0][0];
+    for palette in self.palettes.iter_mut() {
+      palette[0] = color0;
+    }
+    if data[8] & 0x80 == 0x80 {
+      self.attr_set(data[8]);
+    } else if data[8] & 0x40 == 0x40 {
+      self.set_mask(SgbMask::Cancel);
+    }
+  }
+
+  /// Applies one of the attribute files (sent through ATTR_TRN),
+  /// optionally cancelling the screen mask.
+  fn attr_set(&mut self, value: u8) {
+    let file = (value & 0x3f) as usize;
+    if let Some(file) = self.attr_files.get(file) {
+      for (index, attr) in self.attr_map.iter_mut().enumerate() {
+        *attr = (file[index / 4] >> (6 - (index % 4) * 2)) & 0x03;
+      }
+    }
+    if value & 0x40 == 0x40 {
+      self.set_mask(SgbMask::Cancel);
+    }
+  }
+
+  /// Requests the number of controllers to be read, either
+  /// one (0), two (1) or four (3) of them.
+  fn mlt_req(&mut self, value: u8) {
+    self.player_count = match value & 0x03 {
+      0x01 => 2,
+      0x03 => 4,
+      _ => 1,
+    };
+  }
+
+  fn set_mask(&mut self, mask: SgbMask) {
+    self.mask = mask;
+    self.frozen = None;
+  }
+
+  /// RGB frame as of the last call to `render()`.
+  pub fn frame_buffer(&self) -> &[u8] {
+    &self.frame_buffer
+  }
+
+  /// Renders the frame as output by the SGB, from the shades
+  /// of the frame output by the PPU, either with the border
+  /// (256x224) or just the colorized screen (160x144), as RGB.
+  pub fn render(&mut self, shades: &[u8; SHADE_BUFFER_SIZE], border: bool) -> &[u8] {
+    let (width, height, screen_x, screen_y) = if border {
+      (SGB_WIDTH, SGB_HEIGHT, SCREEN_X, SCREEN_Y)
+    } else {
+      (DISPLAY_WIDTH, DISPLAY_HEIGHT, 0, 0)
+    };
+    self.frame_buffer.resize(width * height * RGB_SIZE, 0);
+
+    let backdrop = rgb555(self.palettes[0][0]);
+    if border {
+      for pixel in self.frame_buffer.chunks_mut(RGB_SIZE) {
+        pixel.copy_from_slice(&backdrop);
+      }
+      self.render_border();
+    }
+
+    if self.mask == SgbMask::Freeze && self.frozen.is_none() {
+      self.frozen = Some(Box::new(*shades));
+    }
+    let frozen = self.frozen.take();
+    let shades = frozen.as_deref().unwrap_or(shades);
+    for y in 0..DISPLAY_HEIGHT {
+      for x in 0..DISPLAY_WIDTH {
+        let color = match self.mask {
+          SgbMask::Black => [0, 0, 0],
+          SgbMask::Color0 => backdrop,
+          _ => {
+            let palette = self.attr_map[(y / 8) * ATTR_WIDTH + x / 8] as usize;
+            let shade = shades[y * DISPLAY_WIDTH + x] as usize;
+            rgb555(self.palettes[palette][shade])
+          },
+        };
+        let offset = ((screen_y + y) * width + screen_x + x) * RGB_SIZE;
+        self.frame_buffer[offset..offset + RGB_SIZE].copy_from_slice(&color);
+      }
+    }
+    self.frozen = frozen;
+
+    &self.frame_buffer
+  }
+
+  /// Draws the 32x28 tiles of the border, the color 0 of the
+  /// border palettes being transparent (showing the backdrop).
+  fn render_border(&mut self) {
+    for map_y in 0..SGB_HEIGHT / 8 {
+      for map_x in 0..SGB_WIDTH / 8 {
+        let entry = read_u16(&self.border_map, (map_y * 32 + map_x) * 2);
+        let tile = (entry & 0xff) as usize * BORDER_TILE_SIZE;
+        let palette = &self.border_palettes[((entry >> 10) & 0x03) as usize];
+        let flip_x = entry & 0x4000 == 0x4000;
+        let flip_y = entry & 0x8000 == 0x8000;
+        for y in 0..8 {
+          let row = tile + if flip_y { 7 - y } else { y } * 2;
+          let planes = [
+            self.border_tiles[row],
+            self.border_tiles[row + 1],
+            self.border_tiles[row + 16],
+            self.border_tiles[row + 17],
+          ];
+          for x in 0..8 {
+            let bit = if flip_x { x } else { 7 - x };
+            let color_index = planes.iter().enumerate().fold(0, |value, (plane, byte)| {
+              value | (((byte >> bit) & 0x01) as usize) << plane
+            });
+            if color_index == 0 {
+              continue;
+            }
+            let offset = ((map_y * 8 + y) * SGB_WIDTH + map_x * 8 + x) * RGB_SIZE;
+            self.frame_buffer[offset..offset + RGB_SIZE]
+              .copy_from_slice(&rgb555(palette[color_index]));
+          }
+        }
+      }
+    }
+  }
+}
+
+impl Default for Sgb {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Converts a RGB555 (SNES) color into RGB888.
+pub fn rgb555(color: u16) -> Pixel {
+  let expand = |value: u16| {
+    let value = (value & 0x1f) as u8;
+    (value << 3) | (value >> 2)
+  };
+  [expand(color), expand(color >> 5), expand(color >> 10)]
+}
+
+fn read_palette(data: &[u8]) -> SgbPalette {
+  [
+    read_u16(data, 0),
+    read_u16(data, 2),
+    read_u16(data, 4),
+    read_u16(data, 6),
+  ]
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+  match data.get(offset..offset + 2) {
+    Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+    None => 0x0000,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Builds the packets of a command, with its data following the
+  /// command byte through as many packets as needed.
+  fn packets(command: u8, data: &[u8]) -> Vec<[u8; PACKET_SIZE]> {
+    let mut bytes = data.to_vec();
+    let count = (bytes.len() / (PACKET_SIZE - 1)) + 1;
+    bytes.insert(0, command << 3 | count as u8);
+    bytes.resize(count * PACKET_SIZE, 0x00);
+    bytes
+      .chunks_exact(PACKET_SIZE)
+      .map(|chunk| chunk.try_into().unwrap())
+      .collect()
+  }
+
+  /// Pulses P14 and P15 as the game would to send the packet,
+  /// starting with the reset pulse and ending with the stop bit.
+  fn send_packet_bits(sgb: &mut Sgb, packet: &[u8; PACKET_SIZE], stop: bool) {
+    sgb.write_p1(0x00);
+    sgb.write_p1(0x30);
+    for index in 0..PACKET_SIZE * 8 + 1 {
+      let bit = packet
+        .get(index / 8)
+        .is_some_and(|byte| byte >> (index % 8) & 0x01 == 0x01);
+      let bit = if index == PACKET_SIZE * 8 { stop } else { bit };
+      sgb.write_p1(if bit { 0x10 } else { 0x20 });
+      sgb.write_p1(0x30);
+    }
+  }
+
+  fn send_packet(sgb: &mut Sgb, packet: &[u8; PACKET_SIZE]) {
+    send_packet_bits(sgb, packet, false);
+  }
+
+  fn send(sgb: &mut Sgb, command: u8, data: &[u8]) {
+    for packet in packets(command, data) {
+      send_packet(sgb, &packet);
+    }
+  }
+
+  fn enabled_sgb() -> Sgb {
+    let mut sgb = Sgb::new();
+    sgb.set_enabled(true);
+    sgb
+  }
+
+  fn attr(sgb: &Sgb, x: usize, y: usize) -> u8 {
+    sgb.attr_map()[y * ATTR_WIDTH + x]
+  }
+
+  const PAL01: [u8; 14] = [
+    0x11, 0x11, 0x22, 0x22, 0x33, 0x33, 0x44, 0x44, 0x55, 0x55, 0x66, 0x66, 0x77, 0x77,
+  ];
+
+  #[test]
+  fn test_packet() {
+    let mut sgb = enabled_sgb();
+    send(&mut sgb, 0x00, &PAL01);
+    assert_eq!(
+      sgb.palettes(),
+      &[
+        [0x1111, 0x2222, 0x3333, 0x4444],
+        [0x1111, 0x5555, 0x6666, 0x7777],
+        [
+          0x1111,
+          DEFAULT_PALETTE[1],
+          DEFAULT_PALETTE[2],
+          DEFAULT_PALETTE[3]
+        ],
+        [
+          0x1111,
+          DEFAULT_PALETTE[1],
+          DEFAULT_PALETTE[2],
+          DEFAULT_PALETTE[3]
+        ],
+      ]
+    );
+
+    // a packet with a stop bit of 1 is dropped
+    let mut sgb = enabled_sgb();
+    let packet = packets(0x17, &[0x01])[0];
+    send_packet_bits(&mut sgb, &packet, true);
+    assert_eq!(sgb.mask(), SgbMask::Cancel);
+
+    // a reset pulse restarts the packet being received
+    sgb.write_p1(0x00);
+    sgb.write_p1(0x30);
+    sgb.write_p1(0x10);
+    sgb.write_p1(0x30);
+    send_packet(&mut sgb, &packet);
+    assert_eq!(sgb.mask(), SgbMask::Freeze);
+
+    // the packets are ignored for games not supporting the SGB
+    let mut sgb = Sgb::new();
+    send(&mut sgb, 0x17, &[0x01]);
+    assert_eq!(sgb.mask(), SgbMask::Cancel);
+  }
+
+  #[test]
+  fn test_attr_blk() {
+    let mut sgb = enabled_sgb();
+    // inside (1), line (2) and outside (3) of a 2,2 - 5,5 block
+    send(&mut sgb, 0x04, &[0x01, 0x07, 0x39, 2, 2, 5, 5]);
+    assert_eq!(attr(&sgb, 3, 3), 1);
+    assert_eq!(attr(&sgb, 4, 4), 1);
+    assert_eq!(attr(&sgb, 2, 2), 2);
+    assert_eq!(attr(&sgb, 5, 4), 2);
+    assert_eq!(attr(&sgb, 0, 0), 3);
+    assert_eq!(attr(&sgb, 6, 3), 3);
+
+    // the line is changed together with the inside alone, with
+    // the second data set (in the second packet) setting the line
+    send(
+      &mut sgb,
+      0x04,
+      &[
+        0x03, 0x01, 0x00, 0, 0, 1, 1, 0x00, 0x00, 0, 0, 0, 0, 0x02, 0x08, 9, 9, 10, 10,
+      ],
+    );
+    assert_eq!(attr(&sgb, 0, 0), 0);
+    assert_eq!(attr(&sgb, 1, 1), 0);
+    assert_eq!(attr(&sgb, 2, 2), 2);
+    assert_eq!(attr(&sgb, 10, 9), 2);
+    assert_eq!(attr(&sgb, 11, 11), 3);
+  }
+
+  #[test]
+  fn test_attr_lin() {
+    let mut sgb = enabled_sgb();
+    // row 3 with palette 1 and then column 5 with palette 2
+    send(&mut sgb, 0x05, &[0x02, 0x80 | 0x20 | 3, 0x40 | 5]);
+    assert_eq!(attr(&sgb, 0, 3), 1);
+    assert_eq!(attr(&sgb, 19, 3), 1);
+    assert_eq!(attr(&sgb, 5, 3), 2);
+    assert_eq!(attr(&sgb, 5, 17), 2);
+    assert_eq!(attr(&sgb, 0, 0), 0);
+  }
+
+  #[test]
+  fn test_attr_div() {
+    let mut sgb = enabled_sgb();
+    // rows before 10 with palette 1, row 10 with 2 and after with 3
+    send(&mut sgb, 0x06, &[0x40 | 0x20 | 0x04 | 0x03, 10]);
+    assert_eq!(attr(&sgb, 0, 9), 1);
+    assert_eq!(attr(&sgb, 19, 10), 2);
+    assert_eq!(attr(&sgb, 7, 11), 3);
+
+    // columns before 4 with palette 3, column 4 with 0 and after with 1
+    send(&mut sgb, 0x06, &[0x0c | 0x01, 4]);
+    assert_eq!(attr(&sgb, 3, 17), 3);
+    assert_eq!(attr(&sgb, 4, 0), 0);
+    assert_eq!(attr(&sgb, 5, 9), 1);
+  }
+
+  #[test]
+  fn test_attr_chr() {
+    let mut sgb = enabled_sgb();
+    // four tiles from 18,0 wrapping into the next row
+    send(&mut sgb, 0x07, &[18, 0, 4, 0, 0x00, 0b01_10_11_00]);
+    assert_eq!(attr(&sgb, 18, 0), 1);
+    assert_eq!(attr(&sgb, 19, 0), 2);
+    assert_eq!(attr(&sgb, 0, 1), 3);
+    assert_eq!(attr(&sgb, 1, 1), 0);
+
+    // 60 tiles down the columns, with the data in two packets
+    let mut data = vec![0, 0, 60, 0, 0x01];
+    data.extend_from_slice(&[0xff; 15]);
+    send(&mut sgb, 0x07, &data);
+    assert_eq!(attr(&sgb, 0, 17), 3);
+    assert_eq!(attr(&sgb, 2, 0), 3);
+    assert_eq!(attr(&sgb, 3, 5), 3);
+    assert_eq!(attr(&sgb, 3, 6), 0);
+  }
+
+  #[test]
+  fn test_attr_set() {
+    let mut sgb = enabled_sgb();
+    send(&mut sgb, 0x17, &[0x01]);
+    assert_eq!(sgb.mask(), SgbMask::Freeze);
+
+    // the attribute files are sent through a VRAM transfer
+    send(&mut sgb, 0x15, &[]);
+    assert!(sgb.pending_transfer());
+    let mut data = vec![0x00; TRANSFER_SIZE];
+    data[ATTR_FILE_SIZE..ATTR_FILE_SIZE * 2].fill(0x55);
+    data[ATTR_FILE_SIZE] = 0xe4;
+    sgb.vram_transfer(&data);
+    assert!(!sgb.pending_transfer());
+
+    // applying the file 1 without and then with the mask cancel
+    send(&mut sgb, 0x16, &[0x01]);
+    assert_eq!(&sgb.attr_map()[..5], &[3, 2, 1, 0, 1]);
+    assert_eq!(attr(&sgb, 19, 17), 1);
+    assert_eq!(sgb.mask(), SgbMask::Freeze);
+    send(&mut sgb, 0x16, &[0x40]);
+    assert_eq!(attr(&sgb, 19, 17), 0);
+    assert_eq!(sgb.mask(), SgbMask::Cancel);
+  }
+
+  #[test]
+  fn test_mlt_req() {
+    let mut sgb = enabled_sgb();
+    for (value, count) in [(0x01, 2), (0x03, 4), (0x02, 1), (0x00, 1)] {
+      send(&mut sgb, 0x11, &[value]);
+      assert_eq!(sgb.player_count(), count);
+    }
+  }
+}
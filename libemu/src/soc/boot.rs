@@ -32,6 +32,18 @@ impl Boot {
   pub fn set_active(&mut self, active: bool) {
     self.boot_active = active;
   }
+
+  /// Checks if the address is currently mapped to the boot ROM,
+  /// the CGB boot ROM leaves 0x0100-0x01FF (the cartridge header)
+  /// unmapped, being split around it.
+  pub fn maps(&self, addr: u16) -> bool {
+    self.boot_active
+      && match addr {
+        0x0000..=0x00ff => true,
+        0x0200..=0x08ff => self.data.len() > 0x100,
+        _ => false,
+      }
+  }
 }
 
 impl Address for Boot {
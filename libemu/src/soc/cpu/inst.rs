@@ -569,6 +569,7 @@ fn ld_mbc_a(cpu: &mut Cpu) {
 }
 
 fn inc_bc(cpu: &mut Cpu) {
+  cpu.oam_bug(cpu.bc());
   cpu.set_bc(cpu.bc().wrapping_add(1));
 }
 
@@ -627,6 +628,7 @@ fn ld_a_mbc(cpu: &mut Cpu) {
 }
 
 fn dec_bc(cpu: &mut Cpu) {
+  cpu.oam_bug(cpu.bc());
   cpu.set_bc(cpu.bc().wrapping_sub(1));
 }
 
@@ -681,6 +683,7 @@ fn ld_mde_a(cpu: &mut Cpu) {
 }
 
 fn inc_de(cpu: &mut Cpu) {
+  cpu.oam_bug(cpu.de());
   cpu.set_de(cpu.de().wrapping_add(1));
 }
 
@@ -739,6 +742,7 @@ fn ld_a_mde(cpu: &mut Cpu) {
 }
 
 fn dec_de(cpu: &mut Cpu) {
+  cpu.oam_bug(cpu.de());
   cpu.set_de(cpu.de().wrapping_sub(1));
 }
 
@@ -799,10 +803,12 @@ fn ld_hl_u16(cpu: &mut Cpu) {
 
 fn ld_mhli_a(cpu: &mut Cpu) {
   cpu.bus_mut().write(cpu.hl(), cpu.regs.a);
+  cpu.oam_bug(cpu.hl());
   cpu.set_hl(cpu.hl().wrapping_add(1));
 }
 
 fn inc_hl(cpu: &mut Cpu) {
+  cpu.oam_bug(cpu.hl());
   cpu.set_hl(cpu.hl().wrapping_add(1));
 }
 
@@ -886,10 +892,12 @@ fn add_hl_hl(cpu: &mut Cpu) {
 fn ld_a_mhli(cpu: &mut Cpu) {
   let byte = cpu.bus().read(cpu.hl());
   cpu.regs.a = byte;
+  cpu.oam_bug(cpu.hl());
   cpu.set_hl(cpu.hl().wrapping_add(1));
 }
 
 fn dec_hl(cpu: &mut Cpu) {
+  cpu.oam_bug(cpu.hl());
   cpu.set_hl(cpu.hl().wrapping_sub(1));
 }
 
@@ -944,10 +952,12 @@ fn jr_nc_i8(cpu: &mut Cpu) {
 
 fn ld_mhld_a(cpu: &mut Cpu) {
   cpu.bus_mut().write(cpu.hl(), cpu.regs.a);
+  cpu.oam_bug(cpu.hl());
   cpu.set_hl(cpu.hl().wrapping_sub(1));
 }
 
 fn inc_sp(cpu: &mut Cpu) {
+  cpu.oam_bug(cpu.regs.sp);
   cpu.regs.sp = cpu.regs.sp.wrapping_add(1);
 }
 
@@ -1003,10 +1013,12 @@ fn add_hl_sp(cpu: &mut Cpu) {
 fn ld_a_mhld(cpu: &mut Cpu) {
   let byte = cpu.bus().read(cpu.hl());
   cpu.regs.a = byte;
+  cpu.oam_bug(cpu.hl());
   cpu.set_hl(cpu.hl().wrapping_sub(1));
 }
 
 fn dec_sp(cpu: &mut Cpu) {
+  cpu.oam_bug(cpu.regs.sp);
   cpu.regs.sp = cpu.regs.sp.wrapping_sub(1);
 }
 
@@ -5,6 +5,7 @@ use std::cell::{Ref, RefMut};
 
 use crate::{
   bus::Bus,
+  cdl::{CodeDataLog, CDL_CODE, CDL_DATA, CDL_OPERAND},
   generic::{address::Address, shared::Shared},
 };
 
@@ -34,8 +35,14 @@ pub struct Cpu {
   carry: bool,
   halted: bool,
 
+  /// If the last clock dispatched an interrupt rather
+  /// than executing an instruction.
+  interrupted: bool,
+
   pub bus: Shared<Bus>,
   pub cycles: u8,
+
+  cdl: Option<Shared<CodeDataLog>>,
 }
 
 impl Cpu {
@@ -46,6 +53,16 @@ impl Cpu {
   pub fn bus_mut(&self) -> RefMut<Bus> {
     self.bus.borrow_mut()
   }
+
+  /// Signals the bus that a 16-bit register holding the provided
+  /// address has been incremented or decremented, which may
+  /// trigger the OAM corruption bug.
+  #[inline(always)]
+  pub fn oam_bug(&self, addr: u16) {
+    if (0xfe00..=0xfeff).contains(&addr) {
+      self.bus_mut().oam_bug(addr);
+    }
+  }
 }
 
 impl Cpu {
@@ -58,8 +75,10 @@ impl Cpu {
       half_carry: false,
       carry: false,
       halted: false,
+      interrupted: false,
       bus,
       cycles: 0,
+      cdl: None,
     }
   }
 }
@@ -68,14 +87,19 @@ impl Cpu {
   pub fn clock(&mut self) -> u8 {
     let pc = self.regs.pc;
 
+    // the IE register is peeked so that these checks, made before
+    // every instruction, are not seen as accesses in the bus trace
+    let ie = self.bus().peek(0xFFFF);
+    self.interrupted = false;
+
     if self.halted
       && !self.ime
-      && self.bus().read(0xFFFF) != 0x00
-      && (((self.bus().read(0xFFFF) & 0x01 == 0x01) && self.bus().ppu().int_vblank())
-        || ((self.bus().read(0xFFFF) & 0x02 == 0x02) && self.bus().ppu().int_stat())
-        // || ((self.bus.read(0xFFFF) & 0x04 == 0x04) && self.bus.timer().int_tima())
-        // || ((self.bus.read(0xFFFF) & 0x08 == 0x08) && self.bus.serial().int_serial())
-        || ((self.bus().read(0xFFFF) & 0x10 == 0x10) && self.bus().pad().int_pad()))
+      && ie != 0x00
+      && (((ie & 0x01 == 0x01) && self.bus().ppu().int_vblank())
+        || ((ie & 0x02 == 0x02) && self.bus().ppu().int_stat())
+        // || ((ie & 0x04 == 0x04) && self.bus.timer().int_tima())
+        // || ((ie & 0x08 == 0x08) && self.bus.serial().int_serial())
+        || ((ie & 0x10 == 0x10) && self.bus().pad().int_pad()))
     {
       self.halted = false;
     }
@@ -85,12 +109,13 @@ impl Cpu {
     // to check which one should be handled and then handles it
     // this code assumes that the're no more that one interrupt triggered
     // per clock cycle, this is a limitation of the current implementation
-    if self.ime && self.bus().read(0xFFFF) != 0x00 {
-      if (self.bus().read(0xFFFF) & 0x01 == 0x01) && self.bus().ppu().int_vblank() {
+    if self.ime && ie != 0x00 {
+      if (ie & 0x01 == 0x01) && self.bus().ppu().int_vblank() {
         // debugln!("Going to run V-Blank interrupt handler (0x40)");
 
         self.disable_int();
         self.push_word(pc);
+        self.interrupted = true;
         self.regs.pc = 0x40;
 
         // acknowledges that the V-Blank interrupt has been
@@ -104,11 +129,12 @@ impl Cpu {
         }
 
         return 20;
-      } else if (self.bus.read(0xFFFF) & 0x02 == 0x02) && self.bus().ppu().int_stat() {
+      } else if (ie & 0x02 == 0x02) && self.bus().ppu().int_stat() {
         // debugln!("Going to run LCD STAT interrupt handler (0x48)");
 
         self.disable_int();
         self.push_word(pc);
+        self.interrupted = true;
         self.regs.pc = 0x48;
 
         // acknowledges that the STAT interrupt has been
@@ -122,11 +148,12 @@ impl Cpu {
         }
 
         return 20;
-      } else if (self.bus.read(0xFFFF) & 0x10 == 0x10) && self.bus().pad().int_pad() {
+      } else if (ie & 0x10 == 0x10) && self.bus().pad().int_pad() {
         // debugln!("Going to run JoyPad interrupt handler (0x60)");
 
         self.disable_int();
         self.push_word(pc);
+        self.interrupted = true;
         self.regs.pc = 0x60;
 
         // acknowledges that the pad interrupt has been
@@ -150,6 +177,7 @@ impl Cpu {
       return 4;
     }
 
+    self.set_cdl_access(CDL_CODE);
     let mut opcode = self.bus.read(self.regs.pc);
     self.regs.pc = self.regs.pc.wrapping_add(1);
 
@@ -163,6 +191,7 @@ impl Cpu {
     } else {
       inst = &INSTRUCTIONS[opcode as usize];
     }
+    self.set_cdl_access(CDL_DATA);
 
     #[allow(unused_variables)]
     let (inst_fn, inst_time, inst_str) = inst;
@@ -173,12 +202,42 @@ impl Cpu {
     self.cycles = 0;
     inst_fn(self);
     self.cycles = self.cycles.wrapping_add(*inst_time);
+    self.set_cdl_access(0x00);
 
     // returns the number of cycles that the operation
     // that has been executed has taken
     self.cycles
   }
 
+  /// Disassembles the instruction at the provided address, returning
+  /// its textual representation (with the immediate operands
+  /// resolved) and its length in bytes.
+  pub fn disassemble(&self, addr: u16) -> (String, u16) {
+    let bus = self.bus();
+    let opcode = bus.peek(addr);
+    let (inst, mut length) = if opcode == PREFIX {
+      (&EXTENDED[bus.peek(addr.wrapping_add(1)) as usize], 2)
+    } else {
+      (&INSTRUCTIONS[opcode as usize], 1)
+    };
+    let operand = addr.wrapping_add(length);
+
+    let mut text = String::from(inst.2);
+    if text.contains("u16") {
+      let word = bus.peek(operand) as u16 | (bus.peek(operand.wrapping_add(1)) as u16) << 8;
+      text = text.replace("u16", &format!("${:04X}", word));
+      length += 2;
+    } else if text.contains("u8") {
+      text = text.replace("u8", &format!("${:02X}", bus.peek(operand)));
+      length += 1;
+    } else if text.contains("i8") {
+      text = text.replace("i8", &format!("{:+}", bus.peek(operand) as i8));
+      length += 1;
+    }
+
+    (text, length)
+  }
+
   pub fn reset(&mut self) {
     self.regs.pc = 0x0100;
     self.regs.sp = 0xfffe;
@@ -196,6 +255,7 @@ impl Cpu {
     self.half_carry = false;
     self.carry = false;
     self.halted = false;
+    self.interrupted = false;
   }
 }
 
@@ -302,8 +362,10 @@ impl Cpu {
 
   #[inline(always)]
   pub fn read_u8(&mut self) -> u8 {
+    self.set_cdl_access(CDL_OPERAND);
     let byte = self.bus().read(self.regs.pc);
     self.regs.pc = self.regs.pc.wrapping_add(1);
+    self.set_cdl_access(CDL_DATA);
     byte
   }
 
@@ -384,6 +446,30 @@ impl Cpu {
     self.halted = true;
   }
 
+  #[inline(always)]
+  pub fn halted(&self) -> bool {
+    self.halted
+  }
+
+  #[inline(always)]
+  pub fn interrupted(&self) -> bool {
+    self.interrupted
+  }
+
+  /// Sets the code/data log the kind of the ROM accesses made
+  /// by the CPU is reported to, the log itself is updated by
+  /// the cartridge on each read.
+  pub fn set_cdl(&mut self, cdl: Option<Shared<CodeDataLog>>) {
+    self.cdl = cdl;
+  }
+
+  #[inline(always)]
+  fn set_cdl_access(&self, access: u8) {
+    if let Some(cdl) = &self.cdl {
+      cdl.borrow_mut().set_access(access);
+    }
+  }
+
   #[inline(always)]
   pub fn stop(&mut self) {}
 
@@ -1,4 +1,4 @@
-use log::warn;
+use log::{debug, warn};
 
 use crate::generic::{address::Address, device::Device};
 
@@ -63,6 +63,11 @@ impl Address for Dma {
     match addr {
       // 0xFF46 — DMA: OAM DMA source address & start
       0xFF46 => self.value_dma,
+      // 0xFF51-0xFF55 — HDMA1-HDMA5: VRAM DMA (CGB only)
+      0xFF51..=0xFF55 => {
+        debug!("Reading from unimplemented HDMA location 0x{:04x}", addr);
+        0xff
+      },
       _ => {
         warn!("Reading from unknown DMA location 0x{:04x}", addr);
         0xff
@@ -78,6 +83,8 @@ impl Address for Dma {
         self.cycles_dma = 640;
         self.active_dma = true;
       },
+      // 0xFF51-0xFF55 — HDMA1-HDMA5: VRAM DMA (CGB only)
+      0xFF51..=0xFF55 => debug!("Writing to unimplemented HDMA location 0x{:04x}", addr),
       _ => warn!("Writing to unknown DMA location 0x{:04x}", addr),
     }
   }
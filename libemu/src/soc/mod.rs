@@ -4,6 +4,7 @@ use std::cell::{Ref, RefMut};
 use crate::{
   bus::Bus,
   generic::{memory::Ram, shared::Shared},
+  profiler::{ProfileStep, Profiler},
 };
 
 use self::{boot::Boot, cpu::Cpu, dma::Dma, ppu::Ppu};
@@ -24,6 +25,11 @@ pub struct Soc {
   hram: Shared<Hram>,
 
   bus: Shared<Bus>,
+
+  /// Number of CPU cycles executed since the last reset.
+  cycles: u64,
+
+  profiler: Option<Profiler>,
 }
 
 impl Soc {
@@ -44,16 +50,45 @@ impl Soc {
       boot,
       hram,
       bus,
+      cycles: 0,
+      profiler: None,
     }
   }
 
   pub fn reset(&mut self) {
     self.cpu.reset();
     self.ppu.borrow_mut().reset();
+    self.cycles = 0;
   }
 }
 
 impl Soc {
+  pub fn cpu(&self) -> &Cpu {
+    &self.cpu
+  }
+
+  pub fn cpu_mut(&mut self) -> &mut Cpu {
+    &mut self.cpu
+  }
+
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
+  pub fn profiler(&self) -> Option<&Profiler> {
+    self.profiler.as_ref()
+  }
+
+  /// Starts (or restarts) profiling the execution of the CPU.
+  pub fn start_profiler(&mut self) {
+    self.profiler = Some(Profiler::new());
+  }
+
+  /// Stops profiling, returning the data collected so far.
+  pub fn stop_profiler(&mut self) -> Option<Profiler> {
+    self.profiler.take()
+  }
+
   pub fn ppu(&self) -> Ref<Ppu> {
     self.ppu.borrow()
   }
@@ -65,13 +100,44 @@ impl Soc {
 
 impl Soc {
   pub fn clock_cpu(&mut self) -> u8 {
+    if let Some(trace) = self.bus.borrow().trace() {
+      let bank = self.bus.borrow().rom_bank();
+      trace.set_context(self.cpu.pc(), bank, self.cycles);
+    }
+    let step = self.profiler.as_ref().map(|_| self.profile_step());
     let cycles = self.cpu.clock();
-    if self.cpu.pc() == 0x00FE {
-      self.boot.borrow_mut().set_active(false);
+    self.cycles += cycles as u64;
+    if let Some(mut step) = step {
+      step.cycles = cycles;
+      step.next_pc = self.cpu.pc();
+      step.next_sp = self.cpu.sp();
+      step.next_bank = self.bus.borrow().rom_bank();
+      step.interrupt = self.cpu.interrupted();
+      if let Some(profiler) = self.profiler.as_mut() {
+        profiler.record(&step);
+      }
     }
     cycles
   }
 
+  /// Captures the state of the CPU before the execution of the
+  /// next instruction, to be completed once it's executed.
+  fn profile_step(&self) -> ProfileStep {
+    let bus = self.bus.borrow();
+    let (pc, sp, bank) = (self.cpu.pc(), self.cpu.sp(), bus.rom_bank());
+    ProfileStep {
+      pc,
+      sp,
+      bank,
+      opcode: bus.peek(pc),
+      cycles: 0,
+      next_pc: pc,
+      next_sp: sp,
+      next_bank: bank,
+      interrupt: false,
+    }
+  }
+
   pub fn clock_ppu(&mut self, cycles: u16) {
     self.ppu_mut().clock(cycles)
   }
@@ -87,8 +153,10 @@ impl Soc {
         let data = self
           .bus
           .borrow()
-          .read_many((self.dma.borrow().value_dma() as u16) << 8, 160);
-        self.bus.borrow_mut().write_many(0xfe00, &data);
+          .peek_many((self.dma.borrow().value_dma() as u16) << 8, 160);
+        for (index, byte) in data.iter().enumerate() {
+          self.ppu_mut().write_oam(0xfe00 + index as u16, *byte);
+        }
         self.dma.borrow_mut().set_active_dma(false);
       }
       self.dma.borrow_mut().set_cycles_dma(cycles_dma);
@@ -0,0 +1,268 @@
+use super::{
+  object::ObjectData, Palette, Pixel, Ppu, DISPLAY_HEIGHT, DISPLAY_WIDTH, OBJ_COUNT, PALETTE_SIZE,
+  RGB_SIZE, TILE_COUNT, TILE_HEIGHT, TILE_WIDTH,
+};
+
+/// Number of tiles per row in the tile data view.
+pub const TILES_VIEW_COLUMNS: usize = 16;
+
+pub const TILES_VIEW_WIDTH: usize = TILES_VIEW_COLUMNS * TILE_WIDTH;
+pub const TILES_VIEW_HEIGHT: usize = TILE_COUNT / TILES_VIEW_COLUMNS * TILE_HEIGHT;
+pub const TILES_VIEW_SIZE: usize = TILES_VIEW_WIDTH * TILES_VIEW_HEIGHT * RGB_SIZE;
+
+/// Tile maps are 32x32 tiles, meaning 256x256 pixels.
+pub const MAP_VIEW_WIDTH: usize = 256;
+pub const MAP_VIEW_HEIGHT: usize = 256;
+pub const MAP_VIEW_SIZE: usize = MAP_VIEW_WIDTH * MAP_VIEW_HEIGHT * RGB_SIZE;
+
+/// Color used to outline the visible area in the tile map views.
+pub const OVERLAY_COLOR: Pixel = [255, 0, 0];
+
+/// Layers that can be individually hidden for debugging purposes,
+/// hidden layers are rendered as color 0 without affecting timing.
+pub const LAYER_BG: u8 = 0x01;
+pub const LAYER_WINDOW: u8 = 0x02;
+pub const LAYER_OBJ: u8 = 0x04;
+pub const LAYER_ALL: u8 = LAYER_BG | LAYER_WINDOW | LAYER_OBJ;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TileMap {
+  /// Tile map at 0x9800-0x9BFF.
+  Map9800,
+  /// Tile map at 0x9C00-0x9FFF.
+  Map9C00,
+}
+
+impl TileMap {
+  pub fn from_flag(flag: bool) -> Self {
+    if flag {
+      TileMap::Map9C00
+    } else {
+      TileMap::Map9800
+    }
+  }
+
+  pub fn description(&self) -> &'static str {
+    match self {
+      TileMap::Map9800 => "0x9800",
+      TileMap::Map9C00 => "0x9C00",
+    }
+  }
+
+  fn offset(&self) -> usize {
+    match self {
+      TileMap::Map9800 => 0x1800,
+      TileMap::Map9C00 => 0x1c00,
+    }
+  }
+}
+
+/// Area of a tile map to be outlined, as visible on the LCD.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapOverlay {
+  None,
+  /// Background viewport, at SCX and SCY and wrapping
+  /// around the edges of the map.
+  Scroll,
+  /// Part of the window shown from WX-7 and WY to the bottom
+  /// right corner of the LCD, nothing if the window is off.
+  Window,
+}
+
+/// Mapping of the four color indexes of each of the DMG
+/// palette registers into shades (0 lightest to 3 darkest).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PaletteView {
+  pub bgp: [u8; PALETTE_SIZE],
+  pub obp0: [u8; PALETTE_SIZE],
+  pub obp1: [u8; PALETTE_SIZE],
+}
+
+impl PaletteView {
+  fn shades(value: u8) -> [u8; PALETTE_SIZE] {
+    let mut shades = [0u8; PALETTE_SIZE];
+    for (index, shade) in shades.iter_mut().enumerate() {
+      *shade = (value >> (index * 2)) & 3;
+    }
+    shades
+  }
+}
+
+impl Ppu {
+  /// Renders the complete set of 384 tiles into an RGB image
+  /// of 16 tiles per row, the raw color indexes of the tiles
+  /// are used (no palette register is applied).
+  pub fn render_tiles(&self) -> Vec<u8> {
+    let mut buffer = vec![0u8; TILES_VIEW_SIZE];
+    for (index, tile) in self.tiles.iter().enumerate() {
+      let base_x = (index % TILES_VIEW_COLUMNS) * TILE_WIDTH;
+      let base_y = (index / TILES_VIEW_COLUMNS) * TILE_HEIGHT;
+      for y in 0..TILE_HEIGHT {
+        for x in 0..TILE_WIDTH {
+          let color = &self.palette_colors[tile.get(x, y) as usize];
+          let offset = ((base_y + y) * TILES_VIEW_WIDTH + base_x + x) * RGB_SIZE;
+          buffer[offset..offset + RGB_SIZE].copy_from_slice(color);
+        }
+      }
+    }
+    buffer
+  }
+
+  /// Renders the provided tile map into a 256x256 RGB image using
+  /// the tile data set selected in LCDC and the BGP palette.
+  ///
+  /// If requested the area currently visible on the LCD, either
+  /// through the background scroll or the window, is outlined.
+  pub fn render_tile_map(&self, map: TileMap, overlay: MapOverlay) -> Vec<u8> {
+    let mut buffer = vec![0u8; MAP_VIEW_SIZE];
+    let shades = PaletteView::shades(self.regs.bgp);
+    for map_y in 0..32 {
+      for map_x in 0..32 {
+        let tile_index = self.vram[map.offset() + map_y * 32 + map_x];
+        let tile = &self.tiles[self.map_tile_index(tile_index)];
+        for y in 0..TILE_HEIGHT {
+          for x in 0..TILE_WIDTH {
+            let shade = shades[tile.get(x, y) as usize];
+            let color = &self.palette_colors[shade as usize];
+            let offset =
+              ((map_y * TILE_HEIGHT + y) * MAP_VIEW_WIDTH + map_x * TILE_WIDTH + x) * RGB_SIZE;
+            buffer[offset..offset + RGB_SIZE].copy_from_slice(color);
+          }
+        }
+      }
+    }
+
+    match overlay {
+      MapOverlay::None => (),
+      MapOverlay::Scroll => self.draw_scroll_overlay(&mut buffer),
+      MapOverlay::Window => self.draw_window_overlay(&mut buffer),
+    }
+
+    buffer
+  }
+
+  /// Tile map currently used by the background (LCDC bit 3).
+  pub fn bg_tile_map(&self) -> TileMap {
+    TileMap::from_flag(self.bg_map())
+  }
+
+  /// Tile map currently used by the window (LCDC bit 6).
+  pub fn window_tile_map(&self) -> TileMap {
+    TileMap::from_flag(self.window_map())
+  }
+
+  /// The 40 objects as decoded from OAM, in OAM order.
+  pub fn objects(&self) -> &[ObjectData; OBJ_COUNT] {
+    &self.obj_data
+  }
+
+  /// Current mapping of the BGP, OBP0 and OBP1 palettes.
+  pub fn palettes(&self) -> PaletteView {
+    PaletteView {
+      bgp: PaletteView::shades(self.regs.bgp),
+      obp0: PaletteView::shades(self.regs.obp0),
+      obp1: PaletteView::shades(self.regs.obp1),
+    }
+  }
+
+  /// Colors used to display each of the four shades.
+  pub fn palette_colors(&self) -> &Palette {
+    &self.palette_colors
+  }
+
+  pub fn layers(&self) -> u8 {
+    self.layers
+  }
+
+  pub fn set_layers(&mut self, value: u8) {
+    self.layers = value & LAYER_ALL;
+  }
+
+  pub fn layer_visible(&self, layer: u8) -> bool {
+    self.layers & layer == layer
+  }
+
+  /// Toggles the visibility of the provided layer (`LAYER_*`),
+  /// returning if the layer is now visible.
+  pub fn toggle_layer(&mut self, layer: u8) -> bool {
+    self.set_layers(self.layers ^ layer);
+    self.layer_visible(layer)
+  }
+
+  pub fn object_hidden(&self, index: usize) -> bool {
+    index < OBJ_COUNT && self.hidden_objects & (1 << index) != 0
+  }
+
+  /// Hides (or shows) an individual OAM entry.
+  pub fn set_object_hidden(&mut self, index: usize, value: bool) {
+    if index >= OBJ_COUNT {
+      return;
+    }
+    if value {
+      self.hidden_objects |= 1 << index;
+    } else {
+      self.hidden_objects &= !(1 << index);
+    }
+  }
+
+  /// Restores the visibility of every layer and object.
+  pub fn show_all(&mut self) {
+    self.layers = LAYER_ALL;
+    self.hidden_objects = 0;
+  }
+
+  /// Converts a tile index read from a tile map into an index of
+  /// the tiles array, taking into account the data set in use.
+  fn map_tile_index(&self, tile_index: u8) -> usize {
+    if self.bg_tile() {
+      tile_index as usize
+    } else {
+      (256 + tile_index as i8 as i16) as usize
+    }
+  }
+
+  fn draw_scroll_overlay(&self, buffer: &mut [u8]) {
+    let scx = self.regs.scx as usize;
+    let scy = self.regs.scy as usize;
+    let mut plot = |x: usize, y: usize| {
+      let x = (scx + x) % MAP_VIEW_WIDTH;
+      let y = (scy + y) % MAP_VIEW_HEIGHT;
+      let offset = (y * MAP_VIEW_WIDTH + x) * RGB_SIZE;
+      buffer[offset..offset + RGB_SIZE].copy_from_slice(&OVERLAY_COLOR);
+    };
+    for x in 0..DISPLAY_WIDTH {
+      plot(x, 0);
+      plot(x, DISPLAY_HEIGHT - 1);
+    }
+    for y in 0..DISPLAY_HEIGHT {
+      plot(0, y);
+      plot(DISPLAY_WIDTH - 1, y);
+    }
+  }
+
+  /// Outlines the top left area of the window map that is visible,
+  /// the window is drawn from WX-7 and WY until the LCD edges.
+  fn draw_window_overlay(&self, buffer: &mut [u8]) {
+    let x0 = self.regs.wx as isize - 7;
+    let y0 = self.regs.wy as isize;
+    if !self.window_enable() || x0 >= DISPLAY_WIDTH as isize || y0 >= DISPLAY_HEIGHT as isize {
+      return;
+    }
+    // with WX below 7 the leftmost columns of the window are cut
+    let left = (-x0).max(0) as usize;
+    let right = DISPLAY_WIDTH - x0.max(0) as usize + left - 1;
+    let bottom = DISPLAY_HEIGHT - y0 as usize - 1;
+    let mut plot = |x: usize, y: usize| {
+      let offset = (y * MAP_VIEW_WIDTH + x) * RGB_SIZE;
+      buffer[offset..offset + RGB_SIZE].copy_from_slice(&OVERLAY_COLOR);
+    };
+    for x in left..=right {
+      plot(x, 0);
+      plot(x, bottom);
+    }
+    for y in 0..=bottom {
+      plot(left, y);
+      plot(right, y);
+    }
+  }
+}
@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+use super::TILE_WIDTH;
+
+/// Number of dots taken by each of the fetcher steps
+/// that access VRAM (tile index, data low and data high).
+pub const FETCH_STEP_DOTS: u8 = 2;
+
+/// Number of dots taken by the object fetcher to retrieve
+/// the tile data of an object.
+pub const OBJ_FETCH_DOTS: u8 = 6;
+
+/// Number of dots at the start of mode 3 spent on the
+/// (discarded) initial background tile fetch.
+pub const TRANSFER_DELAY: u8 = 7;
+
+/// Pixel as stored in one of the pixel FIFOs, the color is
+/// the raw (unmapped) color index, the palette is only applied
+/// when the pixel is shifted out to the LCD.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct FifoPixel {
+  pub color: u8,
+  pub palette: u8,
+  pub bg_over: bool,
+  pub window: bool,
+}
+
+pub type PixelFifo = VecDeque<FifoPixel>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FetcherStep {
+  Tile,
+  DataLow,
+  DataHigh,
+  Push,
+}
+
+/// State of the background/window pixel fetcher, the fetcher
+/// runs in steps of two dots and then waits until the background
+/// FIFO is empty to push the eight pixels of the tile row.
+pub struct Fetcher {
+  pub step: FetcherStep,
+  pub dots: u8,
+  pub tile_x: u8,
+  pub tile_index: u8,
+  pub data_low: u8,
+  pub data_high: u8,
+  pub window: bool,
+}
+
+impl Fetcher {
+  pub fn new() -> Self {
+    Self {
+      step: FetcherStep::Tile,
+      dots: 0,
+      tile_x: 0,
+      tile_index: 0,
+      data_low: 0,
+      data_high: 0,
+      window: false,
+    }
+  }
+
+  /// Restarts the fetcher at the beginning of a scanline
+  /// or when switching to the window.
+  pub fn restart(&mut self, window: bool) {
+    self.step = FetcherStep::Tile;
+    self.dots = 0;
+    self.tile_x = 0;
+    self.window = window;
+  }
+}
+
+impl Default for Fetcher {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Decodes one tile row from its two bitplanes into the color
+/// indexes of its pixels, from the leftmost to the rightmost.
+pub fn decode_row(low: u8, high: u8) -> [u8; TILE_WIDTH] {
+  let mut row = [0u8; TILE_WIDTH];
+  for (x, pixel) in row.iter_mut().enumerate() {
+    let mask = 1 << (TILE_WIDTH - 1 - x);
+    *pixel = u8::from(low & mask > 0) | (u8::from(high & mask > 0) << 1);
+  }
+  row
+}
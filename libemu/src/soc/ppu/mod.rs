@@ -1,14 +1,27 @@
-mod object;
+pub mod debug;
+mod fifo;
+pub mod object;
 pub mod palette;
 mod tile;
 
-use std::{borrow::BorrowMut, cmp::max};
+use std::borrow::BorrowMut;
 
 use log::warn;
 
-use crate::generic::{address::Address, device::Device};
-
-use self::{object::ObjectData, tile::Tile};
+use crate::{
+  generic::{address::Address, device::Device},
+  model::Model,
+};
+
+use self::{
+  debug::{LAYER_ALL, LAYER_BG, LAYER_OBJ, LAYER_WINDOW},
+  fifo::{
+    decode_row, Fetcher, FetcherStep, FifoPixel, PixelFifo, FETCH_STEP_DOTS, OBJ_FETCH_DOTS,
+    TRANSFER_DELAY,
+  },
+  object::ObjectData,
+  tile::Tile,
+};
 
 pub const VRAM_SIZE: usize = 0x2000;
 pub const HRAM_SIZE: usize = 0x80;
@@ -22,6 +35,15 @@ pub const TILE_DOUBLE_HEIGHT: usize = 16;
 pub const TILE_COUNT: usize = 384;
 
 pub const OBJ_COUNT: usize = 40;
+pub const LINE_OBJ_COUNT: usize = 10;
+
+pub const LINE_DOTS: u16 = 456;
+pub const OAM_SCAN_DOTS: u16 = 80;
+pub const FRAME_DOTS: u32 = LINE_DOTS as u32 * 154;
+
+/// Number of dots the first line after enabling the LCD is
+/// shorter than a regular line.
+pub const FIRST_LINE_SKIP: u16 = 4;
 
 pub const DISPLAY_WIDTH: usize = 160;
 pub const DISPLAY_HEIGHT: usize = 144;
@@ -31,6 +53,10 @@ pub const COLOR_BUFFER_SIZE: usize = DISPLAY_SIZE;
 pub const SHADE_BUFFER_SIZE: usize = DISPLAY_SIZE;
 pub const FRAME_BUFFER_SIZE: usize = DISPLAY_SIZE * RGB_SIZE;
 
+/// Size of each of the CGB color palette memories (background
+/// and objects), 8 palettes of 4 colors in RGB555.
+pub const CGB_PALETTE_SIZE: usize = 64;
+
 pub const PALETTE_COLORS: Palette = [[255, 255, 255], [192, 192, 192], [96, 96, 96], [0, 0, 0]];
 
 pub type Pixel = [u8; RGB_SIZE];
@@ -60,8 +86,25 @@ pub struct Ppu {
   tiles: [Tile; TILE_COUNT],
   obj_data: [ObjectData; OBJ_COUNT],
   palette_colors: Palette,
-  palette_obj_0: Palette,
-  palette_obj_1: Palette,
+
+  /// Objects selected by the OAM scan for the current line, with
+  /// their OAM slot and a flag indicating if they've been fetched.
+  line_objects: Vec<(usize, ObjectData, bool)>,
+  /// Object currently being fetched (index in the line objects)
+  /// and the number of dots spent in its fetch.
+  obj_fetch: Option<(usize, u8)>,
+  fetcher: Fetcher,
+  bg_fifo: PixelFifo,
+  obj_fifo: PixelFifo,
+  /// X coordinate of the next pixel to be shifted out to the LCD.
+  pixel_x: u8,
+  /// Number of pixels still to be discarded from the background
+  /// FIFO (SCX fine scroll or window partially off-screen).
+  discard: u8,
+  transfer_delay: u8,
+  /// Set once LY has matched WY during the current frame.
+  wy_triggered: bool,
+  window_line: bool,
 
   window_counter: u8,
   frame_index: u16,
@@ -72,6 +115,49 @@ pub struct Ppu {
   mode: PpuMode,
   int_vblank: bool,
   int_stat: bool,
+
+  /// Level of the combined STAT interrupt line (the OR of all
+  /// the enabled sources) as of the last evaluated dot.
+  stat_line: bool,
+
+  /// Value of the LY=LYC coincidence flag (STAT bit 2).
+  lyc_flag: bool,
+
+  /// Mask of the layers (`LAYER_*`) currently displayed, a debug
+  /// option independent of the LCDC enable bits.
+  layers: u8,
+
+  /// Bit mask of the OAM entries hidden from display.
+  hidden_objects: u64,
+
+  /// If the CPU accesses to VRAM and OAM should be restricted
+  /// according to the current PPU mode (as in the hardware),
+  /// disabling it may help debugging homebrew software.
+  access_restrictions: bool,
+
+  /// Set for the first line after the LCD is enabled, during
+  /// which no OAM scan takes place and STAT reports mode 0.
+  first_line: bool,
+
+  /// Set for the first frame after the LCD is enabled, which
+  /// is not displayed by the LCD (remains blank).
+  first_frame: bool,
+
+  /// Number of dots elapsed since the last (blank) frame
+  /// event while the LCD is off.
+  off_dots: u32,
+
+  /// Model the PPU is part of, controlling its quirks and
+  /// the availability of the CGB registers.
+  model: Model,
+
+  /// CGB color palette memories and their index registers
+  /// (BCPS and OCPS), only readable and writable by software
+  /// as the rendering uses the DMG palettes.
+  bg_palette_ram: [u8; CGB_PALETTE_SIZE],
+  obj_palette_ram: [u8; CGB_PALETTE_SIZE],
+  bcps: u8,
+  ocps: u8,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -92,8 +178,16 @@ impl Ppu {
       tiles: [Tile { buffer: [0u8; 64] }; TILE_COUNT],
       obj_data: [ObjectData::default(); OBJ_COUNT],
       palette_colors: PALETTE_COLORS,
-      palette_obj_0: [[0u8; RGB_SIZE]; PALETTE_SIZE],
-      palette_obj_1: [[0u8; RGB_SIZE]; PALETTE_SIZE],
+      line_objects: Vec::with_capacity(LINE_OBJ_COUNT),
+      obj_fetch: None,
+      fetcher: Fetcher::new(),
+      bg_fifo: PixelFifo::with_capacity(16),
+      obj_fifo: PixelFifo::with_capacity(16),
+      pixel_x: 0,
+      discard: 0,
+      transfer_delay: 0,
+      wy_triggered: false,
+      window_line: false,
       regs: PpuRegisters {
         lcdc: 0x0,
         stat: 0x0,
@@ -114,6 +208,19 @@ impl Ppu {
       frame_buffer_index: std::u16::MAX,
       int_vblank: false,
       int_stat: false,
+      stat_line: false,
+      lyc_flag: false,
+      layers: LAYER_ALL,
+      hidden_objects: 0,
+      access_restrictions: true,
+      first_line: false,
+      first_frame: false,
+      off_dots: 0,
+      model: Model::default(),
+      bg_palette_ram: [0xffu8; CGB_PALETTE_SIZE],
+      obj_palette_ram: [0xffu8; CGB_PALETTE_SIZE],
+      bcps: 0x0,
+      ocps: 0x0,
     }
   }
 
@@ -123,8 +230,16 @@ impl Ppu {
     self.vram = [0u8; VRAM_SIZE];
     self.tiles = [Tile { buffer: [0u8; 64] }; TILE_COUNT];
     self.obj_data = [ObjectData::default(); OBJ_COUNT];
-    self.palette_obj_0 = [[0u8; RGB_SIZE]; PALETTE_SIZE];
-    self.palette_obj_1 = [[0u8; RGB_SIZE]; PALETTE_SIZE];
+    self.line_objects.clear();
+    self.obj_fetch = None;
+    self.fetcher = Fetcher::new();
+    self.bg_fifo.clear();
+    self.obj_fifo.clear();
+    self.pixel_x = 0;
+    self.discard = 0;
+    self.transfer_delay = 0;
+    self.wy_triggered = false;
+    self.window_line = false;
     self.regs.lcdc = 0x0;
     self.regs.bgp = 0x0;
     self.regs.obp0 = 0x0;
@@ -140,6 +255,15 @@ impl Ppu {
     self.frame_buffer_index = std::u16::MAX;
     self.int_vblank = false;
     self.int_stat = false;
+    self.stat_line = false;
+    self.lyc_flag = false;
+    self.first_line = false;
+    self.first_frame = false;
+    self.off_dots = 0;
+    self.bg_palette_ram = [0xffu8; CGB_PALETTE_SIZE];
+    self.obj_palette_ram = [0xffu8; CGB_PALETTE_SIZE];
+    self.bcps = 0x0;
+    self.ocps = 0x0;
   }
 
   fn lcd_enable(&self) -> bool {
@@ -174,6 +298,20 @@ impl Ppu {
     self.regs.lcdc & 0x01 == 0x01
   }
 
+  /// VRAM is not accessible by the CPU while the PPU is
+  /// transferring pixels to the LCD (mode 3).
+  fn vram_blocked(&self) -> bool {
+    self.access_restrictions && self.lcd_enable() && self.mode == PpuMode::VramRead
+  }
+
+  /// OAM is not accessible by the CPU while the PPU is scanning
+  /// it (mode 2) or transferring pixels to the LCD (mode 3).
+  fn oam_blocked(&self) -> bool {
+    self.access_restrictions
+      && self.lcd_enable()
+      && (self.mode == PpuMode::OamRead && !self.first_line || self.mode == PpuMode::VramRead)
+  }
+
   fn stat_lyc(&self) -> bool {
     self.regs.stat & 0x40 == 0x40
   }
@@ -191,41 +329,61 @@ impl Ppu {
   }
 
   pub fn clock(&mut self, cycles: u16) {
-    // in case the LCD is currently off then we skip the current
-    // clock operation the PPU should not work
+    // in case the LCD is currently off the PPU does not work, still
+    // blank frames are signaled at the usual rate so that frontends
+    // present a blank screen instead of freezing the last frame
     if !self.lcd_enable() {
+      self.off_dots += cycles as u32;
+      while self.off_dots >= FRAME_DOTS {
+        self.off_dots -= FRAME_DOTS;
+        self.frame_index = self.frame_index.wrapping_add(1);
+      }
       return;
     }
 
-    // 模拟并行？
-    self.dot += cycles;
+    // the PPU is advanced one dot at a time so that register
+    // changes made by the CPU in between are seen at the same
+    // point of the scanline where they'd be seen by the hardware
+    for _ in 0..cycles {
+      self.tick();
+    }
+  }
 
+  fn tick(&mut self) {
     match self.mode {
       PpuMode::OamRead => {
-        if self.dot >= 80 {
+        if self.dot == 0 {
+          self.start_line();
+        }
+
+        self.dot += 1;
+
+        if self.dot == OAM_SCAN_DOTS {
+          self.first_line = false;
+          self.scan_objects();
+          self.start_transfer();
           self.mode = PpuMode::VramRead;
-          self.dot -= 80;
         }
       },
       PpuMode::VramRead => {
-        if self.dot >= 172 {
-          self.render_line();
+        self.dot += 1;
+        self.transfer();
+
+        // the length of mode 3 is variable, it ends as soon
+        // as the last pixel of the line is shifted out
+        if self.pixel_x as usize == DISPLAY_WIDTH {
+          if self.window_line {
+            self.window_counter += 1;
+          }
 
           self.mode = PpuMode::HBlank;
-          self.dot -= 172;
-          self.update_stat()
         }
       },
       PpuMode::HBlank => {
-        if self.dot >= 204 {
-          if self.window_enable()
-            && self.regs.wx as i16 - 7 < DISPLAY_WIDTH as i16
-            && self.regs.wy < DISPLAY_HEIGHT as u8
-            && self.regs.ly >= self.regs.wy
-          {
-            self.window_counter += 1;
-          }
+        self.dot += 1;
 
+        if self.dot == LINE_DOTS {
+          self.dot = 0;
           self.regs.ly += 1;
 
           if self.regs.ly == 144 {
@@ -234,27 +392,30 @@ impl Ppu {
           } else {
             self.mode = PpuMode::OamRead;
           }
-
-          self.dot -= 204;
-          self.update_stat()
         }
       },
       PpuMode::VBlank => {
-        if self.dot >= 456 {
+        self.dot += 1;
+
+        if self.dot == LINE_DOTS {
+          self.dot = 0;
           self.regs.ly += 1;
 
           if self.regs.ly == 154 {
             self.mode = PpuMode::OamRead;
             self.regs.ly = 0;
             self.window_counter = 0;
+            self.wy_triggered = false;
+            self.first_frame = false;
             self.frame_index = self.frame_index.wrapping_add(1);
-            self.update_stat()
           }
-
-          self.dot -= 456;
         }
       },
     }
+
+    // the STAT sources (and the LY=LYC comparison) are evaluated
+    // on every single dot and not only on mode transitions
+    self.update_stat();
   }
 
   pub fn frame_buffer(&mut self) -> &mut [u8; FRAME_BUFFER_SIZE] {
@@ -274,14 +435,120 @@ impl Ppu {
     &mut self.frame_buffer
   }
 
+  /// RGB frame as of the last call to `frame_buffer()`.
+  pub fn rgb_buffer(&self) -> &[u8; FRAME_BUFFER_SIZE] {
+    &self.frame_buffer
+  }
+
+  /// Shades (0-3) of the pixels of the last frame, after
+  /// the palette registers have been applied.
+  pub fn shade_buffer(&self) -> &[u8; SHADE_BUFFER_SIZE] {
+    &self.shade_buffer
+  }
+
+  /// Data of the first 256 tiles displayed by the background
+  /// (20 per row, from the top left of the tile map), which is
+  /// what the SGB captures on its VRAM transfers.
+  pub fn screen_tile_data(&self) -> Vec<u8> {
+    let map = if self.bg_map() { 0x1c00 } else { 0x1800 };
+    let mut data = Vec::with_capacity(256 * 16);
+    for index in 0..256 {
+      let tile_index = self.vram[map + (index / 20) * 32 + index % 20];
+      let offset = if self.bg_tile() {
+        tile_index as usize * 16
+      } else {
+        (256 + tile_index as i8 as i16) as usize * 16
+      };
+      data.extend_from_slice(&self.vram[offset..offset + 16]);
+    }
+    data
+  }
+
   pub fn set_palette_colors(&mut self, value: &Palette) {
     self.palette_colors = *value;
   }
 
+  pub fn mode(&self) -> PpuMode {
+    self.mode
+  }
+
   pub fn frame_index(&self) -> u16 {
     self.frame_index
   }
 
+  pub fn model(&self) -> Model {
+    self.model
+  }
+
+  pub fn set_model(&mut self, value: Model) {
+    self.model = value;
+  }
+
+  pub fn bg_palette_ram(&self) -> &[u8; CGB_PALETTE_SIZE] {
+    &self.bg_palette_ram
+  }
+
+  pub fn obj_palette_ram(&self) -> &[u8; CGB_PALETTE_SIZE] {
+    &self.obj_palette_ram
+  }
+
+  pub fn access_restrictions(&self) -> bool {
+    self.access_restrictions
+  }
+
+  pub fn set_access_restrictions(&mut self, value: bool) {
+    self.access_restrictions = value;
+  }
+
+  /// Writes a byte directly into OAM, bypassing the PPU mode
+  /// restrictions, this is the path used by the OAM DMA.
+  pub fn write_oam(&mut self, addr: u16, value: u8) {
+    self.oam[(addr & 0x00ff) as usize] = value;
+    self.update_object(addr, value);
+  }
+
+  /// Emulates the DMG OAM corruption bug, triggered when the CPU
+  /// increments or decrements a 16-bit register holding an address
+  /// in the 0xFE00-0xFEFF range while the PPU is scanning OAM.
+  ///
+  /// The row (8 bytes) being read by the PPU is corrupted by
+  /// mixing it with the preceding row, the first row is never
+  /// affected by the bug.
+  pub fn oam_bug(&mut self, addr: u16) {
+    if !self.model.has_dmg_quirks()
+      || !(0xfe00..=0xfeff).contains(&addr)
+      || !self.access_restrictions
+      || !self.lcd_enable()
+      || self.mode != PpuMode::OamRead
+    {
+      return;
+    }
+
+    let row = (self.dot / 4) as usize;
+    if row == 0 || row >= OBJ_COUNT / 2 {
+      return;
+    }
+
+    let word = |oam: &[u8; OAM_SIZE], index: usize| -> u16 {
+      oam[index] as u16 | (oam[index + 1] as u16) << 8
+    };
+    let base = row * 8;
+    let prev = base - 8;
+    let a = word(&self.oam, base);
+    let b = word(&self.oam, prev);
+    let c = word(&self.oam, prev + 4);
+    let first = ((a ^ c) & (b ^ c)) ^ c;
+
+    let mut data = [0u8; 8];
+    data.copy_from_slice(&self.oam[prev..base]);
+    data[0] = first as u8;
+    data[1] = (first >> 8) as u8;
+
+    for (index, value) in data.iter().enumerate() {
+      self.write_oam(0xfe00 + (base + index) as u16, *value);
+    }
+  }
+
   #[inline(always)]
   pub fn int_vblank(&self) -> bool {
     self.int_vblank
@@ -354,301 +621,432 @@ impl Ppu {
         obj.xflip = value & 0x20 == 0x20;
         obj.yflip = value & 0x40 == 0x40;
         obj.bg_over = value & 0x80 == 0x80;
-        obj.index = obj_index as u8;
       },
       _ => (),
     }
   }
 
-  fn render_line(&mut self) {
-    if self.bg_enable() {
-      self.render_map(
-        self.bg_map(),
-        self.regs.scx,
-        self.regs.scy,
-        0,
-        0,
-        self.regs.ly,
-      );
+  /// Prepares a new visible line, the WY condition is checked
+  /// at the start of every line and once met the window may be
+  /// rendered until the end of the frame.
+  fn start_line(&mut self) {
+    if self.regs.ly == self.regs.wy {
+      self.wy_triggered = true;
     }
-    if self.bg_enable() && self.window_enable() {
-      self.render_map(
-        self.window_map(),
-        0,
-        0,
-        self.regs.wx,
-        self.regs.wy,
-        self.window_counter,
-      );
-    }
-    if self.obj_enable() {
-      self.render_objects();
+  }
+
+  /// Selects (in OAM order) the first objects that overlap
+  /// the current line, up to the hardware limit of 10.
+  fn scan_objects(&mut self) {
+    let obj_height = if self.obj_size() {
+      TILE_DOUBLE_HEIGHT
+    } else {
+      TILE_HEIGHT
+    } as i16;
+    let ly = self.regs.ly as i16;
+
+    self.line_objects.clear();
+    for (slot, obj) in self.obj_data.iter().enumerate() {
+      if self.line_objects.len() == LINE_OBJ_COUNT {
+        break;
+      }
+      if obj.y <= ly && obj.y + obj_height > ly {
+        self.line_objects.push((slot, *obj, false));
+      }
     }
   }
 
-  fn render_map(&mut self, map: bool, scx: u8, scy: u8, wx: u8, wy: u8, ld: u8) {
-    if self.regs.ly < wy {
+  fn start_transfer(&mut self) {
+    self.fetcher.restart(false);
+    self.bg_fifo.clear();
+    self.obj_fifo.clear();
+    self.obj_fetch = None;
+    self.pixel_x = 0;
+    self.discard = self.regs.scx & 0x07;
+    self.transfer_delay = TRANSFER_DELAY;
+    self.window_line = false;
+  }
+
+  /// Runs one dot of the pixel transfer (mode 3), the length
+  /// of the mode depends on the number of pixels discarded for
+  /// fine scrolling, the window restarting the fetcher and the
+  /// objects stalling the pixel output while being fetched.
+  fn transfer(&mut self) {
+    if self.transfer_delay > 0 {
+      self.transfer_delay -= 1;
       return;
     }
 
-    let map_offset: usize = if map { 0x1c00 } else { 0x1800 };
+    // the background fetcher keeps running even when the pixel
+    // output is stalled by an object fetch
+    self.step_fetcher();
 
-    // calculates the map row index for the tile by using the current line
-    // index and the DY (scroll Y) divided by 8 (as the tiles are 8x8 pixels),
-    // on top of that ensures that the result is modulus 32 meaning that the
-    // drawing wraps around the Y axis
-    let row_index = (((ld as usize + scy as usize) & 0xff) >> 3) % 32;
-
-    // calculates the map offset by the row offset multiplied by the number
-    // of tiles in each row (32)
-    let row_offset = row_index * 32;
-
-    // calculates the sprite line offset by using the SCX register
-    // shifted by 3 meaning that the tiles are 8x8
-    let mut line_offset = (scx >> 3) as usize;
-
-    // calculates the index of the initial tile in drawing,
-    // if the tile data set in use is #1, the indexes are
-    // signed, then calculates a real tile offset
-    let mut tile_index = self.vram[map_offset + row_offset + line_offset] as usize;
-    if !self.bg_tile() && tile_index < 128 {
-      tile_index += 256;
-    }
-
-    // obtains the reference to the tile that is going to be drawn
-    let mut tile = &self.tiles[tile_index];
-
-    // calculates the offset that is going to be used in the update of the color buffer
-    // which stores Game Boy colors from 0 to 3
-    let mut color_offset = self.regs.ly as usize * DISPLAY_WIDTH;
-
-    // obtains the current integer value (raw) for the background palette
-    // this is going to be used for shade index value computation (DMG only)
-    let palette_v = self.regs.bgp;
-
-    // calculates both the current Y and X positions within the tiles
-    // using the bitwise and operation as an effective modulus 8
-    let y = (ld as usize + scy as usize) & 0x07;
-    let mut x = (scx & 0x07) as usize;
-
-    // calculates the initial tile X position in drawing, doing this
-    // allows us to position the background map properly in the display
-    let initial_index = max(wx as i16 - 7, 0) as usize;
-    color_offset += initial_index;
-
-    // iterates over all the pixels in the current line of the display
-    // to draw the background map, note that the initial index is used
-    // to skip the drawing of the tiles that are not visible (WX)
-    for _ in initial_index..DISPLAY_WIDTH {
-      // obtains the current pixel data from the tile
-      let pixel = tile.get(x, y);
-
-      // updates the pixel in the color buffer, which stores
-      // the raw pixel color information (unmapped) and then
-      // updates the shade buffer with the shade index
-      self.shade_buffer[color_offset] = (palette_v >> (pixel * 2)) & 3;
-
-      // increments the current tile X position in drawing
-      x += 1;
-
-      // in case the end of tile width has been reached then
-      // a new tile must be retrieved for rendering
-      if x == TILE_WIDTH {
-        // resets the tile X position to the base value
-        // as a new tile is going to be drawn
-        x = 0;
-
-        // calculates the new line tile offset making sure that
-        // the maximum of 32 is not overflown
-        line_offset = (line_offset + 1) % 32;
-
-        // calculates the tile index and makes sure the value
-        // takes into consideration the bg tile value
-        tile_index = self.vram[map_offset + row_offset + line_offset] as usize;
-        if !self.bg_tile() && tile_index < 128 {
-          tile_index += 256;
-        }
+    if self.bg_fifo.is_empty() {
+      return;
+    }
+
+    // switches the fetcher to the window once the pixel at WX - 7
+    // is reached, the pixels already in the FIFO are dropped and
+    // the fetch restarts from the first tile of the window line
+    if !self.fetcher.window
+      && self.window_enable()
+      && self.wy_triggered
+      && self.pixel_x as u16 + 7 >= self.regs.wx as u16
+    {
+      self.fetcher.restart(true);
+      self.bg_fifo.clear();
+      self.discard = 7u8.saturating_sub(self.regs.wx);
+      self.window_line = true;
+      return;
+    }
+
+    if self.obj_fetch.is_none() && self.obj_enable() && self.discard == 0 {
+      self.obj_fetch = self
+        .next_object()
+        .map(|index| (index, OBJ_FETCH_DOTS + self.fetch_wait()));
+    }
 
-        // obtains the reference to the new tile in drawing
-        tile = &self.tiles[tile_index];
+    if let Some((index, dots)) = self.obj_fetch {
+      if dots > 1 {
+        self.obj_fetch = Some((index, dots - 1));
+        return;
       }
 
-      // increments the color offset by one, representing
-      // the drawing of one pixel
-      color_offset += 1;
+      self.fetch_object(index);
+      self.obj_fetch = None;
+      return;
     }
+
+    self.shift_pixel();
   }
 
-  fn render_objects(&mut self) {
-    let mut draw_count = 0u8;
+  /// Number of dots an object fetch has to wait for the background
+  /// fetcher to be done with the tile it's currently fetching, which
+  /// depends on how far the fetch is (pixels already shifted out).
+  fn fetch_wait(&self) -> u8 {
+    if self.fetcher.step == FetcherStep::Push {
+      return 0;
+    }
+    let shifted = TILE_WIDTH as u8 - self.bg_fifo.len().min(TILE_WIDTH) as u8;
+    5u8.saturating_sub(shifted)
+  }
 
-    // allocates the buffer that is going to be used to determine
-    // drawing priority for overlapping pixels between different
-    // objects, in MBR mode the object that has the smallest X
-    // coordinate takes priority in drawing the pixel
-    let mut index_buffer = [-256i16; DISPLAY_WIDTH];
+  /// Obtains the line object that should be fetched for the current
+  /// pixel, in case there are several the one with the smallest X
+  /// (and then the first in OAM) takes precedence.
+  fn next_object(&self) -> Option<usize> {
+    self
+      .line_objects
+      .iter()
+      .enumerate()
+      .filter(|(_, (_, obj, fetched))| !fetched && obj.x <= self.pixel_x as i16)
+      .min_by_key(|(_, (_, obj, _))| obj.x)
+      .map(|(index, _)| index)
+  }
 
-    // iterates over the complete set of available object to check
-    // the ones that require drawing and draws them
-    for index in 0..OBJ_COUNT {
-      if draw_count == 10 {
-        break;
+  fn step_fetcher(&mut self) {
+    if self.fetcher.step != FetcherStep::Push {
+      self.fetcher.dots += 1;
+      if self.fetcher.dots < FETCH_STEP_DOTS {
+        return;
       }
+      self.fetcher.dots = 0;
+    }
 
-      let obj = &self.obj_data[index];
+    match self.fetcher.step {
+      FetcherStep::Tile => {
+        self.fetcher.tile_index = self.vram[self.fetch_map_addr()];
+        self.fetcher.step = FetcherStep::DataLow;
+      },
+      FetcherStep::DataLow => {
+        self.fetcher.data_low = self.vram[self.fetch_tile_addr()];
+        self.fetcher.step = FetcherStep::DataHigh;
+      },
+      FetcherStep::DataHigh => {
+        self.fetcher.data_high = self.vram[self.fetch_tile_addr() + 1];
+        self.fetcher.step = FetcherStep::Push;
+        self.push_fetcher();
+      },
+      FetcherStep::Push => self.push_fetcher(),
+    }
+  }
 
-      let obj_height = if self.obj_size() {
-        TILE_DOUBLE_HEIGHT
-      } else {
-        TILE_HEIGHT
-      };
+  fn push_fetcher(&mut self) {
+    // the pixels are only pushed once the background
+    // FIFO is empty, otherwise the fetcher keeps waiting
+    if !self.bg_fifo.is_empty() {
+      return;
+    }
+    for color in decode_row(self.fetcher.data_low, self.fetcher.data_high) {
+      self.bg_fifo.push_back(FifoPixel {
+        color,
+        window: self.fetcher.window,
+        ..Default::default()
+      });
+    }
+    self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+    self.fetcher.step = FetcherStep::Tile;
+  }
 
-      // verifies if the sprite is currently located at the
-      // current line that is going to be drawn and skips it
-      // in case it's not
-      let is_contained =
-        (obj.y <= self.regs.ly as i16) && ((obj.y + obj_height as i16) > self.regs.ly as i16);
-      if !is_contained {
-        continue;
-      }
+  /// Computes the VRAM offset of the map entry for the tile being
+  /// fetched, the scroll registers are read at fetch time so that
+  /// changes in the middle of the line are visible.
+  fn fetch_map_addr(&self) -> usize {
+    let (map, x, y) = if self.fetcher.window {
+      (
+        self.window_map(),
+        self.fetcher.tile_x as usize,
+        self.window_counter as usize,
+      )
+    } else {
+      (
+        self.bg_map(),
+        (self.regs.scx >> 3) as usize + self.fetcher.tile_x as usize,
+        (self.regs.ly as usize + self.regs.scy as usize) & 0xff,
+      )
+    };
+    let map_offset: usize = if map { 0x1c00 } else { 0x1800 };
+    map_offset + (y >> 3) * 32 + (x & 0x1f)
+  }
 
-      let (palette, palette_index) = if obj.palette == 0 {
-        (&self.palette_obj_0, 1_u8)
-      } else if obj.palette == 1 {
-        (&self.palette_obj_1, 2_u8)
-      } else {
-        panic!("Invalid object palette: {:02x}", obj.palette);
-      };
+  /// Computes the VRAM offset of the low byte of the row of the
+  /// tile being fetched, if the tile data set in use is #1 the
+  /// tile indexes are signed and relative to 0x9000.
+  fn fetch_tile_addr(&self) -> usize {
+    let y = if self.fetcher.window {
+      self.window_counter as usize
+    } else {
+      self.regs.ly as usize + self.regs.scy as usize
+    } & 0x07;
+    let tile_index = self.fetcher.tile_index;
+    let tile_offset = if self.bg_tile() {
+      tile_index as usize * 16
+    } else {
+      (0x1000 + tile_index as i8 as i32 * 16) as usize
+    };
+    tile_offset + y * 2
+  }
 
-      // obtains the current integer value (raw) for the palette in use
-      // this is going to be used for shade index value computation (DMG only)
-      // let palette_v = self.palettes[palette_index as usize];
-      //
-      let palette_v = if palette_index == 0 {
-        self.regs.bgp
-      } else if palette_index == 1 {
-        self.regs.obp0
-      } else {
-        self.regs.obp1
-      };
+  /// Fetches the row of the object for the current line and mixes
+  /// it into the object FIFO, pixels already in the FIFO belong to
+  /// objects with higher priority so only transparent ones are replaced.
+  fn fetch_object(&mut self, index: usize) {
+    let (slot, obj, _) = self.line_objects[index];
+    self.line_objects[index].2 = true;
+
+    let obj_height = if self.obj_size() {
+      TILE_DOUBLE_HEIGHT
+    } else {
+      TILE_HEIGHT
+    } as i16;
+
+    // the relative row should range from 0 to 7 in 8x8 objects
+    // and from 0 to 15 in 8x16 objects, inverted when flipping
+    let mut row = (self.regs.ly as i16 - obj.y) & (obj_height - 1);
+    if obj.yflip {
+      row = obj_height - row - 1;
+    }
 
-      // calculates the offset in the color buffer (raw color information
-      // from 0 to 3) for the sprit that is going to be drawn, this value
-      // is kept as a signed integer to allow proper negative number math
-      let mut color_offset = self.regs.ly as i32 * DISPLAY_WIDTH as i32 + obj.x as i32;
-
-      // calculates the offset in the frame buffer for the sprite
-      // that is going to be drawn, this is going to be the starting
-      // point for the draw operation to be performed
-      let mut frame_offset =
-        (self.regs.ly as i32 * DISPLAY_WIDTH as i32 + obj.x as i32) * RGB_SIZE as i32;
-
-      // the relative title offset should range from 0 to 7 in 8x8
-      // objects and from 0 to 15 in 8x16 objects
-      let mut tile_offset = self.regs.ly as i16 - obj.y;
-
-      // in case we're flipping the object we must recompute the
-      // tile offset as an inverted value using the object's height
-      if obj.yflip {
-        tile_offset = obj_height as i16 - tile_offset - 1;
+    let tile_index = if self.obj_size() {
+      if row < 8 {
+        obj.tile as usize & 0xfe
+      } else {
+        row -= 8;
+        obj.tile as usize | 0x01
       }
+    } else {
+      obj.tile as usize
+    };
+
+    let addr = tile_index * 16 + row as usize * 2;
+    let mut pixels = decode_row(self.vram[addr], self.vram[addr + 1]);
+    if obj.xflip {
+      pixels.reverse();
+    }
 
-      let tile: &Tile;
+    // hidden objects are still fetched (keeping the timing intact)
+    // but are made transparent, revealing the ones below them
+    if self.object_hidden(slot) {
+      pixels = [0u8; TILE_WIDTH];
+    }
+
+    // objects partially off-screen (or fetched late) have their
+    // leftmost pixels skipped so the FIFO stays aligned with the LCD
+    let skip = (self.pixel_x as i16 - obj.x).clamp(0, TILE_WIDTH as i16) as usize;
+    for (index, color) in pixels.into_iter().skip(skip).enumerate() {
+      let pixel = FifoPixel {
+        color,
+        palette: obj.palette,
+        bg_over: obj.bg_over,
+        window: false,
+      };
+      match self.obj_fifo.get_mut(index) {
+        Some(current) => {
+          if current.color == 0 {
+            *current = pixel;
+          }
+        },
+        None => self.obj_fifo.push_back(pixel),
+      }
+    }
+  }
 
-      // "calculates" the index offset that is going to be applied
-      // to the tile index to retrieve the proper tile taking into
-      // consideration the VRAM in which the tile is stored
-      let tile_bank_offset = { obj.tile_bank as usize * TILE_COUNT };
+  /// Shifts one pixel out of the FIFOs into the LCD, mixing the
+  /// background and object pixels and applying the palettes, as the
+  /// palettes are read here their changes take effect mid-line.
+  fn shift_pixel(&mut self) {
+    let bg = match self.bg_fifo.pop_front() {
+      Some(pixel) => pixel,
+      None => return,
+    };
+
+    if self.discard > 0 {
+      self.discard -= 1;
+      return;
+    }
 
-      if self.obj_size() {
-        // 8x16 object
-        if tile_offset < 8 {
-          let tile_index = (obj.tile as usize & 0xfe) + tile_bank_offset;
-          tile = &self.tiles[tile_index];
+    let obj = self.obj_fifo.pop_front();
+
+    // in the DMG clearing LCDC.0 blanks both the background
+    // and the window, leaving only objects visible
+    let bg_layer = if bg.window { LAYER_WINDOW } else { LAYER_BG };
+    let bg_color = if self.bg_enable() && self.layer_visible(bg_layer) {
+      bg.color
+    } else {
+      0
+    };
+    let mut shade = (self.regs.bgp >> (bg_color * 2)) & 3;
+
+    if let Some(obj) = obj {
+      if obj.color != 0
+        && self.obj_enable()
+        && self.layer_visible(LAYER_OBJ)
+        && !(obj.bg_over && bg_color != 0)
+      {
+        let palette_v = if obj.palette == 0 {
+          self.regs.obp0
         } else {
-          let tile_index = (obj.tile as usize | 0x01) + tile_bank_offset;
-          tile = &self.tiles[tile_index];
-          tile_offset -= 8;
-        }
-      } else {
-        // 8x8 object
-        let tile_index = obj.tile as usize + tile_bank_offset;
-        tile = &self.tiles[tile_index];
+          self.regs.obp1
+        };
+        shade = (palette_v >> (obj.color * 2)) & 3;
       }
+    }
 
-      let tile_row = tile.get_row(tile_offset as usize);
-
-      // determines if the object should always be placed over the
-      // previously placed background or window pixels
-      let obj_over = !obj.bg_over;
-
-      for tile_x in 0..TILE_WIDTH {
-        let x = obj.x + tile_x as i16;
-        let is_contained = (x >= 0) && (x < DISPLAY_WIDTH as i16);
-        if is_contained {
-          // the object is only considered visible if no background or
-          // window should be drawn over or if the underlying pixel
-          // is transparent (zero value) meaning there's no background
-          // or window for the provided pixel
-          let mut is_visible = obj_over;
-
-          // additionally (in CCG mode) the object is only considered to
-          // be visible if the priority buffer is not set for the current
-          // pixel, this means that the background is capturing priority
-          // by having the BG-to-OAM priority bit set in the bg map attributes
-          is_visible &= true;
-
-          let pixel = tile_row[if obj.xflip {
-            // TODO: 为什么-1
-            TILE_WIDTH - 1 - tile_x
-          } else {
-            tile_x
-          }];
-          if is_visible && pixel != 0 {
-            // marks the current pixel in iteration as "owned"
-            // by the object with the defined X base position,
-            // to be used in priority calculus
-            index_buffer[x as usize] = obj.x;
-
-            // updates the pixel in the color buffer, which stores
-            // the raw pixel color information (unmapped) and then
-            // updates the shade buffer with the shade index
-            self.shade_buffer[color_offset as usize] = (palette_v >> (pixel * 2)) & 3;
-
-            // re-maps the pixel according to the object palette
-            // and then sets the color pixel in the frame buffer
-            let color = &palette[pixel as usize];
-            self.frame_buffer[frame_offset as usize] = color[0];
-            self.frame_buffer[frame_offset as usize + 1] = color[1];
-            self.frame_buffer[frame_offset as usize + 2] = color[2];
-          }
-        }
+    // the first frame after enabling the LCD is not displayed
+    if !self.first_frame {
+      self.shade_buffer[self.regs.ly as usize * DISPLAY_WIDTH + self.pixel_x as usize] = shade;
+    }
+    self.pixel_x += 1;
+  }
 
-        // increment the color offset by one as this represents
-        // the advance of one color pixel
-        color_offset += 1;
+  /// Turns the LCD off, LY is reset to 0, STAT reports mode 0
+  /// and the screen is cleared to the off (lightest) color, with
+  /// a frame event being signaled right away.
+  fn disable_lcd(&mut self) {
+    self.mode = PpuMode::HBlank;
+    self.dot = 0;
+    self.regs.ly = 0;
+    self.int_vblank = false;
+    self.int_stat = false;
+    self.stat_line = false;
+    self.window_counter = 0;
+    self.wy_triggered = false;
+    self.first_line = false;
+    self.first_frame = false;
+    self.off_dots = 0;
+    self.shade_buffer.fill(0);
+    self.frame_index = self.frame_index.wrapping_add(1);
+  }
 
-        // increments the offset of the frame buffer by the
-        // size of an RGB pixel (which is 3 bytes)
-        frame_offset += RGB_SIZE as i32;
-      }
+  /// Turns the LCD on, the first line starts without an OAM scan
+  /// and is slightly shorter than the remaining ones, while the
+  /// first frame rendered is not displayed.
+  fn enable_lcd(&mut self) {
+    self.mode = PpuMode::OamRead;
+    self.dot = FIRST_LINE_SKIP;
+    self.regs.ly = 0;
+    self.window_counter = 0;
+    self.wy_triggered = false;
+    self.first_line = true;
+    self.first_frame = true;
+    self.start_line();
+    self.update_stat();
+  }
 
-      // increments the counter so that we're able to keep
-      // track on the number of object drawn
-      draw_count += 1;
+  /// Value of LY as seen by the CPU, on line 153 the register
+  /// is reset to 0 after the first 4 dots of the line.
+  fn ly(&self) -> u8 {
+    if self.regs.ly == 153 && self.dot >= 4 {
+      0
+    } else {
+      self.regs.ly
     }
   }
 
+  /// Value of LY used by the LY=LYC comparator, the comparator
+  /// lags 4 dots behind a change of LY, during which no match
+  /// is reported, meaning that on line 153 LYC=153 only matches
+  /// for 4 dots and LYC=0 matches from dot 8 until the end of line 0.
+  fn ly_compare(&self) -> Option<u8> {
+    match (self.regs.ly, self.dot) {
+      (0, _) => Some(0),
+      (_, 0..=3) => None,
+      (153, 4..=7) => Some(153),
+      (153, _) => Some(0),
+      (ly, _) => Some(ly),
+    }
+  }
+
+  /// Updates the LY=LYC flag and the combined STAT interrupt
+  /// line, the interrupt is only requested on a rising edge of
+  /// the line, so while any of the sources keeps it high every
+  /// other source is blocked from requesting a new interrupt.
   fn update_stat(&mut self) {
-    self.int_stat = self.stat_level();
+    self.lyc_flag = self.ly_compare() == Some(self.regs.lyc);
+    let level = self.stat_level();
+    if level && !self.stat_line {
+      self.int_stat = true;
+    }
+    self.stat_line = level;
   }
 
   fn stat_level(&self) -> bool {
-    self.stat_lyc() && self.regs.lyc == self.regs.ly
-      || self.stat_oam() && self.mode == PpuMode::OamRead
+    self.stat_lyc() && self.lyc_flag
+      || self.stat_oam() && self.oam_condition()
       || self.stat_vblank() && self.mode == PpuMode::VBlank
       || self.stat_hblank() && self.mode == PpuMode::HBlank
   }
+
+  /// The mode 2 source is also raised at the start of line 144,
+  /// when the PPU enters VBlank instead of scanning OAM.
+  fn oam_condition(&self) -> bool {
+    self.mode == PpuMode::OamRead && !self.first_line || self.regs.ly == 144 && self.dot < 4
+  }
+
+  /// Emulates the DMG STAT write bug, for one cycle the write
+  /// behaves as if every source was enabled, so an interrupt is
+  /// requested if the line was low and the PPU is in HBlank,
+  /// VBlank or LY matches LYC.
+  fn stat_write_bug(&mut self) {
+    if !self.model.has_dmg_quirks() || !self.lcd_enable() || self.stat_line {
+      return;
+    }
+    if self.mode == PpuMode::HBlank || self.mode == PpuMode::VBlank || self.lyc_flag {
+      self.int_stat = true;
+    }
+  }
+
+  fn read_stat(&self) -> u8 {
+    // bit 7 is unused and always reads as set, the mode is
+    // reported as 0 (HBlank) while the LCD is off and during the
+    // OAM scan period of the first line after enabling it
+    let mode = if !self.lcd_enable() || self.first_line && self.mode == PpuMode::OamRead {
+      0
+    } else {
+      self.mode as u8
+    };
+    0x80 | (self.regs.stat & 0x78) | (u8::from(self.lyc_flag) << 2) | mode
+  }
 }
 
 impl Default for Ppu {
@@ -660,21 +1058,36 @@ impl Default for Ppu {
 impl Address for Ppu {
   fn read(&self, addr: u16) -> u8 {
     match addr {
-      0x8000..=0x9fff => self.vram[(addr & 0x1fff) as usize],
-      0xfe00..=0xfe9f => self.oam[(addr & 0x009f) as usize],
+      0x8000..=0x9fff => {
+        if self.vram_blocked() {
+          return 0xff;
+        }
+        self.vram[(addr & 0x1fff) as usize]
+      },
+      0xfe00..=0xfe9f => {
+        if self.oam_blocked() {
+          return 0xff;
+        }
+        self.oam[(addr & 0x00ff) as usize]
+      },
       // Not Usable
       0xfea0..=0xfeff => 0xff,
       0xff40 => self.regs.lcdc,
-      0xff41 => self.regs.stat,
+      0xff41 => self.read_stat(),
       0xff42 => self.regs.scy,
       0xff43 => self.regs.scx,
-      0xff44 => self.regs.ly,
+      0xff44 => self.ly(),
       0xff45 => self.regs.lyc,
       0xff47 => self.regs.bgp,
       0xff48 => self.regs.obp0,
       0xff49 => self.regs.obp1,
       0xff4a => self.regs.wy,
       0xff4b => self.regs.wx,
+      // CGB color palettes, bit 6 of the index is unused
+      0xff68 if self.model.is_cgb() => self.bcps | 0x40,
+      0xff69 if self.model.is_cgb() => self.bg_palette_ram[(self.bcps & 0x3f) as usize],
+      0xff6a if self.model.is_cgb() => self.ocps | 0x40,
+      0xff6b if self.model.is_cgb() => self.obj_palette_ram[(self.ocps & 0x3f) as usize],
       _ => {
         warn!("Reading from unknown PPU location 0x{:04x}", addr);
         0xff
@@ -685,37 +1098,70 @@ impl Address for Ppu {
   fn write(&mut self, addr: u16, value: u8) {
     match addr {
       0x8000..=0x9fff => {
+        if self.vram_blocked() {
+          return;
+        }
         self.vram[(addr & 0x1fff) as usize] = value;
         if addr < 0x9800 {
           self.update_tile(addr, value);
         }
       },
       0xfe00..=0xfe9f => {
-        self.oam[(addr & 0x009f) as usize] = value;
-        self.update_object(addr, value);
+        if self.oam_blocked() {
+          return;
+        }
+        self.write_oam(addr, value);
       },
       // Not Usable
       0xfea0..=0xfeff => (),
       0xff40 => {
+        let enabled = self.lcd_enable();
         self.regs.lcdc = value;
-        if !self.lcd_enable() {
-          self.mode = PpuMode::HBlank;
-          self.dot = 0;
-          self.regs.ly = 0;
-          self.int_vblank = false;
-          self.int_stat = false;
-          self.window_counter = 0;
+        if enabled && !self.lcd_enable() {
+          self.disable_lcd();
+        } else if !enabled && self.lcd_enable() {
+          self.enable_lcd();
+        }
+      },
+      0xff41 => {
+        self.stat_write_bug();
+
+        // only the interrupt source bits (3-6) are writable, the
+        // mode and coincidence bits are driven by the PPU itself
+        self.regs.stat = value & 0x78;
+        if self.lcd_enable() {
+          self.update_stat();
         }
       },
-      0xff41 => self.regs.stat = value,
       0xff42 => self.regs.scy = value, // scrolling
       0xff43 => self.regs.scx = value, // scrolling
-      0xff45 => self.regs.lyc = value, // LCD status
+      0xff45 => {
+        self.regs.lyc = value; // LCD status
+        if self.lcd_enable() {
+          self.update_stat();
+        }
+      },
       0xff47 => self.regs.bgp = value,
       0xff48 => self.regs.obp0 = value,
       0xff49 => self.regs.obp1 = value,
       0xff4a => self.regs.wy = value, // scrolling
       0xff4b => self.regs.wx = value, // scrolling
+      // CGB color palettes, the index is incremented after
+      // each data write when bit 7 of it is set
+      0xff68 if self.model.is_cgb() => self.bcps = value & 0xbf,
+      0xff69 if self.model.is_cgb() => {
+        self.bg_palette_ram[(self.bcps & 0x3f) as usize] = value;
+        if self.bcps & 0x80 == 0x80 {
+          self.bcps = 0x80 | (self.bcps.wrapping_add(1) & 0x3f);
+        }
+      },
+      0xff6a if self.model.is_cgb() => self.ocps = value & 0xbf,
+      0xff6b if self.model.is_cgb() => {
+        self.obj_palette_ram[(self.ocps & 0x3f) as usize] = value;
+        if self.ocps & 0x80 == 0x80 {
+          self.ocps = 0x80 | (self.ocps.wrapping_add(1) & 0x3f);
+        }
+      },
       0xff7f => (),
       _ => warn!("Writing in unknown PPU location 0x{:04x}", addr),
     }
@@ -723,3 +1169,357 @@ impl Address for Ppu {
 }
 
 impl Device for Ppu {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Clocks the PPU one dot at a time until it reaches the dot
+  /// of the line, panicking if it takes longer than a frame.
+  fn run_to(ppu: &mut Ppu, ly: u8, dot: u16) {
+    for _ in 0..LINE_DOTS as u32 * 155 {
+      if ppu.regs.ly == ly && ppu.dot == dot {
+        return;
+      }
+      ppu.clock(1);
+    }
+    panic!("Line {} dot {} not reached", ly, dot);
+  }
+
+  /// Length in dots of the mode 3 of the line, with the LCD being
+  /// enabled with the LCDC value after the setup of the PPU.
+  fn mode3_dots(lcdc: u8, setup: impl Fn(&mut Ppu)) -> u16 {
+    let mut ppu = Ppu::new();
+    setup(&mut ppu);
+    ppu.write(0xff40, lcdc);
+    run_to(&mut ppu, 8, OAM_SCAN_DOTS);
+    assert!(ppu.mode == PpuMode::VramRead);
+    let mut dots = 0;
+    while ppu.mode == PpuMode::VramRead {
+      ppu.clock(1);
+      dots += 1;
+    }
+    dots
+  }
+
+  fn object(ppu: &mut Ppu, index: u16, x: u8) {
+    ppu.write(0xfe00 + index * 4, 24);
+    ppu.write(0xfe00 + index * 4 + 1, x);
+  }
+
+  #[test]
+  fn test_mode3_length() {
+    assert_eq!(mode3_dots(0x91, |_| ()), 172);
+
+    // the pixels discarded for the fine scroll
+    for scx in 0..16 {
+      assert_eq!(
+        mode3_dots(0x91, |ppu| ppu.write(0xff43, scx)),
+        172 + (scx & 0x07) as u16
+      );
+    }
+
+    // the window restarting the fetcher
+    assert_eq!(
+      mode3_dots(0xb1, |ppu| {
+        ppu.write(0xff4a, 8);
+        ppu.write(0xff4b, 7)
+      }),
+      178
+    );
+    assert_eq!(
+      mode3_dots(0xb1, |ppu| {
+        ppu.write(0xff4a, 8);
+        ppu.write(0xff4b, 100)
+      }),
+      178
+    );
+    assert_eq!(
+      mode3_dots(0xb1, |ppu| {
+        ppu.write(0xff4a, 9);
+        ppu.write(0xff4b, 7)
+      }),
+      172
+    );
+    assert_eq!(
+      mode3_dots(0x91, |ppu| {
+        ppu.write(0xff4a, 8);
+        ppu.write(0xff4b, 7)
+      }),
+      172
+    );
+
+    // the objects, each taking 6 dots plus the wait for the
+    // background fetch of the tile they start in
+    for (x, dots) in [
+      (0, 183),
+      (8, 183),
+      (9, 182),
+      (12, 179),
+      (13, 178),
+      (16, 183),
+      (100, 179),
+      (167, 178),
+      (168, 172),
+    ] {
+      assert_eq!(mode3_dots(0x93, |ppu| object(ppu, 0, x)), dots, "{}", x);
+    }
+    assert_eq!(mode3_dots(0x91, |ppu| object(ppu, 0, 8)), 172);
+    assert_eq!(
+      mode3_dots(0x93, |ppu| (0..10).for_each(|index| object(ppu, index, 8))),
+      172 + 11 + 9 * 6
+    );
+    // only the first 10 objects of the line are drawn
+    assert_eq!(
+      mode3_dots(0x93, |ppu| (0..12).for_each(|index| object(ppu, index, 8))),
+      172 + 11 + 9 * 6
+    );
+  }
+
+  #[test]
+  fn test_object_hidden() {
+    // the object in slot 3 never had its attributes written
+    fn shades(hidden: usize) -> Vec<u8> {
+      let mut ppu = Ppu::new();
+      ppu.write(0x8000, 0xff);
+      ppu.write(0x8001, 0xff);
+      ppu.write(0xff47, 0x00);
+      ppu.write(0xff48, 0xff);
+      object(&mut ppu, 3, 8);
+      ppu.set_object_hidden(hidden, true);
+      ppu.write(0xff40, 0x93);
+      run_to(&mut ppu, 0, 0);
+      run_to(&mut ppu, 9, 0);
+      ppu.shade_buffer()[8 * DISPLAY_WIDTH..8 * DISPLAY_WIDTH + 10].to_vec()
+    }
+
+    assert_eq!(shades(0), [3, 3, 3, 3, 3, 3, 3, 3, 0, 0]);
+    assert_eq!(shades(3), [0; 10]);
+  }
+
+  #[test]
+  fn test_lcd_off() {
+    let mut ppu = Ppu::new();
+    ppu.write(0xff47, 0xff);
+    ppu.write(0xff41, 0x08);
+    ppu.write(0xff40, 0x91);
+    run_to(&mut ppu, 50, 100);
+    ppu.set_int_vblank(true);
+    ppu.set_int_stat(true);
+
+    // LY and the mode are reset with a frame signaled right
+    // away, for the screen to be cleared to the off color
+    let frame = ppu.frame_index();
+    ppu.write(0xff40, 0x11);
+    assert_eq!(ppu.read(0xff44), 0);
+    assert_eq!(ppu.read(0xff41) & 0x03, 0x00);
+    assert!(!ppu.int_vblank() && !ppu.int_stat());
+    assert_eq!(ppu.frame_index(), frame.wrapping_add(1));
+    assert!(ppu.shade_buffer().iter().all(|&shade| shade == 0));
+
+    // blank frames are then signaled at the usual rate
+    for _ in 0..153 {
+      ppu.clock(LINE_DOTS);
+    }
+    ppu.clock(LINE_DOTS - 1);
+    assert_eq!(ppu.frame_index(), frame.wrapping_add(1));
+    ppu.clock(1);
+    assert_eq!(ppu.frame_index(), frame.wrapping_add(2));
+    for _ in 0..154 {
+      ppu.clock(LINE_DOTS);
+    }
+    assert_eq!(ppu.frame_index(), frame.wrapping_add(3));
+    assert_eq!(ppu.read(0xff44), 0);
+    assert!(!ppu.int_vblank() && !ppu.int_stat());
+  }
+
+  #[test]
+  fn test_lcd_on() {
+    let mut ppu = Ppu::new();
+    ppu.write(0xff47, 0xff);
+    ppu.write(0xff40, 0x91);
+
+    // line 0 starts at dot 4, being 4 dots shorter
+    assert_eq!((ppu.read(0xff44), ppu.dot), (0, FIRST_LINE_SKIP));
+    for _ in FIRST_LINE_SKIP..LINE_DOTS {
+      assert_eq!(ppu.read(0xff44), 0);
+      ppu.clock(1);
+    }
+    assert_eq!((ppu.read(0xff44), ppu.dot), (1, 0));
+
+    // the first frame is not displayed, only the next one is
+    let frame = ppu.frame_index();
+    run_to(&mut ppu, 0, 0);
+    assert_eq!(ppu.frame_index(), frame.wrapping_add(1));
+    assert!(ppu.shade_buffer().iter().all(|&shade| shade == 0));
+    run_to(&mut ppu, 144, 0);
+    assert_eq!(ppu.frame_index(), frame.wrapping_add(1));
+    assert!(ppu.shade_buffer().iter().all(|&shade| shade == 3));
+    run_to(&mut ppu, 0, 0);
+    assert_eq!(ppu.frame_index(), frame.wrapping_add(2));
+  }
+
+  #[test]
+  fn test_stat_lyc() {
+    let mut ppu = Ppu::new();
+    ppu.write(0xff45, 2);
+    ppu.write(0xff41, 0x40);
+    ppu.write(0xff40, 0x91);
+    assert!(!ppu.int_stat());
+
+    // the comparison lags 4 dots behind the change of LY
+    run_to(&mut ppu, 2, 3);
+    assert_eq!(ppu.read(0xff44), 2);
+    assert_eq!(ppu.read(0xff41) & 0x04, 0x00);
+    assert!(!ppu.int_stat());
+    ppu.clock(1);
+    assert_eq!(ppu.read(0xff41) & 0x04, 0x04);
+    assert!(ppu.int_stat());
+    ppu.ack_stat();
+
+    // the interrupt is only requested on the rising edge
+    run_to(&mut ppu, 2, LINE_DOTS - 1);
+    assert_eq!(ppu.read(0xff41) & 0x04, 0x04);
+    assert!(!ppu.int_stat());
+    run_to(&mut ppu, 3, 4);
+    assert_eq!(ppu.read(0xff41) & 0x04, 0x00);
+
+    // on line 153 LY reads as 0 from dot 4, with LYC=153
+    // matching for 4 dots and LYC=0 from dot 8 on
+    ppu.write(0xff45, 153);
+    run_to(&mut ppu, 153, 4);
+    assert_eq!(ppu.read(0xff44), 0);
+    assert!(ppu.int_stat());
+    ppu.ack_stat();
+    ppu.write(0xff45, 0);
+    assert!(!ppu.int_stat());
+    run_to(&mut ppu, 153, 8);
+    assert!(ppu.int_stat());
+    ppu.ack_stat();
+    run_to(&mut ppu, 0, 8);
+    assert_eq!(ppu.read(0xff41) & 0x04, 0x04);
+    assert!(!ppu.int_stat());
+  }
+
+  #[test]
+  fn test_stat_blocking() {
+    let mut ppu = Ppu::new();
+    ppu.write(0xff45, 2);
+    ppu.write(0xff41, 0x48);
+    ppu.write(0xff40, 0x91);
+    run_to(&mut ppu, 2, 4);
+    assert!(ppu.int_stat());
+    ppu.ack_stat();
+
+    // the HBlank of the matching line is blocked by the LYC
+    // source, keeping the line high, but not the next ones
+    run_to(&mut ppu, 2, LINE_DOTS - 1);
+    assert_eq!(ppu.read(0xff41) & 0x03, 0x00);
+    assert!(!ppu.int_stat());
+    run_to(&mut ppu, 3, LINE_DOTS - 1);
+    assert!(ppu.int_stat());
+    ppu.ack_stat();
+
+    // the OAM source blocks the HBlank one across lines, as the
+    // mode 0 to mode 2 transition keeps the line high
+    ppu.write(0xff41, 0x28);
+    assert!(!ppu.int_stat());
+    run_to(&mut ppu, 4, 1);
+    assert_eq!(ppu.read(0xff41) & 0x03, 0x02);
+    assert!(!ppu.int_stat());
+    run_to(&mut ppu, 4, LINE_DOTS - 1);
+    assert!(ppu.int_stat());
+  }
+
+  #[test]
+  fn test_access_restrictions() {
+    let mut ppu = Ppu::new();
+    ppu.write(0x8000, 0x11);
+    ppu.write(0xfe00, 0x22);
+    ppu.write(0xfe20, 0x23);
+    ppu.write(0xfe9f, 0x24);
+    assert_eq!((ppu.read(0xfe20), ppu.read(0xfe9f)), (0x23, 0x24));
+    ppu.write(0xff40, 0x91);
+
+    // the OAM is accessible during the first line after the
+    // LCD is turned on, as there's no OAM scan on it
+    assert!(ppu.mode() == PpuMode::OamRead);
+    assert_eq!((ppu.read(0x8000), ppu.read(0xfe00)), (0x11, 0x22));
+
+    run_to(&mut ppu, 1, 10);
+    assert!(ppu.mode() == PpuMode::OamRead);
+    assert_eq!((ppu.read(0x8000), ppu.read(0xfe00)), (0x11, 0xff));
+    ppu.write(0xfe00, 0x33);
+
+    run_to(&mut ppu, 1, OAM_SCAN_DOTS + 10);
+    assert!(ppu.mode() == PpuMode::VramRead);
+    assert_eq!((ppu.read(0x8000), ppu.read(0xfe00)), (0xff, 0xff));
+    ppu.write(0x8000, 0x44);
+    ppu.write(0xfe00, 0x44);
+
+    // with the restrictions disabled both are always accessible
+    ppu.set_access_restrictions(false);
+    assert_eq!((ppu.read(0x8000), ppu.read(0xfe00)), (0x11, 0x22));
+    ppu.write(0x8000, 0x55);
+    assert_eq!(ppu.read(0x8000), 0x55);
+    ppu.set_access_restrictions(true);
+
+    run_to(&mut ppu, 1, LINE_DOTS - 1);
+    assert!(ppu.mode() == PpuMode::HBlank);
+    assert_eq!((ppu.read(0x8000), ppu.read(0xfe00)), (0x55, 0x22));
+
+    run_to(&mut ppu, 144, 10);
+    assert!(ppu.mode() == PpuMode::VBlank);
+    assert_eq!((ppu.read(0x8000), ppu.read(0xfe00)), (0x55, 0x22));
+
+    // the OAM DMA is not affected by the PPU mode
+    run_to(&mut ppu, 2, 10);
+    ppu.write_oam(0xfe00, 0x66);
+    assert_eq!(ppu.oam[0], 0x66);
+  }
+
+  #[test]
+  fn test_oam_bug() {
+    fn corrupted(model: Model, ly: u8, dot: u16, addr: u16, restrictions: bool) -> bool {
+      let mut ppu = Ppu::new();
+      ppu.set_model(model);
+      for index in 0..0xa0 {
+        ppu.write_oam(0xfe00 + index, index as u8);
+      }
+      ppu.write(0xff40, 0x91);
+      ppu.set_access_restrictions(restrictions);
+      run_to(&mut ppu, ly, dot);
+      let oam = ppu.oam;
+      ppu.oam_bug(addr);
+      ppu.oam != oam
+    }
+
+    // the row read at dot 8 (the third one) is mixed with the
+    // previous one, the first word as ((a ^ c) & (b ^ c)) ^ c
+    let mut ppu = Ppu::new();
+    for index in 0..0xa0 {
+      ppu.write_oam(0xfe00 + index, index as u8);
+    }
+    for (addr, value) in [(0xfe08, 0x00ff), (0xfe0c, 0x0f0f), (0xfe10, 0x3333)] {
+      ppu.write_oam(addr, value as u8);
+      ppu.write_oam(addr + 1, (value >> 8) as u8);
+    }
+    ppu.write(0xff40, 0x91);
+    run_to(&mut ppu, 1, 8);
+    ppu.oam_bug(0xfe00);
+    assert_eq!(ppu.oam[16..24], [0x3f, 0x03, 10, 11, 0x0f, 0x0f, 14, 15]);
+    assert_eq!(ppu.oam[8..16], [0xff, 0x00, 10, 11, 0x0f, 0x0f, 14, 15]);
+    assert_eq!(ppu.oam[24], 24);
+
+    assert!(corrupted(Model::Dmg, 1, 8, 0xfeff, true));
+    assert!(corrupted(Model::Dmg, 1, OAM_SCAN_DOTS - 1, 0xfe00, true));
+    // the first row is never corrupted
+    assert!(!corrupted(Model::Dmg, 1, 3, 0xfe00, true));
+    assert!(!corrupted(Model::Dmg, 1, 8, 0xfdff, true));
+    assert!(!corrupted(Model::Dmg, 1, 8, 0xff00, true));
+    assert!(!corrupted(Model::Dmg, 1, OAM_SCAN_DOTS + 8, 0xfe00, true));
+    assert!(!corrupted(Model::Dmg, 144, 8, 0xfe00, true));
+    assert!(!corrupted(Model::Dmg, 1, 8, 0xfe00, false));
+    assert!(!corrupted(Model::Cgb, 1, 8, 0xfe00, true));
+  }
+}
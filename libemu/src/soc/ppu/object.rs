@@ -11,25 +11,61 @@ pub struct ObjectData {
   pub(crate) xflip: bool,
   pub(crate) yflip: bool,
   pub(crate) bg_over: bool,
-  pub(crate) index: u8,
 }
 
 impl ObjectData {
+  /// Creates the object for a zeroed OAM entry, which is
+  /// placed (hidden) above and to the left of the screen.
   pub fn new() -> Self {
     Self {
-      x: 0,
-      y: 0,
+      x: -8,
+      y: -16,
       tile: 0,
       tile_bank: 0,
       palette: 0,
       xflip: false,
       yflip: false,
       bg_over: false,
-      index: 0,
     }
   }
 }
 
+impl ObjectData {
+  /// Horizontal position on screen, the raw OAM value minus 8.
+  pub fn x(&self) -> i16 {
+    self.x
+  }
+
+  /// Vertical position on screen, the raw OAM value minus 16.
+  pub fn y(&self) -> i16 {
+    self.y
+  }
+
+  pub fn tile(&self) -> u8 {
+    self.tile
+  }
+
+  pub fn tile_bank(&self) -> u8 {
+    self.tile_bank
+  }
+
+  pub fn palette(&self) -> u8 {
+    self.palette
+  }
+
+  pub fn xflip(&self) -> bool {
+    self.xflip
+  }
+
+  pub fn yflip(&self) -> bool {
+    self.yflip
+  }
+
+  pub fn bg_over(&self) -> bool {
+    self.bg_over
+  }
+}
+
 impl Default for ObjectData {
   fn default() -> Self {
     Self::new()
@@ -38,10 +74,6 @@ impl Default for ObjectData {
 
 impl Display for ObjectData {
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-    write!(
-      f,
-      "Index: {}, X: {}, Y: {}, Tile: {}",
-      self.index, self.x, self.y, self.tile
-    )
+    write!(f, "X: {}, Y: {}, Tile: {}", self.x, self.y, self.tile)
   }
 }
@@ -0,0 +1,167 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{error::Error, memory_map::MemoryRegion, util::read_file};
+
+/// Table of the symbols of a ROM, as emitted by RGBDS in the
+/// `.sym` files, with one `bank:addr label` entry per line.
+///
+/// Symbols are keyed by bank and address so that labels of
+/// different ROM banks mapped at the same address don't clash.
+#[derive(Clone, Default)]
+pub struct SymbolTable {
+  labels: BTreeMap<(u16, u16), String>,
+  addrs: HashMap<String, (u16, u16)>,
+}
+
+impl SymbolTable {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn from_data(data: &str) -> Result<Self, Error> {
+    let mut table = Self::new();
+    for (index, line) in data.lines().enumerate() {
+      let line = line.split(';').next().unwrap_or("").trim();
+      if line.is_empty() {
+        continue;
+      }
+      let entry = line
+        .split_once(char::is_whitespace)
+        .and_then(|(location, name)| {
+          let (bank, addr) = location.split_once(':')?;
+          Some((
+            u16::from_str_radix(bank, 16).ok()?,
+            u16::from_str_radix(addr, 16).ok()?,
+            name.trim(),
+          ))
+        });
+      match entry {
+        Some((bank, addr, name)) => table.insert(bank, addr, name),
+        None => {
+          return Err(Error::CustomError(format!(
+            "Invalid symbol entry at line {}",
+            index + 1
+          )))
+        },
+      }
+    }
+    Ok(table)
+  }
+
+  pub fn from_file(path: &str) -> Result<Self, Error> {
+    let data = read_file(path)?;
+    let data = String::from_utf8(data)
+      .map_err(|_| Error::CustomError(format!("Invalid symbol file: {}", path)))?;
+    Self::from_data(&data)
+  }
+
+  /// Adds a symbol to the table, when more than one symbol is defined
+  /// at the same location the first one is used as its label.
+  pub fn insert(&mut self, bank: u16, addr: u16, name: &str) {
+    self
+      .labels
+      .entry((bank, addr))
+      .or_insert_with(|| String::from(name));
+    self.addrs.insert(String::from(name), (bank, addr));
+  }
+
+  pub fn len(&self) -> usize {
+    self.addrs.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.addrs.is_empty()
+  }
+
+  /// Resolves the bank a symbol for the address would be
+  /// defined in, given the ROM bank currently mapped.
+  ///
+  /// Only the switchable ROM bank is tracked, as the DMG has
+  /// a single WRAMX bank (defined by RGBDS as bank 1) and SRAM
+  /// symbols are assumed to be in its first bank.
+  pub fn bank_for(addr: u16, rom_bank: u16) -> u16 {
+    match MemoryRegion::from_addr(addr) {
+      MemoryRegion::RomX => rom_bank,
+      MemoryRegion::WramX => 1,
+      _ => 0,
+    }
+  }
+
+  /// Name of the symbol defined exactly at the address.
+  pub fn name(&self, bank: u16, addr: u16) -> Option<&str> {
+    self.labels.get(&(bank, addr)).map(|name| name.as_str())
+  }
+
+  /// Label for the address relative to the closest symbol before it
+  /// in the same bank and region (eg: `Main+$3`).
+  pub fn label(&self, bank: u16, addr: u16) -> Option<String> {
+    let (&(symbol_bank, symbol_addr), name) = self.labels.range(..=(bank, addr)).next_back()?;
+    if symbol_bank != bank || MemoryRegion::from_addr(symbol_addr) != MemoryRegion::from_addr(addr)
+    {
+      return None;
+    }
+    match addr - symbol_addr {
+      0 => Some(name.clone()),
+      offset => Some(format!("{}+${:X}", name, offset)),
+    }
+  }
+
+  /// Location (bank and address) of the symbol with the name.
+  pub fn resolve(&self, name: &str) -> Option<(u16, u16)> {
+    self.addrs.get(name).copied()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SYMBOLS: &str = "; File generated by rgblink
+00:0150 Main
+00:0150 Start
+00:0160 Main.loop
+01:4000 BankedFn
+02:4000 OtherBankFn
+00:c000 wBuffer ; WRAM
+01:d000 wBank1
+";
+
+  #[test]
+  fn test_from_data() {
+    let table = SymbolTable::from_data(SYMBOLS).unwrap();
+    assert_eq!(table.len(), 7);
+    assert_eq!(table.name(0, 0x0150), Some("Main"));
+    assert_eq!(table.name(0, 0x0151), None);
+    assert_eq!(table.resolve("Start"), Some((0, 0x0150)));
+    assert_eq!(table.resolve("wBuffer"), Some((0, 0xc000)));
+    assert_eq!(table.resolve("Missing"), None);
+
+    assert!(SymbolTable::from_data("").unwrap().is_empty());
+    assert!(SymbolTable::from_data("00:0150").is_err());
+    assert!(SymbolTable::from_data("0150 Main").is_err());
+    assert!(SymbolTable::from_data("00:xyz Main").is_err());
+  }
+
+  #[test]
+  fn test_label() {
+    let table = SymbolTable::from_data(SYMBOLS).unwrap();
+    assert_eq!(table.label(0, 0x0150).as_deref(), Some("Main"));
+    assert_eq!(table.label(0, 0x015f).as_deref(), Some("Main+$F"));
+    assert_eq!(table.label(0, 0x0165).as_deref(), Some("Main.loop+$5"));
+    assert_eq!(table.label(0, 0x0100), None);
+    assert_eq!(table.label(1, 0x4010).as_deref(), Some("BankedFn+$10"));
+    assert_eq!(table.label(2, 0x4000).as_deref(), Some("OtherBankFn"));
+    assert_eq!(table.label(3, 0x4000), None);
+    // symbols don't extend into other regions
+    assert_eq!(table.label(0, 0x4000), None);
+    assert_eq!(table.label(0, 0xc010).as_deref(), Some("wBuffer+$10"));
+  }
+
+  #[test]
+  fn test_bank_for() {
+    assert_eq!(SymbolTable::bank_for(0x0150, 5), 0);
+    assert_eq!(SymbolTable::bank_for(0x4000, 5), 5);
+    assert_eq!(SymbolTable::bank_for(0xc000, 5), 0);
+    assert_eq!(SymbolTable::bank_for(0xd000, 5), 1);
+  }
+}
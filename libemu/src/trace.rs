@@ -0,0 +1,68 @@
+use std::cell::{Cell, RefCell};
+
+use crate::memory_map::MemoryRegion;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+  Read,
+  Write,
+}
+
+/// Single access performed through the bus, together with the
+/// context (PC of the instruction, ROM bank mapped and CPU cycle)
+/// it was made in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BusAccess {
+  pub kind: AccessKind,
+  pub addr: u16,
+  pub value: u8,
+  pub region: MemoryRegion,
+  pub pc: u16,
+  pub bank: u16,
+  pub cycle: u64,
+}
+
+/// Callback invoked for every bus access, it's called while the
+/// bus is borrowed so it must not access the system itself.
+pub type TraceHook = Box<dyn FnMut(&BusAccess)>;
+
+/// Opt-in tracing of the bus accesses, keeping the context
+/// of the instruction currently being executed.
+pub struct BusTrace {
+  hook: RefCell<TraceHook>,
+  pc: Cell<u16>,
+  bank: Cell<u16>,
+  cycle: Cell<u64>,
+}
+
+impl BusTrace {
+  pub fn new(hook: TraceHook) -> Self {
+    Self {
+      hook: RefCell::new(hook),
+      pc: Cell::new(0),
+      bank: Cell::new(0),
+      cycle: Cell::new(0),
+    }
+  }
+
+  /// Updates the context of the accesses, should be called
+  /// before the execution of each instruction.
+  pub fn set_context(&self, pc: u16, bank: u16, cycle: u64) {
+    self.pc.set(pc);
+    self.bank.set(bank);
+    self.cycle.set(cycle);
+  }
+
+  pub fn record(&self, kind: AccessKind, addr: u16, value: u8, region: MemoryRegion) {
+    let access = BusAccess {
+      kind,
+      addr,
+      value,
+      region,
+      pc: self.pc.get(),
+      bank: self.bank.get(),
+      cycle: self.cycle.get(),
+    };
+    (self.hook.borrow_mut())(&access);
+  }
+}
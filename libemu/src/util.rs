@@ -40,3 +40,20 @@ pub fn replace_ext(path: &str, new_extension: &str) -> Option<String> {
   let new_file_path = parent_dir.join(new_file_name);
   Some(String::from(new_file_path.to_str()?))
 }
+
+/// CRC32 (IEEE 802.3) of the data, as used by the
+/// BPS and UPS patches and by the ZIP archives.
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xffffffffu32;
+  for &byte in data {
+    crc ^= byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 {
+        (crc >> 1) ^ 0xedb88320
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
@@ -0,0 +1,190 @@
+//! Instruction level tests for the SM83 CPU, runs the [`Cpu`] against a
+//! flat RAM bus and compares registers, flags, memory side effects and
+//! cycle counts with the expected state.
+//!
+//! The cases use the JSON format of the SingleStepTests sm83 suite, a
+//! small set of hand written cases lives under `tests/data` while the
+//! complete suite (one file per opcode, eg: `00.json`, `cb 00.json`) is
+//! run when `SM83_TESTS_DIR` points to a local checkout of its `v1`
+//! directory, as an ignored test:
+//!
+//! ```text
+//! SM83_TESTS_DIR=path/to/sm83/v1 cargo test -p libemu --test sm83 -- --ignored
+//! ```
+
+use std::{env, fs, path::Path};
+
+use libemu::{
+  bus::Bus,
+  generic::{address::Address, shared::Shared},
+  soc::cpu::Cpu,
+};
+use serde::Deserialize;
+
+/// Opcodes that are not defined for the SM83, the prefix byte
+/// is included as it's covered by the extended table.
+const ILLEGAL: [u8; 12] = [
+  0xcb, 0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd,
+];
+
+#[derive(Deserialize)]
+struct Case {
+  name: String,
+  initial: State,
+  #[serde(rename = "final")]
+  expected: State,
+  cycles: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct State {
+  pc: u16,
+  sp: u16,
+  a: u8,
+  b: u8,
+  c: u8,
+  d: u8,
+  e: u8,
+  f: u8,
+  h: u8,
+  l: u8,
+  ime: Option<u8>,
+  ie: Option<u8>,
+  ram: Vec<(u16, u8)>,
+}
+
+fn setup(state: &State) -> Cpu {
+  let bus = Shared::new(Bus::flat());
+  let mut cpu = Cpu::new(bus);
+
+  cpu.regs.pc = state.pc;
+  cpu.regs.sp = state.sp;
+  cpu.regs.a = state.a;
+  cpu.regs.b = state.b;
+  cpu.regs.c = state.c;
+  cpu.regs.d = state.d;
+  cpu.regs.e = state.e;
+  cpu.regs.h = state.h;
+  cpu.regs.l = state.l;
+  cpu.set_f(state.f);
+  cpu.set_ime(state.ime.unwrap_or(0) == 1);
+
+  if let Some(ie) = state.ie {
+    cpu.bus_mut().write(0xffff, ie);
+  }
+  for (addr, value) in &state.ram {
+    cpu.bus_mut().write(*addr, *value);
+  }
+
+  cpu
+}
+
+/// Runs a single case returning the list of mismatches found
+/// between the CPU state and the expected one.
+fn run_case(case: &Case) -> Vec<String> {
+  let mut cpu = setup(&case.initial);
+  let cycles = cpu.clock();
+
+  let expected = &case.expected;
+  let mut errors = vec![];
+  let mut check = |name: &str, found: u16, expected: u16| {
+    if found != expected {
+      errors.push(format!(
+        "{}: found {:#06x}, expected {:#06x}",
+        name, found, expected
+      ));
+    }
+  };
+
+  check("pc", cpu.regs.pc, expected.pc);
+  check("sp", cpu.regs.sp, expected.sp);
+  check("a", cpu.regs.a as u16, expected.a as u16);
+  check("b", cpu.regs.b as u16, expected.b as u16);
+  check("c", cpu.regs.c as u16, expected.c as u16);
+  check("d", cpu.regs.d as u16, expected.d as u16);
+  check("e", cpu.regs.e as u16, expected.e as u16);
+  check("f", cpu.f() as u16, expected.f as u16);
+  check("h", cpu.regs.h as u16, expected.h as u16);
+  check("l", cpu.regs.l as u16, expected.l as u16);
+  if let Some(ime) = expected.ime {
+    check("ime", cpu.ime() as u16, ime as u16);
+  }
+  if let Some(ie) = expected.ie {
+    check("ie", cpu.bus().read(0xffff) as u16, ie as u16);
+  }
+  check("cycles", cycles as u16, case.cycles.len() as u16 * 4);
+  for (addr, value) in &expected.ram {
+    check(
+      &format!("[{:#06x}]", addr),
+      cpu.bus().read(*addr) as u16,
+      *value as u16,
+    );
+  }
+
+  errors
+}
+
+/// Runs every case of the file at the given path, returning a
+/// description of the failed ones.
+fn run_file(path: &Path) -> Vec<String> {
+  let data = fs::read_to_string(path)
+    .unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+  let cases: Vec<Case> = serde_json::from_str(&data)
+    .unwrap_or_else(|err| panic!("Failed to parse {}: {}", path.display(), err));
+
+  cases
+    .iter()
+    .filter_map(|case| {
+      let errors = run_case(case);
+      if errors.is_empty() {
+        None
+      } else {
+        Some(format!("{}: {}", case.name, errors.join(", ")))
+      }
+    })
+    .collect()
+}
+
+#[test]
+fn test_fixtures() {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/sm83.json");
+  let failures = run_file(&path);
+  assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+#[ignore = "requires SM83_TESTS_DIR to point to the SingleStepTests sm83 suite"]
+fn test_single_step_tests() {
+  let dir = env::var("SM83_TESTS_DIR")
+    .expect("SM83_TESTS_DIR must point to the v1 directory of the SingleStepTests sm83 suite");
+
+  let names = (0x00..=0xffu8)
+    .filter(|opcode| !ILLEGAL.contains(opcode))
+    .map(|opcode| format!("{:02x}.json", opcode))
+    .chain((0x00..=0xffu8).map(|opcode| format!("cb {:02x}.json", opcode)));
+
+  let mut failures = vec![];
+  for name in names {
+    let path = Path::new(&dir).join(&name);
+    if !path.exists() {
+      failures.push(format!("{}: missing test file", name));
+      continue;
+    }
+    let errors = run_file(&path);
+    if let Some(first) = errors.first() {
+      failures.push(format!(
+        "{}: {} failed, first {}",
+        name,
+        errors.len(),
+        first
+      ));
+    }
+  }
+
+  assert!(
+    failures.is_empty(),
+    "{} opcodes failed\n{}",
+    failures.len(),
+    failures.join("\n")
+  );
+}